@@ -7,12 +7,9 @@ pub mod memory_layout {
     /// Kernel stack size (16KB)
     pub const KERNEL_STACK_SIZE: usize = 4096 * 4;
 
-    /// Kernel heap size (8MB)
+    /// Initial kernel heap size (8MB, grows on demand)
     pub const KERNEL_HEAP_SIZE: usize = 0x80_0000;
 
-    /// Physical memory end (128MB for QEMU virt)
-    pub const MEMORY_END: usize = 0x8800_0000;
-
     /// Page size (4KB)
     pub const PAGE_SIZE: usize = 0x1000;
 
@@ -24,26 +21,41 @@ pub mod memory_layout {
 
     /// Kernel code start (defined by linker)
     pub const KERNEL_START: usize = 0x8022_0000;
-
-    /// Kernel code end (assume kernel + data < 2MB, heap starts at 2MB offset)
-    pub const KERNEL_END: usize = 0x8042_0000;
-
-    /// Kernel heap start (2MB from kernel base)
-    pub const KERNEL_HEAP_START: usize = 0x8042_0000;
-
-    /// Kernel heap end
-    pub const KERNEL_HEAP_END: usize = KERNEL_HEAP_START + KERNEL_HEAP_SIZE;
 }
 
-/// Max number of apps
-pub const MAX_APP_NUM: usize = 16;
+/// Max number of tasks, including idle and initproc (also sizes the kernel
+/// stack area and the per-pid scheduler arrays)
+pub const MAX_APP_NUM: usize = 32;
 
 /// Max syscall number
 pub const MAX_SYSCALL_NUM: usize = 500;
 
+/// Max length of a path passed to a system call, including the NUL
+pub const PATH_MAX: usize = 256;
+
+/// Use four-level Sv48 paging instead of Sv39 (overridden by the boot
+/// arguments `sv39` / `sv48`; falls back to Sv39 if the hart lacks Sv48)
+pub const DEFAULT_SV48: bool = false;
+
+/// Randomize the user stack, mmap base and PIE load address (overridden by
+/// the boot arguments `aslr` / `noaslr`)
+pub const DEFAULT_ASLR: bool = false;
+
+/// Refuse user mappings that are both writable and executable, and never let
+/// user stacks and heaps become executable (W^X; overridden by the boot
+/// arguments `wxorx` / `nowxorx`)
+pub const DEFAULT_W_XOR_X: bool = false;
+
+/// Scheduling policy: "rr", "stride", "mlfq" or "cfs" (overridden by the boot
+/// argument `sched=<policy>`)
+pub const DEFAULT_SCHED_POLICY: &str = "stride";
+
 /// Clock frequency (10MHz for QEMU)
 pub const CLOCK_FREQ: usize = 10_000_000;
 
+/// Timer interrupts per second (10ms ticks)
+pub const TICKS_PER_SEC: usize = 100;
+
 /// Trampoline virtual address (highest page)
 pub const TRAMPOLINE: usize = usize::MAX - memory_layout::PAGE_SIZE + 1;
 
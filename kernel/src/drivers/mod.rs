@@ -1,5 +1,41 @@
 //! Device drivers module
+//!
+//! Devices are discovered from the device tree (see crate::fdt).
 
+pub mod virtio_blk;
+pub mod virtio_mmio;
+pub mod virtio_rng;
+
+use crate::fdt::{self, DeviceKind};
+use alloc::vec::Vec;
+
+/// Initialize device drivers for the devices found in the device tree
+///
+/// The console still goes through SBI and no device uses interrupts yet;
+/// the first virtio-blk device is set up for polled I/O (used for swap), and
+/// the first virtio-rng device as entropy source (used to seed ASLR).
 pub fn init() {
-    // TODO: Initialize device drivers
+    let info = fdt::machine_info();
+    for device in info.devices() {
+        match device.irq {
+            Some(irq) => println!(
+                "[Drivers] {:?} at {:#x} (size {:#x}, irq {})",
+                device.kind, device.base, device.size, irq
+            ),
+            None => println!(
+                "[Drivers] {:?} at {:#x} (size {:#x})",
+                device.kind, device.base, device.size
+            ),
+        }
+    }
+    let virtio_bases: Vec<usize> = info
+        .devices()
+        .iter()
+        .filter(|device| device.kind == DeviceKind::VirtioMmio)
+        .map(|device| device.base)
+        .collect();
+    drop(info);
+
+    virtio_blk::init(virtio_bases.iter().copied());
+    virtio_rng::init(virtio_bases.into_iter());
 }
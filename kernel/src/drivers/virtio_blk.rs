@@ -0,0 +1,175 @@
+//! VirtIO Block Device Driver
+//!
+//! Minimal polling driver for a virtio-blk device behind a virtio-mmio
+//! transport (see super::virtio_mmio), as provided by QEMU's `virt` machine
+//! with `-device virtio-blk-device`.
+//!
+//! - A single request is in flight at a time, so no interrupts are needed
+//! - Request header, status byte and a one-page bounce buffer live in the
+//!   transport's DMA frames, so callers may pass buffers from anywhere
+//!   (kernel stacks are not identity mapped)
+
+use super::virtio_mmio::{
+    Descriptor, VirtIOMmio, DESC_F_NEXT, DESC_F_WRITE, DRIVER_OFFSET, QUEUE_FRAMES,
+};
+use crate::config::memory_layout::PAGE_SIZE;
+use core::ptr::{read_volatile, write_volatile};
+use spin::Mutex;
+
+/// Sector size of virtio-blk requests
+pub const SECTOR_SIZE: usize = 512;
+
+const VIRTIO_DEVICE_BLOCK: u32 = 2;
+
+/// Offset of the capacity (u64, in sectors) in the device configuration space
+const CONFIG_CAPACITY: usize = 0;
+
+// Request types
+const BLK_T_IN: u32 = 0;
+const BLK_T_OUT: u32 = 1;
+const BLK_S_OK: u8 = 0;
+
+// Layout of the driver data in the DMA frames: header and status next to the
+// queue, the bounce buffer in a frame of its own
+const HEADER_OFFSET: usize = DRIVER_OFFSET;
+const STATUS_OFFSET: usize = DRIVER_OFFSET + 0x10;
+const BOUNCE_OFFSET: usize = QUEUE_FRAMES * PAGE_SIZE;
+const DMA_FRAMES: usize = QUEUE_FRAMES + 1;
+
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// A virtio-blk device
+pub struct VirtIOBlock {
+    transport: VirtIOMmio,
+    /// Capacity in sectors
+    capacity: u64,
+}
+
+impl VirtIOBlock {
+    /// Set up the virtio-mmio device at `base`
+    ///
+    /// # Returns
+    /// * `None` if there is no block device at `base`, the device rejects the
+    ///   driver, or no memory is left for the queue
+    pub fn probe(base: usize) -> Option<Self> {
+        let transport = VirtIOMmio::probe(base, VIRTIO_DEVICE_BLOCK, DMA_FRAMES)?;
+        let low = transport.read_config(CONFIG_CAPACITY) as u64;
+        let high = transport.read_config(CONFIG_CAPACITY + 4) as u64;
+        Some(Self {
+            transport,
+            capacity: (high << 32) | low,
+        })
+    }
+
+    /// Capacity in sectors
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Run one request on up to a page of data through the bounce buffer
+    fn request(&mut self, kind: u32, sector: u64, len: usize) -> Result<(), &'static str> {
+        if len == 0 || len > PAGE_SIZE || len % SECTOR_SIZE != 0 {
+            return Err("Bad request length");
+        }
+        if sector + (len / SECTOR_SIZE) as u64 > self.capacity {
+            return Err("Request beyond end of disk");
+        }
+        unsafe {
+            *self.transport.dma_ptr::<RequestHeader>(HEADER_OFFSET) = RequestHeader {
+                kind,
+                reserved: 0,
+                sector,
+            };
+            write_volatile(self.transport.dma_ptr::<u8>(STATUS_OFFSET), 0xff);
+        }
+
+        // Header -> data -> status, always in descriptors 0..3
+        let header = self.transport.dma_addr(HEADER_OFFSET);
+        self.transport.set_descriptor(
+            0,
+            Descriptor {
+                addr: header,
+                len: core::mem::size_of::<RequestHeader>() as u32,
+                flags: DESC_F_NEXT,
+                next: 1,
+            },
+        );
+        let bounce = self.transport.dma_addr(BOUNCE_OFFSET);
+        self.transport.set_descriptor(
+            1,
+            Descriptor {
+                addr: bounce,
+                len: len as u32,
+                flags: if kind == BLK_T_IN {
+                    DESC_F_NEXT | DESC_F_WRITE
+                } else {
+                    DESC_F_NEXT
+                },
+                next: 2,
+            },
+        );
+        let status = self.transport.dma_addr(STATUS_OFFSET);
+        self.transport.set_descriptor(
+            2,
+            Descriptor {
+                addr: status,
+                len: 1,
+                flags: DESC_F_WRITE,
+                next: 0,
+            },
+        );
+        self.transport.submit(0);
+
+        if unsafe { read_volatile(self.transport.dma_ptr::<u8>(STATUS_OFFSET)) } == BLK_S_OK {
+            Ok(())
+        } else {
+            Err("Block device I/O error")
+        }
+    }
+
+    /// Read `buf.len()` bytes (a multiple of SECTOR_SIZE, at most a page) from `sector` on
+    pub fn read(&mut self, sector: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.request(BLK_T_IN, sector, buf.len())?;
+        let bounce = unsafe {
+            core::slice::from_raw_parts(self.transport.dma_ptr::<u8>(BOUNCE_OFFSET), buf.len())
+        };
+        buf.copy_from_slice(bounce);
+        Ok(())
+    }
+
+    /// Write `buf` (a multiple of SECTOR_SIZE, at most a page) to `sector` on
+    pub fn write(&mut self, sector: u64, buf: &[u8]) -> Result<(), &'static str> {
+        if buf.len() > PAGE_SIZE {
+            return Err("Bad request length");
+        }
+        let bounce = unsafe {
+            core::slice::from_raw_parts_mut(self.transport.dma_ptr::<u8>(BOUNCE_OFFSET), buf.len())
+        };
+        bounce.copy_from_slice(buf);
+        self.request(BLK_T_OUT, sector, buf.len())
+    }
+}
+
+/// The block device, if one was found (see drivers::init)
+pub static BLOCK_DEVICE: Mutex<Option<VirtIOBlock>> = Mutex::new(None);
+
+/// Take the first virtio-blk device among the virtio-mmio slots at `bases`
+pub fn init(bases: impl Iterator<Item = usize>) {
+    for base in bases {
+        if let Some(device) = VirtIOBlock::probe(base) {
+            println!(
+                "[Drivers] virtio-blk at {:#x}: {} sectors ({} KB)",
+                base,
+                device.capacity(),
+                device.capacity() as usize * SECTOR_SIZE / 1024
+            );
+            *BLOCK_DEVICE.lock() = Some(device);
+            return;
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! VirtIO MMIO Transport
+//!
+//! Device setup and a single polled request queue for virtio devices behind
+//! a virtio-mmio transport, as provided by QEMU's `virt` machine. The device
+//! drivers (virtio_blk, virtio_rng) build their requests on top of it.
+//!
+//! - Both the legacy (version 1) and the modern (version 2) MMIO register
+//!   layouts are supported
+//! - One request is in flight at a time: submit() notifies the device and
+//!   spins until the used ring advances, so no interrupts are needed
+//! - The queue lives in DMA frames owned by the transport; drivers keep
+//!   their request data in the rest of those frames (see DRIVER_OFFSET)
+
+use crate::config::memory_layout::PAGE_SIZE;
+use crate::mm::FRAME_ALLOCATOR;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+/// "virt" in little endian
+const VIRTIO_MAGIC: u32 = 0x7472_6976;
+
+// MMIO register offsets
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+const REG_GUEST_PAGE_SIZE: usize = 0x028;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03c;
+const REG_QUEUE_PFN: usize = 0x040;
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_QUEUE_DESC_LOW: usize = 0x080;
+const REG_QUEUE_DESC_HIGH: usize = 0x084;
+const REG_QUEUE_DRIVER_LOW: usize = 0x090;
+const REG_QUEUE_DRIVER_HIGH: usize = 0x094;
+const REG_QUEUE_DEVICE_LOW: usize = 0x0a0;
+const REG_QUEUE_DEVICE_HIGH: usize = 0x0a4;
+/// Device configuration space, laid out by each device type
+const REG_CONFIG: usize = 0x100;
+
+// Device status bits
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+
+/// VIRTIO_F_VERSION_1, bit 32 of the feature bits (bit 0 of feature word 1)
+const FEATURE_VERSION_1: u32 = 1;
+
+// Descriptor flags
+pub const DESC_F_NEXT: u16 = 1;
+pub const DESC_F_WRITE: u16 = 2;
+
+/// Descriptors in the queue
+pub const QUEUE_SIZE: usize = 8;
+
+// Layout of the queue in the DMA frames (legacy layout: the used ring starts
+// on the second page, as required for QueueAlign = PAGE_SIZE)
+const DESC_OFFSET: usize = 0;
+const AVAIL_OFFSET: usize = DESC_OFFSET + 16 * QUEUE_SIZE;
+const USED_OFFSET: usize = PAGE_SIZE;
+
+/// First byte of the DMA frames free for driver data, up to the used ring
+pub const DRIVER_OFFSET: usize = 0x400;
+
+/// DMA frames the queue takes; frames a driver asks for beyond these are its own
+pub const QUEUE_FRAMES: usize = 2;
+
+#[repr(C)]
+pub struct Descriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+/// A virtio-mmio device with its request queue (queue 0)
+pub struct VirtIOMmio {
+    /// Base address of the MMIO registers (identity mapped)
+    base: usize,
+    /// Start of the DMA frames (PA == VA)
+    dma: usize,
+    /// Next free slot of the available ring
+    avail_idx: u16,
+    /// Used ring index seen last
+    used_idx: u16,
+}
+
+// The raw addresses refer to MMIO registers and frames owned by the transport
+unsafe impl Send for VirtIOMmio {}
+
+impl VirtIOMmio {
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// Set up the virtio-mmio device at `base` with `dma_frames` DMA frames
+    /// (at least QUEUE_FRAMES)
+    ///
+    /// No optional features are negotiated.
+    ///
+    /// # Returns
+    /// * `None` if there is no device of type `device_id` at `base`, the
+    ///   device rejects the driver, or no memory is left for the queue
+    pub fn probe(base: usize, device_id: u32, dma_frames: usize) -> Option<Self> {
+        let mut device = Self {
+            base,
+            dma: 0,
+            avail_idx: 0,
+            used_idx: 0,
+        };
+        if device.read_reg(REG_MAGIC) != VIRTIO_MAGIC || device.read_reg(REG_DEVICE_ID) != device_id {
+            return None;
+        }
+        let version = device.read_reg(REG_VERSION);
+        if version != 1 && version != 2 {
+            return None;
+        }
+
+        // Reset, then announce the driver
+        device.write_reg(REG_STATUS, 0);
+        let mut status = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        device.write_reg(REG_STATUS, status);
+
+        // No optional features are used; a modern device must see VERSION_1
+        device.write_reg(REG_DRIVER_FEATURES_SEL, 0);
+        device.write_reg(REG_DRIVER_FEATURES, 0);
+        if version == 2 {
+            device.write_reg(REG_DEVICE_FEATURES_SEL, 1);
+            if device.read_reg(REG_DEVICE_FEATURES) & FEATURE_VERSION_1 == 0 {
+                return None;
+            }
+            device.write_reg(REG_DRIVER_FEATURES_SEL, 1);
+            device.write_reg(REG_DRIVER_FEATURES, FEATURE_VERSION_1);
+            status |= STATUS_FEATURES_OK;
+            device.write_reg(REG_STATUS, status);
+            if device.read_reg(REG_STATUS) & STATUS_FEATURES_OK == 0 {
+                return None;
+            }
+        }
+
+        // Queue 0 is the request queue
+        device.write_reg(REG_QUEUE_SEL, 0);
+        if (device.read_reg(REG_QUEUE_NUM_MAX) as usize) < QUEUE_SIZE {
+            return None;
+        }
+        let dma_ppn = FRAME_ALLOCATOR.alloc_contiguous(dma_frames.max(QUEUE_FRAMES), 1)?;
+        device.dma = dma_ppn.as_addr();
+        device.write_reg(REG_QUEUE_NUM, QUEUE_SIZE as u32);
+        if version == 1 {
+            device.write_reg(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+            device.write_reg(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+            device.write_reg(REG_QUEUE_PFN, dma_ppn.as_usize() as u32);
+        } else {
+            let desc = (device.dma + DESC_OFFSET) as u64;
+            let avail = (device.dma + AVAIL_OFFSET) as u64;
+            let used = (device.dma + USED_OFFSET) as u64;
+            device.write_reg(REG_QUEUE_DESC_LOW, desc as u32);
+            device.write_reg(REG_QUEUE_DESC_HIGH, (desc >> 32) as u32);
+            device.write_reg(REG_QUEUE_DRIVER_LOW, avail as u32);
+            device.write_reg(REG_QUEUE_DRIVER_HIGH, (avail >> 32) as u32);
+            device.write_reg(REG_QUEUE_DEVICE_LOW, used as u32);
+            device.write_reg(REG_QUEUE_DEVICE_HIGH, (used >> 32) as u32);
+            device.write_reg(REG_QUEUE_READY, 1);
+        }
+
+        status |= STATUS_DRIVER_OK;
+        device.write_reg(REG_STATUS, status);
+        Some(device)
+    }
+
+    /// Read a 32-bit word of the device configuration space
+    pub fn read_config(&self, offset: usize) -> u32 {
+        self.read_reg(REG_CONFIG + offset)
+    }
+
+    /// Pointer to `offset` bytes into the DMA frames
+    pub fn dma_ptr<T>(&self, offset: usize) -> *mut T {
+        (self.dma + offset) as *mut T
+    }
+
+    /// Device (physical) address of `offset` bytes into the DMA frames
+    pub fn dma_addr(&self, offset: usize) -> u64 {
+        (self.dma + offset) as u64
+    }
+
+    /// Fill in descriptor `index` (below QUEUE_SIZE)
+    pub fn set_descriptor(&mut self, index: usize, descriptor: Descriptor) {
+        assert!(index < QUEUE_SIZE, "descriptor {} out of range", index);
+        unsafe { *self.dma_ptr::<Descriptor>(DESC_OFFSET).add(index) = descriptor };
+    }
+
+    /// Hand the descriptor chain starting at `head` to the device and spin
+    /// until the device has processed it
+    ///
+    /// # Returns
+    /// * Number of bytes the device wrote into the chain's buffers
+    pub fn submit(&mut self, head: u16) -> u32 {
+        let written = unsafe {
+            // Available ring: flags, idx, ring[QUEUE_SIZE]
+            let avail = self.dma_ptr::<u16>(AVAIL_OFFSET);
+            write_volatile(avail.add(2 + self.avail_idx as usize % QUEUE_SIZE), head);
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            write_volatile(avail.add(1), self.avail_idx);
+            fence(Ordering::SeqCst);
+            self.write_reg(REG_QUEUE_NOTIFY, 0);
+
+            // Used ring: flags, idx, ring[QUEUE_SIZE] of (id: u32, len: u32)
+            let used_idx = self.dma_ptr::<u16>(USED_OFFSET).add(1);
+            while read_volatile(used_idx) == self.used_idx {
+                core::hint::spin_loop();
+            }
+            fence(Ordering::SeqCst);
+            let elem = self
+                .dma_ptr::<u32>(USED_OFFSET + 4)
+                .add(2 * (self.used_idx as usize % QUEUE_SIZE));
+            self.used_idx = self.used_idx.wrapping_add(1);
+            read_volatile(elem.add(1))
+        };
+
+        let interrupts = self.read_reg(REG_INTERRUPT_STATUS);
+        self.write_reg(REG_INTERRUPT_ACK, interrupts);
+        written
+    }
+}
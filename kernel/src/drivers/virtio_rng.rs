@@ -0,0 +1,78 @@
+//! VirtIO Entropy Device Driver
+//!
+//! Minimal polling driver for a virtio-rng device behind a virtio-mmio
+//! transport (see super::virtio_mmio), as provided by QEMU's `virt` machine
+//! with `-device virtio-rng-device`. Used to seed address space layout
+//! randomization (see mm::aslr).
+//!
+//! - A request is a single device-writable buffer in the transport's DMA
+//!   frames; the driver spins until the device has filled it
+
+use super::virtio_mmio::{Descriptor, VirtIOMmio, DESC_F_WRITE, DRIVER_OFFSET, QUEUE_FRAMES};
+use spin::Mutex;
+
+const VIRTIO_DEVICE_ENTROPY: u32 = 4;
+
+/// Largest number of bytes fetched by one request
+const BUFFER_SIZE: usize = 64;
+
+// Layout of the driver data in the DMA frames
+const BUFFER_OFFSET: usize = DRIVER_OFFSET;
+const DMA_FRAMES: usize = QUEUE_FRAMES;
+
+/// A virtio-rng device
+pub struct VirtIORng {
+    transport: VirtIOMmio,
+}
+
+impl VirtIORng {
+    /// Set up the virtio-mmio device at `base`
+    ///
+    /// # Returns
+    /// * `None` if there is no entropy device at `base`, the device rejects
+    ///   the driver, or no memory is left for the queue
+    pub fn probe(base: usize) -> Option<Self> {
+        let transport = VirtIOMmio::probe(base, VIRTIO_DEVICE_ENTROPY, DMA_FRAMES)?;
+        Some(Self { transport })
+    }
+
+    /// Fill `buf` (at most BUFFER_SIZE bytes) with random bytes
+    ///
+    /// # Returns
+    /// * Number of bytes the device provided (may be fewer than requested)
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(BUFFER_SIZE);
+        if len == 0 {
+            return 0;
+        }
+        self.transport.set_descriptor(
+            0,
+            Descriptor {
+                addr: self.transport.dma_addr(BUFFER_OFFSET),
+                len: len as u32,
+                flags: DESC_F_WRITE,
+                next: 0,
+            },
+        );
+        let filled = (self.transport.submit(0) as usize).min(len);
+
+        let data =
+            unsafe { core::slice::from_raw_parts(self.transport.dma_ptr::<u8>(BUFFER_OFFSET), filled) };
+        buf[..filled].copy_from_slice(data);
+        filled
+    }
+}
+
+/// The entropy device, if one was found (see drivers::init)
+pub static RNG_DEVICE: Mutex<Option<VirtIORng>> = Mutex::new(None);
+
+/// Take the first virtio-rng device among the virtio-mmio slots at `bases`
+pub fn init(bases: impl Iterator<Item = usize>) {
+    for base in bases {
+        if let Some(device) = VirtIORng::probe(base) {
+            println!("[Drivers] virtio-rng at {:#x}", base);
+            *RNG_DEVICE.lock() = Some(device);
+            return;
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Flattened Device Tree (FDT) parser
+//!
+//! Reads the DTB the firmware passes in a1 and records what the kernel needs:
+//! - Physical memory regions (`/memory`)
+//! - Number of harts and the timebase frequency (`/cpus`)
+//! - The kernel command line (`/chosen/bootargs`)
+//! - MMIO devices (UART, PLIC, CLINT, virtio-mmio, RTC)
+//!
+//! Everything is copied into fixed-size tables, so parsing needs no heap (it
+//! runs before mm::init) and the DTB memory may be reused afterwards.
+
+use spin::{Mutex, MutexGuard};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// Size of the FDT header
+const FDT_HEADER_SIZE: usize = 40;
+
+/// Largest device tree accepted; QEMU's are a few KB
+const FDT_MAX_SIZE: usize = 2 * 1024 * 1024;
+
+/// Structure block tokens
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+const MAX_MEMORY_REGIONS: usize = 8;
+const MAX_DEVICES: usize = 16;
+const MAX_BOOTARGS_LEN: usize = 256;
+const MAX_DEPTH: usize = 16;
+
+/// A physical memory range [start, end)
+#[derive(Copy, Clone)]
+pub struct MemoryRegion {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DeviceKind {
+    Uart,
+    Plic,
+    Clint,
+    VirtioMmio,
+    Rtc,
+}
+
+/// An MMIO device found in the device tree
+#[derive(Copy, Clone)]
+pub struct Device {
+    pub kind: DeviceKind,
+    pub base: usize,
+    pub size: usize,
+    /// First interrupt number, if the node has one
+    pub irq: Option<u32>,
+}
+
+/// Machine description gathered from the device tree
+pub struct MachineInfo {
+    memory: [MemoryRegion; MAX_MEMORY_REGIONS],
+    memory_count: usize,
+    devices: [Device; MAX_DEVICES],
+    device_count: usize,
+    bootargs: [u8; MAX_BOOTARGS_LEN],
+    bootargs_len: usize,
+    /// Number of harts under /cpus
+    pub harts: usize,
+    /// Frequency of the `time` CSR in Hz (0 if not given)
+    pub timebase_frequency: usize,
+}
+
+impl MachineInfo {
+    const fn empty() -> Self {
+        Self {
+            memory: [MemoryRegion { start: 0, end: 0 }; MAX_MEMORY_REGIONS],
+            memory_count: 0,
+            devices: [Device {
+                kind: DeviceKind::Uart,
+                base: 0,
+                size: 0,
+                irq: None,
+            }; MAX_DEVICES],
+            device_count: 0,
+            bootargs: [0; MAX_BOOTARGS_LEN],
+            bootargs_len: 0,
+            harts: 0,
+            timebase_frequency: 0,
+        }
+    }
+
+    /// Physical memory regions, in device tree order
+    pub fn memory_regions(&self) -> &[MemoryRegion] {
+        &self.memory[..self.memory_count]
+    }
+
+    /// MMIO devices, in device tree order
+    pub fn devices(&self) -> &[Device] {
+        &self.devices[..self.device_count]
+    }
+
+    /// Kernel command line (empty if /chosen has no bootargs)
+    pub fn bootargs(&self) -> &str {
+        core::str::from_utf8(&self.bootargs[..self.bootargs_len]).unwrap_or("")
+    }
+
+    /// Value of a switch on the kernel command line: `on` turns it on, `off`
+    /// turns it off, and the last one given wins (None if neither is given)
+    pub fn boot_flag(&self, on: &str, off: &str) -> Option<bool> {
+        self.bootargs()
+            .split_whitespace()
+            .filter_map(|arg| match arg {
+                arg if arg == on => Some(true),
+                arg if arg == off => Some(false),
+                _ => None,
+            })
+            .last()
+    }
+
+    /// Value of a `key=value` argument on the kernel command line (the last
+    /// one given wins)
+    pub fn boot_arg(&self, key: &str) -> Option<&str> {
+        self.bootargs()
+            .split_whitespace()
+            .filter_map(|arg| arg.strip_prefix(key)?.strip_prefix('='))
+            .last()
+    }
+
+    /// End of the memory region containing `addr`
+    pub fn memory_end_of(&self, addr: usize) -> Option<usize> {
+        self.memory_regions()
+            .iter()
+            .find(|region| region.start <= addr && addr < region.end)
+            .map(|region| region.end)
+    }
+
+    fn add_memory(&mut self, start: usize, size: usize) {
+        if size > 0 && self.memory_count < MAX_MEMORY_REGIONS {
+            self.memory[self.memory_count] = MemoryRegion {
+                start,
+                end: start + size,
+            };
+            self.memory_count += 1;
+        }
+    }
+
+    fn add_device(&mut self, device: Device) {
+        if self.device_count < MAX_DEVICES {
+            self.devices[self.device_count] = device;
+            self.device_count += 1;
+        }
+    }
+
+    fn set_bootargs(&mut self, value: &[u8]) {
+        let value = cstr(value);
+        let len = value.len().min(MAX_BOOTARGS_LEN);
+        self.bootargs[..len].copy_from_slice(&value[..len]);
+        self.bootargs_len = len;
+    }
+}
+
+/// Machine description, filled in by init()
+static MACHINE_INFO: Mutex<MachineInfo> = Mutex::new(MachineInfo::empty());
+
+/// Get the machine description
+///
+/// Empty (no memory, no devices) if init() found no valid device tree.
+pub fn machine_info() -> MutexGuard<'static, MachineInfo> {
+    MACHINE_INFO.lock()
+}
+
+/// Parse the device tree at physical address `dtb`
+///
+/// Must run before paging is enabled (the DTB is accessed by its physical address).
+pub fn init(dtb: usize) {
+    let mut info = MACHINE_INFO.lock();
+    match unsafe { parse(dtb, &mut info) } {
+        Ok(()) => {
+            for region in info.memory_regions() {
+                println!("[FDT] memory: {:#x} - {:#x}", region.start, region.end);
+            }
+            println!("[FDT] harts: {}, timebase: {} Hz", info.harts, info.timebase_frequency);
+            if !info.bootargs().is_empty() {
+                println!("[FDT] bootargs: {}", info.bootargs());
+            }
+        }
+        Err(e) => {
+            *info = MachineInfo::empty();
+            println!("[FDT] Invalid device tree at {:#x}: {}", dtb, e);
+        }
+    }
+}
+
+/// Read a big-endian u32 at `offset`
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read a value of `cells` 32-bit cells (1 or 2) from the front of `data`
+fn read_cells(data: &[u8], cells: u32) -> Option<usize> {
+    match cells {
+        1 => be32(data, 0).map(|value| value as usize),
+        2 => Some(((be32(data, 0)? as usize) << 32) | be32(data, 4)? as usize),
+        _ => None,
+    }
+}
+
+/// Bytes of a NUL-terminated string, without the terminator
+fn cstr(data: &[u8]) -> &[u8] {
+    let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    &data[..len]
+}
+
+/// Round up to the 4-byte alignment of the structure block
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Properties of one node that are only interpreted when the node ends
+#[derive(Copy, Clone)]
+struct Node<'a> {
+    /// Node name without the unit address (`memory@80000000` -> `memory`)
+    name: &'a [u8],
+    reg: &'a [u8],
+    compatible: &'a [u8],
+    device_type: &'a [u8],
+    irq: Option<u32>,
+    disabled: bool,
+    /// #address-cells and #size-cells that apply to this node's children
+    address_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> Node<'a> {
+    const fn new(name: &'a [u8]) -> Self {
+        Self {
+            name,
+            reg: &[],
+            compatible: &[],
+            device_type: &[],
+            irq: None,
+            disabled: false,
+            address_cells: 2,
+            size_cells: 1,
+        }
+    }
+}
+
+/// Device kind for a `compatible` string list
+fn device_kind(compatible: &[u8]) -> Option<DeviceKind> {
+    compatible
+        .split(|&b| b == 0)
+        .find_map(|name| match name {
+            b"ns16550a" | b"ns16550" => Some(DeviceKind::Uart),
+            b"riscv,plic0" | b"sifive,plic-1.0.0" => Some(DeviceKind::Plic),
+            b"riscv,clint0" | b"sifive,clint0" => Some(DeviceKind::Clint),
+            b"virtio,mmio" => Some(DeviceKind::VirtioMmio),
+            b"google,goldfish-rtc" => Some(DeviceKind::Rtc),
+            _ => None,
+        })
+}
+
+/// Record a node once all of its properties have been seen
+fn finish_node(info: &mut MachineInfo, node: &Node, parent: &Node) {
+    let address_cells = parent.address_cells;
+    let size_cells = parent.size_cells;
+    let entry_size = ((address_cells + size_cells) * 4) as usize;
+
+    if node.device_type == b"memory\0" || node.name == b"memory" {
+        if entry_size == 0 {
+            return;
+        }
+        for entry in node.reg.chunks_exact(entry_size) {
+            let start = read_cells(entry, address_cells);
+            let size = read_cells(&entry[(address_cells * 4) as usize..], size_cells);
+            if let (Some(start), Some(size)) = (start, size) {
+                info.add_memory(start, size);
+            }
+        }
+        return;
+    }
+
+    if parent.name == b"cpus" && node.device_type == b"cpu\0" {
+        if !node.disabled {
+            info.harts += 1;
+        }
+        return;
+    }
+
+    if node.disabled {
+        return;
+    }
+    if let Some(kind) = device_kind(node.compatible) {
+        let base = read_cells(node.reg, address_cells);
+        let size = node
+            .reg
+            .get((address_cells * 4) as usize..)
+            .and_then(|rest| read_cells(rest, size_cells));
+        if let (Some(base), Some(size)) = (base, size) {
+            info.add_device(Device {
+                kind,
+                base,
+                size,
+                irq: node.irq,
+            });
+        }
+    }
+}
+
+/// Walk the structure block and fill `info`
+///
+/// # Safety
+/// `dtb` must be the physical address of a readable device tree blob.
+unsafe fn parse(dtb: usize, info: &mut MachineInfo) -> Result<(), &'static str> {
+    if dtb == 0 || dtb % 4 != 0 {
+        return Err("bad address");
+    }
+    let header = core::slice::from_raw_parts(dtb as *const u8, FDT_HEADER_SIZE);
+    if be32(header, 0) != Some(FDT_MAGIC) {
+        return Err("bad magic");
+    }
+    let total_size = be32(header, 4).unwrap() as usize;
+    if !(FDT_HEADER_SIZE..=FDT_MAX_SIZE).contains(&total_size) {
+        return Err("bad total size");
+    }
+    let struct_offset = be32(header, 8).unwrap() as usize;
+    let strings_offset = be32(header, 12).unwrap() as usize;
+    if struct_offset >= total_size {
+        return Err("bad structure block offset");
+    }
+    let data = core::slice::from_raw_parts(dtb as *const u8, total_size);
+    let strings = data.get(strings_offset..).ok_or("bad strings offset")?;
+
+    let mut nodes = [Node::new(&[]); MAX_DEPTH];
+    let mut depth = 0usize;
+    let mut offset = struct_offset;
+    loop {
+        let token = be32(data, offset).ok_or("truncated structure block")?;
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let full_name = cstr(data.get(offset..).ok_or("truncated node name")?);
+                offset = align4(offset + full_name.len() + 1);
+                depth += 1;
+                if depth >= MAX_DEPTH {
+                    return Err("nesting too deep");
+                }
+                let name_len = full_name
+                    .iter()
+                    .position(|&b| b == b'@')
+                    .unwrap_or(full_name.len());
+                nodes[depth] = Node::new(&full_name[..name_len]);
+            }
+            FDT_END_NODE => {
+                if depth == 0 {
+                    return Err("unbalanced nodes");
+                }
+                let (node, parent) = (nodes[depth], nodes[depth - 1]);
+                finish_node(info, &node, &parent);
+                depth -= 1;
+            }
+            FDT_PROP => {
+                let len = be32(data, offset).ok_or("truncated property")? as usize;
+                let name_offset = be32(data, offset + 4).ok_or("truncated property")? as usize;
+                offset += 8;
+                let value = data.get(offset..offset + len).ok_or("truncated property")?;
+                offset = align4(offset + len);
+                let name = cstr(strings.get(name_offset..).ok_or("bad property name")?);
+
+                let node = &mut nodes[depth];
+                match name {
+                    b"#address-cells" => node.address_cells = be32(value, 0).unwrap_or(2),
+                    b"#size-cells" => node.size_cells = be32(value, 0).unwrap_or(1),
+                    b"reg" => node.reg = value,
+                    b"compatible" => node.compatible = value,
+                    b"device_type" => node.device_type = value,
+                    b"interrupts" => node.irq = be32(value, 0),
+                    b"status" => node.disabled = cstr(value) == b"disabled",
+                    b"bootargs" if depth == 2 && node.name == b"chosen" => info.set_bootargs(value),
+                    b"timebase-frequency" if node.name == b"cpus" => {
+                        info.timebase_frequency = read_cells(value, (len / 4) as u32).unwrap_or(0);
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            _ => return Err("bad token"),
+        }
+    }
+
+    if info.memory_count == 0 {
+        return Err("no memory node");
+    }
+    Ok(())
+}
@@ -1,6 +1,7 @@
 #![no_std]
 #![no_main]
 #![feature(alloc_error_handler)]
+#![feature(allocator_api)]
 
 extern crate alloc;
 
@@ -8,6 +9,7 @@ extern crate alloc;
 mod console;
 mod config;
 mod drivers;
+mod fdt;
 mod lang_items;
 mod mm;
 mod sbi;
@@ -38,7 +40,11 @@ pub fn kernel_main(hartid: usize, dtb: usize) -> ! {
     println!("DTB: {:#x}", dtb);
 
     // Initialize subsystems
-    mm::init(dtb);
+    fdt::init(dtb);
+    mm::init();
+    drivers::init();
+    mm::swap::init();
+    mm::aslr::init();
     trap::init();
     task::init();
 
@@ -0,0 +1,165 @@
+//! Address Space Identifiers (ASID)
+//!
+//! Every user address space is tagged with an ASID in satp, so TLB entries of
+//! different address spaces can live side by side and switching satp needs no
+//! TLB flush. ASID 0 belongs to the kernel.
+//!
+//! ASIDs are handed out in generations. An address space remembers the
+//! generation its ASID was taken from; when a generation runs out of ASIDs, a
+//! new one starts with a single full TLB flush and every address space picks
+//! up a fresh ASID the next time it is entered. ASIDs are never reused within
+//! a generation, so freeing an address space needs no flush either.
+//!
+//! ASIDs are only (re)assigned to the address space that is about to run:
+//! starting a new generation while another address space is live on the hart
+//! could hand its ASID to someone else.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Position of the ASID field in satp (Sv39/Sv48)
+const SATP_ASID_SHIFT: usize = 44;
+
+/// Width of the ASID field in satp
+const SATP_ASID_BITS: usize = 16;
+
+struct AsidAllocator {
+    /// Number of ASID bits implemented by the hart (0 = no ASIDs)
+    bits: usize,
+    generation: usize,
+    /// Next unused ASID of the current generation
+    next: usize,
+}
+
+static ASID_ALLOCATOR: Mutex<AsidAllocator> = Mutex::new(AsidAllocator {
+    bits: 0,
+    generation: 1,
+    next: 1,
+});
+
+/// Find out how many ASID bits the hart implements
+///
+/// Writes all ones to the ASID field of satp and reads back which bits stuck.
+/// Must run with the kernel address space active.
+pub fn init() {
+    let satp: usize;
+    let probed: usize;
+    let mask = ((1 << SATP_ASID_BITS) - 1) << SATP_ASID_SHIFT;
+    unsafe {
+        asm!("csrr {}, satp", out(reg) satp);
+        asm!("csrw satp, {}", in(reg) satp | mask);
+        asm!("csrr {}, satp", out(reg) probed);
+        asm!("csrw satp, {}", in(reg) satp);
+        asm!("sfence.vma");
+    }
+    let bits = ((probed & mask) >> SATP_ASID_SHIFT).count_ones() as usize;
+    ASID_ALLOCATOR.lock().bits = bits;
+    println!("[ASID] {} ASID bits", bits);
+}
+
+/// Check whether the hart implements ASIDs
+pub fn enabled() -> bool {
+    ASID_ALLOCATOR.lock().bits > 0
+}
+
+/// ASID of one address space
+///
+/// Stores `generation << SATP_ASID_BITS | asid`, or 0 if none was assigned yet.
+pub struct Asid {
+    value: AtomicUsize,
+    /// The kernel address space, which always uses ASID 0
+    kernel: bool,
+}
+
+impl Asid {
+    /// No ASID yet; one is assigned on first activation
+    pub const fn new() -> Self {
+        Self {
+            value: AtomicUsize::new(0),
+            kernel: false,
+        }
+    }
+
+    /// The kernel's fixed ASID 0
+    pub const fn kernel() -> Self {
+        Self {
+            value: AtomicUsize::new(0),
+            kernel: true,
+        }
+    }
+
+    /// ASID of the current generation, if one is assigned
+    pub fn current(&self) -> Option<usize> {
+        if self.kernel {
+            return Some(0);
+        }
+        let allocator = ASID_ALLOCATOR.lock();
+        let value = self.value.load(Ordering::Relaxed);
+        if allocator.bits > 0 && value >> SATP_ASID_BITS == allocator.generation {
+            Some(value & ((1 << SATP_ASID_BITS) - 1))
+        } else {
+            None
+        }
+    }
+
+    /// ASID to run with, assigning a new one if this address space has none
+    /// from the current generation
+    ///
+    /// Only call this for the address space that is about to be entered.
+    /// Returns 0 if the hart has no ASIDs.
+    pub fn activate(&self) -> usize {
+        if self.kernel {
+            return 0;
+        }
+        let mut allocator = ASID_ALLOCATOR.lock();
+        if allocator.bits == 0 {
+            return 0;
+        }
+        let value = self.value.load(Ordering::Relaxed);
+        if value >> SATP_ASID_BITS == allocator.generation {
+            return value & ((1 << SATP_ASID_BITS) - 1);
+        }
+        if allocator.next >= 1 << allocator.bits {
+            // Out of ASIDs: start over, dropping every tagged translation
+            allocator.generation += 1;
+            allocator.next = 1;
+            unsafe {
+                asm!("sfence.vma");
+            }
+        }
+        let asid = allocator.next;
+        allocator.next += 1;
+        self.value
+            .store((allocator.generation << SATP_ASID_BITS) | asid, Ordering::Relaxed);
+        asid
+    }
+
+    /// Flush this address space's translations of `va`, or all of them
+    ///
+    /// Without ASIDs the whole TLB is flushed. An address space without an
+    /// ASID of the current generation has nothing cached.
+    pub fn flush(&self, va: Option<usize>) {
+        let asid = if enabled() {
+            match self.current() {
+                Some(asid) => asid,
+                None => return,
+            }
+        } else {
+            0
+        };
+        unsafe {
+            match (va, asid) {
+                (Some(va), 0) => asm!("sfence.vma {}, zero", in(reg) va),
+                (Some(va), asid) => asm!("sfence.vma {}, {}", in(reg) va, in(reg) asid),
+                (None, 0) => asm!("sfence.vma"),
+                (None, asid) => asm!("sfence.vma zero, {}", in(reg) asid),
+            }
+        }
+    }
+}
+
+/// satp value with the given ASID
+pub fn satp_with_asid(satp: usize, asid: usize) -> usize {
+    satp | (asid << SATP_ASID_SHIFT)
+}
@@ -0,0 +1,85 @@
+//! Address Space Layout Randomization
+//!
+//! When enabled, MemorySet::from_elf() shifts each new user address space by
+//! a random number of pages:
+//!
+//! - The user stack (and the heap right above it) moves down by up to 16MB
+//! - The mmap search base moves up by up to 256MB
+//! - Position independent executables (ET_DYN) are loaded at a random bias
+//!   of up to 64MB above their base
+//!
+//! Offsets come from a xorshift generator seeded from the virtio-rng device
+//! if there is one, and from the timer otherwise; the timer is mixed in on
+//! every draw as well. ASLR is switched by the boot arguments `aslr` /
+//! `noaslr` (default: crate::config::DEFAULT_ASLR).
+
+use crate::drivers::virtio_rng::RNG_DEVICE;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+/// Range of the user stack shift, in pages (16MB)
+pub const STACK_RANDOM_PAGES: usize = 0x1000;
+
+/// Range of the mmap base shift, in pages (256MB)
+pub const MMAP_RANDOM_PAGES: usize = 0x10000;
+
+/// Range of the PIE load bias, in pages (64MB)
+pub const PIE_RANDOM_PAGES: usize = 0x4000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// State of the xorshift64 generator (never 0)
+static STATE: Mutex<u64> = Mutex::new(0x9e37_79b9_7f4a_7c15);
+
+/// Read the boot arguments and seed the generator
+///
+/// Must run after drivers::init(), so that a virtio-rng device can be used.
+pub fn init() {
+    let enabled = crate::fdt::machine_info()
+        .boot_flag("aslr", "noaslr")
+        .unwrap_or(crate::config::DEFAULT_ASLR);
+    ENABLED.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        println!("[ASLR] disabled");
+        return;
+    }
+
+    let mut seed = [0u8; 8];
+    let from_device = match RNG_DEVICE.lock().as_mut() {
+        Some(device) => device.read(&mut seed) == seed.len(),
+        None => false,
+    };
+    let seed = u64::from_le_bytes(seed) ^ crate::sbi::get_time();
+    *STATE.lock() = if seed == 0 { 1 } else { seed };
+    println!(
+        "[ASLR] enabled, seeded from {}",
+        if from_device { "virtio-rng" } else { "timer" }
+    );
+}
+
+/// Check whether address space layouts are randomized
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Next number of the generator, stirred with the timer
+fn next_random() -> u64 {
+    let mut state = STATE.lock();
+    let mut x = *state ^ crate::sbi::get_time().rotate_left(32);
+    if x == 0 {
+        x = 1;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+/// Random offset of less than `pages` pages, in pages (0 if ASLR is disabled)
+pub fn random_pages(pages: usize) -> usize {
+    if !enabled() || pages == 0 {
+        return 0;
+    }
+    (next_random() % pages as u64) as usize
+}
@@ -1,153 +1,324 @@
 //! Physical Frame Allocator
 //!
-//! Manages physical memory pages using a bitmap allocation strategy.
+//! Manages physical memory pages with a buddy system sized at runtime.
+//!
+//! - Memory is added as regions discovered from the device tree, which may be
+//!   discontiguous; each region carves its own metadata (one byte per frame)
+//!   from its first frames, so no heap and no compile-time limit is needed
+//! - Free blocks of 2^k frames are kept in per-order doubly linked lists that
+//!   live inside the free frames themselves (identity mapping, PA == VA)
+//! - Blocks are aligned to their size in physical address space, so a block
+//!   of order k also satisfies any alignment up to 2^k frames
+//! - alloc/dealloc are O(MAX_ORDER) = O(log n)
 
 use crate::config::memory_layout::*;
 use crate::mm::memory_layout::*;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Largest block order (2^18 frames = 1GB, enough for a 1GB huge page)
+const MAX_ORDER: usize = 18;
+
+/// Maximum number of discontiguous memory regions
+const MAX_REGIONS: usize = 8;
 
-/// Maximum number of physical frames (128MB / 4KB = 32K frames)
-const MAX_FRAMES: usize = 32768;
+/// Metadata byte of a frame that is not the head of a free block
+const NOT_FREE_HEAD: u8 = 0xff;
 
-/// Bitmap-based frame allocator
-pub struct BitmapFrameAllocator {
-    /// Start physical page number
+/// End-of-list marker for free list links
+const NIL: usize = usize::MAX;
+
+/// A contiguous range of managed frames
+#[derive(Copy, Clone)]
+struct Region {
     start_ppn: usize,
-    /// End physical page number
     end_ppn: usize,
-    /// Bitmap for tracking allocated frames (1 = allocated, 0 = free)
-    bitmap: [AtomicUsize; MAX_FRAMES / (core::mem::size_of::<usize>() * 8)],
-    /// Next frame to try allocating (optimization)
-    next: AtomicUsize,
+    /// One byte per frame in [start_ppn, end_ppn): order of the free block
+    /// starting at that frame, or NOT_FREE_HEAD
+    meta: *mut u8,
+}
+
+/// Free list links, stored in the first bytes of each free block
+struct FreeNode {
+    prev: usize,
+    next: usize,
+}
+
+struct BuddyInner {
+    regions: [Region; MAX_REGIONS],
+    region_count: usize,
+    /// Head PPN of each order's free list (NIL if empty)
+    free_lists: [usize; MAX_ORDER + 1],
+    free_frames: usize,
+    total_frames: usize,
+}
+
+// The raw metadata pointers refer to physical memory owned by the allocator
+unsafe impl Send for BuddyInner {}
+
+/// Buddy system frame allocator
+pub struct BuddyFrameAllocator {
+    inner: Mutex<BuddyInner>,
 }
 
-impl BitmapFrameAllocator {
-    /// Create a new frame allocator
+impl BuddyInner {
+    fn node(ppn: usize) -> *mut FreeNode {
+        PhysPageNum::new(ppn).as_ptr::<FreeNode>()
+    }
+
+    fn region_of(&self, ppn: usize) -> Option<&Region> {
+        self.regions[..self.region_count]
+            .iter()
+            .find(|region| region.start_ppn <= ppn && ppn < region.end_ppn)
+    }
+
+    fn meta(&self, ppn: usize) -> Option<u8> {
+        let region = self.region_of(ppn)?;
+        Some(unsafe { *region.meta.add(ppn - region.start_ppn) })
+    }
+
+    fn set_meta(&mut self, ppn: usize, value: u8) {
+        if let Some(region) = self.region_of(ppn) {
+            unsafe { *region.meta.add(ppn - region.start_ppn) = value };
+        }
+    }
+
+    /// Put a free block at the head of its order's list
+    fn push(&mut self, ppn: usize, order: usize) {
+        let head = self.free_lists[order];
+        unsafe {
+            *Self::node(ppn) = FreeNode { prev: NIL, next: head };
+            if head != NIL {
+                (*Self::node(head)).prev = ppn;
+            }
+        }
+        self.free_lists[order] = ppn;
+        self.set_meta(ppn, order as u8);
+    }
+
+    /// Unlink a free block from its order's list
+    fn remove(&mut self, ppn: usize, order: usize) {
+        let FreeNode { prev, next } = unsafe { core::ptr::read(Self::node(ppn)) };
+        if prev == NIL {
+            self.free_lists[order] = next;
+        } else {
+            unsafe { (*Self::node(prev)).next = next };
+        }
+        if next != NIL {
+            unsafe { (*Self::node(next)).prev = prev };
+        }
+        self.set_meta(ppn, NOT_FREE_HEAD);
+    }
+
+    /// Take a block of 2^order frames
+    fn alloc_block(&mut self, order: usize) -> Option<usize> {
+        let mut current = (order..=MAX_ORDER).find(|&k| self.free_lists[k] != NIL)?;
+        let ppn = self.free_lists[current];
+        self.remove(ppn, current);
+        // Split, giving the upper halves back
+        while current > order {
+            current -= 1;
+            self.push(ppn + (1 << current), current);
+        }
+        self.free_frames -= 1 << order;
+        Some(ppn)
+    }
+
+    /// Give back a block of 2^order frames, merging it with free buddies
+    fn free_block(&mut self, mut ppn: usize, mut order: usize) {
+        self.free_frames += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = ppn ^ (1 << order);
+            // The buddy must be a free block of the same order in the same region
+            let same_region = match self.region_of(ppn) {
+                Some(region) => buddy >= region.start_ppn && buddy + (1 << order) <= region.end_ppn,
+                None => false,
+            };
+            if !same_region || self.meta(buddy) != Some(order as u8) {
+                break;
+            }
+            self.remove(buddy, order);
+            ppn = ppn.min(buddy);
+            order += 1;
+        }
+        self.push(ppn, order);
+    }
+
+    /// Order of the largest naturally aligned block at `ppn` that ends by `end`
+    fn largest_block(ppn: usize, end: usize) -> usize {
+        let mut order = (ppn.trailing_zeros() as usize).min(MAX_ORDER);
+        while ppn + (1 << order) > end {
+            order -= 1;
+        }
+        order
+    }
+
+    /// Give back the frames [start, end) in the largest naturally aligned blocks
+    fn free_range(&mut self, start: usize, end: usize) {
+        let mut ppn = start;
+        while ppn < end {
+            let order = Self::largest_block(ppn, end);
+            self.free_block(ppn, order);
+            ppn += 1 << order;
+        }
+    }
+
+    /// Check if frame `ppn` lies in a free block
+    fn is_free(&self, ppn: usize) -> bool {
+        (0..=MAX_ORDER).any(|order| self.meta(ppn & !((1 << order) - 1)) == Some(order as u8))
+    }
+}
+
+impl BuddyFrameAllocator {
+    /// Create an empty frame allocator (memory is added with add_region)
     pub const fn new() -> Self {
-        const ATOMIC_ZERO: AtomicUsize = AtomicUsize::new(0);
         Self {
-            start_ppn: 0,
-            end_ppn: 0,
-            bitmap: [ATOMIC_ZERO; MAX_FRAMES / (core::mem::size_of::<usize>() * 8)],
-            next: AtomicUsize::new(0),
+            inner: Mutex::new(BuddyInner {
+                regions: [Region {
+                    start_ppn: 0,
+                    end_ppn: 0,
+                    meta: core::ptr::null_mut(),
+                }; MAX_REGIONS],
+                region_count: 0,
+                free_lists: [NIL; MAX_ORDER + 1],
+                free_frames: 0,
+                total_frames: 0,
+            }),
         }
     }
 
-    /// Initialize the frame allocator with memory range
+    /// Hand the physical memory [start, end) to the allocator
+    ///
+    /// The first frames of the region hold its metadata and are never handed out.
     ///
     /// # Safety
-    /// Must be called only once during system initialization
-    pub unsafe fn init(&mut self, start: usize, end: usize) {
+    /// The range must be unused RAM, accessible at its physical address, and
+    /// must not overlap a region added before.
+    pub unsafe fn add_region(&self, start: usize, end: usize) {
+        let mut inner = self.inner.lock();
         let start_ppn = align_up(start) >> PAGE_SIZE_BITS;
         let end_ppn = align_down(end) >> PAGE_SIZE_BITS;
+        if inner.region_count == MAX_REGIONS || end_ppn <= start_ppn {
+            return;
+        }
+        let frames = end_ppn - start_ppn;
+        let meta_frames = (frames + PAGE_SIZE - 1) / PAGE_SIZE;
+        if meta_frames >= frames {
+            return;
+        }
 
-        self.start_ppn = start_ppn;
-        self.end_ppn = end_ppn;
-        self.next.store(0, Ordering::Relaxed);
+        let meta = PhysPageNum::new(start_ppn).as_ptr::<u8>();
+        core::ptr::write_bytes(meta, NOT_FREE_HEAD, frames);
+        let index = inner.region_count;
+        inner.regions[index] = Region {
+            start_ppn,
+            end_ppn,
+            meta,
+        };
+        inner.region_count += 1;
+        inner.total_frames += frames - meta_frames;
 
-        // Clear all bitmap entries
-        for entry in self.bitmap.iter() {
-            entry.store(0, Ordering::Relaxed);
-        }
+        // Cover the rest with the largest naturally aligned blocks that fit
+        inner.free_range(start_ppn + meta_frames, end_ppn);
     }
 
-    /// Allocate a physical frame
+    /// Allocate a physical frame (zeroed)
     pub fn alloc(&self) -> Option<PhysPageNum> {
-        let total_frames = self.end_ppn - self.start_ppn;
-        let start_idx = self.next.load(Ordering::Relaxed);
-
-        // Search from next position
-        for offset in 0..total_frames {
-            let idx = (start_idx + offset) % total_frames;
-            let bitmap_idx = idx / (core::mem::size_of::<usize>() * 8);
-            let bit_idx = idx % (core::mem::size_of::<usize>() * 8);
-
-            let old_val = self.bitmap[bitmap_idx].load(Ordering::Acquire);
-            let mask = 1usize << bit_idx;
-
-            // If bit is 0 (free), try to set it to 1 (allocated)
-            if (old_val & mask) == 0 {
-                let new_val = old_val | mask;
-                if self.bitmap[bitmap_idx]
-                    .compare_exchange(old_val, new_val, Ordering::AcqRel, Ordering::Acquire)
-                    .is_ok()
-                {
-                    // Successfully allocated
-                    self.next.store((idx + 1) % total_frames, Ordering::Relaxed);
-                    let ppn = self.start_ppn + idx;
-
-                    // Zero out the frame for security
-                    let ptr = (ppn << PAGE_SIZE_BITS) as *mut u8;
-                    unsafe {
-                        core::ptr::write_bytes(ptr, 0, PAGE_SIZE);
-                    }
-
-                    return Some(PhysPageNum::new(ppn));
-                }
-            }
-        }
-
-        None // Out of memory
+        self.alloc_contiguous(1, 1)
     }
 
-    /// Deallocate a physical frame
-    pub fn dealloc(&self, ppn: PhysPageNum) {
-        let ppn_val = ppn.as_usize();
-        if ppn_val < self.start_ppn || ppn_val >= self.end_ppn {
-            return; // Invalid frame
+    /// Allocate `count` physically contiguous frames (zeroed)
+    ///
+    /// # Arguments
+    /// * `count` - Number of frames
+    /// * `align` - Alignment of the first frame, in frames (power of two)
+    ///
+    /// # Returns
+    /// * PPN of the first frame; free the range with dealloc_contiguous()
+    pub fn alloc_contiguous(&self, count: usize, align: usize) -> Option<PhysPageNum> {
+        if count == 0 || !align.is_power_of_two() {
+            return None;
+        }
+        let order = (count.next_power_of_two().trailing_zeros() as usize)
+            .max(align.trailing_zeros() as usize);
+        if order > MAX_ORDER {
+            return None;
         }
 
-        let idx = ppn_val - self.start_ppn;
-        let bitmap_idx = idx / (core::mem::size_of::<usize>() * 8);
-        let bit_idx = idx % (core::mem::size_of::<usize>() * 8);
+        let mut inner = self.inner.lock();
+        let ppn = inner.alloc_block(order)?;
+        // Return the unused tail of the block
+        inner.free_range(ppn + count, ppn + (1 << order));
+        drop(inner);
 
-        let mask = !(1usize << bit_idx);
-        self.bitmap[bitmap_idx].fetch_and(mask, Ordering::Release);
+        // Zero out the frames for security
+        unsafe {
+            core::ptr::write_bytes(PhysPageNum::new(ppn).as_ptr::<u8>(), 0, count * PAGE_SIZE);
+        }
+        Some(PhysPageNum::new(ppn))
     }
 
-    /// Get number of free frames
-    pub fn free_frames(&self) -> usize {
-        let total_frames = self.end_ppn - self.start_ppn;
-        let mut free_count = 0;
-
-        for idx in 0..total_frames {
-            let bitmap_idx = idx / (core::mem::size_of::<usize>() * 8);
-            let bit_idx = idx % (core::mem::size_of::<usize>() * 8);
-            let val = self.bitmap[bitmap_idx].load(Ordering::Relaxed);
+    /// Deallocate a physical frame
+    pub fn dealloc(&self, ppn: PhysPageNum) {
+        self.dealloc_contiguous(ppn, 1);
+    }
 
-            if (val & (1usize << bit_idx)) == 0 {
-                free_count += 1;
+    /// Deallocate `count` contiguous frames starting at `ppn`
+    ///
+    /// The range is given back in the largest naturally aligned blocks, so a
+    /// huge page costs O(MAX_ORDER). Blocks outside managed memory or already
+    /// free are reported and skipped (and trip an assertion in debug builds).
+    pub fn dealloc_contiguous(&self, ppn: PhysPageNum, count: usize) {
+        let mut inner = self.inner.lock();
+        let end = ppn.as_usize() + count;
+        let mut frame = ppn.as_usize();
+        while frame < end {
+            let order = BuddyInner::largest_block(frame, end);
+            let block_end = frame + (1 << order);
+            let managed = matches!(inner.region_of(frame), Some(region) if block_end <= region.end_ppn);
+            if !managed {
+                println!("[Frame] free of frames {:#x}..{:#x} outside managed memory", frame, block_end);
+                debug_assert!(false, "frames freed outside managed memory");
+            } else if inner.is_free(frame) || inner.is_free(block_end - 1) {
+                println!("[Frame] double free of frames {:#x}..{:#x}", frame, block_end);
+                debug_assert!(false, "frames freed twice");
+            } else {
+                inner.free_block(frame, order);
             }
+            frame = block_end;
         }
+    }
 
-        free_count
+    /// Get number of free frames
+    pub fn free_frames(&self) -> usize {
+        self.inner.lock().free_frames
     }
 
     /// Get total number of frames
     pub fn total_frames(&self) -> usize {
-        self.end_ppn - self.start_ppn
+        self.inner.lock().total_frames
     }
 }
 
 /// Global frame allocator instance
-pub static FRAME_ALLOCATOR: BitmapFrameAllocator = BitmapFrameAllocator::new();
+pub static FRAME_ALLOCATOR: BuddyFrameAllocator = BuddyFrameAllocator::new();
 
-/// Initialize the global frame allocator
+/// Add a physical memory region to the global frame allocator
 ///
 /// # Safety
-/// Must be called only once during system initialization
-pub unsafe fn init(start: usize, end: usize) {
-    let allocator = &FRAME_ALLOCATOR as *const _ as *mut BitmapFrameAllocator;
-    (*allocator).init(start, end);
+/// See BuddyFrameAllocator::add_region
+pub unsafe fn add_region(start: usize, end: usize) {
+    FRAME_ALLOCATOR.add_region(start, end);
 }
 
 /// Frame allocator trait for abstraction
 pub trait FrameAllocator {
     fn alloc(&self) -> Option<PhysPageNum>;
     fn dealloc(&self, ppn: PhysPageNum);
+    fn alloc_contiguous(&self, count: usize, align: usize) -> Option<PhysPageNum>;
+    fn dealloc_contiguous(&self, ppn: PhysPageNum, count: usize);
 }
 
-impl FrameAllocator for BitmapFrameAllocator {
+impl FrameAllocator for BuddyFrameAllocator {
     fn alloc(&self) -> Option<PhysPageNum> {
         self.alloc()
     }
@@ -155,4 +326,12 @@ impl FrameAllocator for BitmapFrameAllocator {
     fn dealloc(&self, ppn: PhysPageNum) {
         self.dealloc(ppn)
     }
+
+    fn alloc_contiguous(&self, count: usize, align: usize) -> Option<PhysPageNum> {
+        self.alloc_contiguous(count, align)
+    }
+
+    fn dealloc_contiguous(&self, ppn: PhysPageNum, count: usize) {
+        self.dealloc_contiguous(ppn, count)
+    }
 }
@@ -1,26 +1,92 @@
 //! Heap Allocator
 //!
 //! Uses buddy system allocator for efficient memory management
+//!
+//! The heap starts as a block of KERNEL_HEAP_SIZE taken from the frame
+//! allocator, and grows by pulling more frames whenever an allocation does not
+//! fit. Frames added to the heap are never given back.
 
 use crate::config::memory_layout::*;
-use buddy_system_allocator::LockedHeap;
+use crate::mm::frame_allocator::FRAME_ALLOCATOR;
+use buddy_system_allocator::{Heap, LockedHeapWithRescue};
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Minimum heap growth step (1MB)
+const HEAP_GROW_MIN: usize = 0x10_0000;
 
 /// Global allocator instance using Buddy System
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap<32> = LockedHeap::empty();
+static HEAP_ALLOCATOR: LockedHeapWithRescue<32> = LockedHeapWithRescue::new(grow_heap);
+
+/// Number of times the heap has grown
+static HEAP_GROW_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Kernel heap usage, as reported by sys_heap_stats
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct HeapStats {
+    /// Bytes handed to the heap so far (initial block plus growth)
+    pub total_bytes: usize,
+    /// Bytes requested by live allocations
+    pub user_bytes: usize,
+    /// Bytes taken by live allocations, including rounding
+    pub actual_bytes: usize,
+    /// Number of times the heap has grown
+    pub grow_count: usize,
+}
 
 /// Initialize the heap allocator
 ///
+/// Must run after the frame allocator has been given memory.
+///
 /// # Safety
 /// Must be called only once during system initialization
 pub unsafe fn init_heap() {
+    let frames = KERNEL_HEAP_SIZE / PAGE_SIZE;
+    let start = FRAME_ALLOCATOR
+        .alloc_contiguous(frames, frames)
+        .expect("No memory for the kernel heap");
     HEAP_ALLOCATOR
         .lock()
-        .init(KERNEL_HEAP_START, KERNEL_HEAP_SIZE);
+        .init(start.as_addr(), KERNEL_HEAP_SIZE);
+}
+
+/// Grow the heap after a failed allocation
+///
+/// Called by the allocator with the heap locked. Adds a naturally aligned
+/// block of frames big enough for `layout`; if the frame allocator has none
+/// left, the allocation fails and alloc_error_handler reports it.
+fn grow_heap(heap: &mut Heap<32>, layout: &Layout) {
+    let size = layout
+        .size()
+        .max(layout.align())
+        .next_power_of_two()
+        .max(HEAP_GROW_MIN);
+    let frames = size / PAGE_SIZE;
+    if let Some(start) = FRAME_ALLOCATOR.alloc_contiguous(frames, frames) {
+        unsafe {
+            heap.add_to_heap(start.as_addr(), start.as_addr() + size);
+        }
+        HEAP_GROW_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Get current heap usage
+pub fn stats() -> HeapStats {
+    let heap = HEAP_ALLOCATOR.lock();
+    HeapStats {
+        total_bytes: heap.stats_total_bytes(),
+        user_bytes: heap.stats_alloc_user(),
+        actual_bytes: heap.stats_alloc_actual(),
+        grow_count: HEAP_GROW_COUNT.load(Ordering::Relaxed),
+    }
 }
 
 /// Allocation error handler
+///
+/// Only reached when the heap could not grow: physical memory is exhausted.
 #[alloc_error_handler]
 fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
-    panic!("Allocation error: {:?}", layout);
+    panic!("Allocation error: {:?} (out of physical memory)", layout);
 }
@@ -156,10 +156,14 @@ impl VirtPageNum {
         Self(addr >> PAGE_SIZE_BITS)
     }
 
-    /// Get indexes for 3-level page table (SV39)
-    pub fn indexes(&self) -> [usize; 3] {
+    /// Get page table indexes, root level first
+    ///
+    /// Always returns the four Sv48 levels; in Sv39 mode the walk starts at
+    /// PagingMode::root_index() and the first entry is unused.
+    pub fn indexes(&self) -> [usize; 4] {
         let vpn = self.0;
         [
+            (vpn >> 27) & 0x1FF, // Level 3
             (vpn >> 18) & 0x1FF, // Level 2
             (vpn >> 9) & 0x1FF,  // Level 1
             vpn & 0x1FF,         // Level 0
@@ -13,14 +13,40 @@
 //! 
 //! The ELF file structure is preserved - no stripping of ELF headers is needed.
 //! The kernel parses ELF structure and extracts segments for memory mapping.
+//!
+//! User pages are shared copy-on-write between a parent and its forked child:
+//! frames are reference-counted (`FrameRef`), shared writable pages
+//! are mapped read-only, and the first store copies the page (see
+//! `MemorySet::handle_cow_fault`).
+//!
+//! Lazy areas (user stack, anonymous mmap) get their frames on first touch
+//! from the page fault handler (see `MemorySet::handle_page_fault`).
+//!
+//! Shared areas (MAP_SHARED, shared memory objects) keep pointing at the same
+//! frames after fork and are never copied on write.
+//!
+//! Areas may use huge pages (see `MapArea::with_page_size`): identity mappings
+//! pick the largest leaf that fits at each address, framed areas map whole
+//! 2MB/1GB pages backed by contiguous frames. Huge pages are not shared
+//! copy-on-write; fork copies them right away.
+//!
+//! With ASLR (see `super::aslr`), `MemorySet::from_elf` places the user stack,
+//! the mmap base and position independent executables at random offsets.
 
+use super::asid::{self, Asid};
+use super::aslr;
 use super::frame_allocator::FRAME_ALLOCATOR;
 use super::memory_layout::*;
-use super::page_table::{PTEFlags, PageTable, PageTableEntry};
-use crate::config::memory_layout::{PAGE_SIZE, MEMORY_END, USER_STACK_SIZE};
+use super::page_table::{paging_mode, PTEFlags, PageSize, PageTable, PageTableEntry};
+use super::slab::{SlabAllocator, FRAME_TRACKER_CACHE, MAP_AREA_CACHE};
+use super::swap::{SwapRef, SwapSlot};
+use crate::config::memory_layout::{PAGE_SIZE, USER_STACK_SIZE};
+use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
@@ -30,6 +56,15 @@ lazy_static! {
     static ref TRAMPOLINE_FRAME: Mutex<Option<PhysPageNum>> = Mutex::new(None);
 }
 
+/// Why a user page fault could not be resolved
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FaultError {
+    /// The access is not allowed, or a swapped out page could not be read back
+    AccessViolation,
+    /// The access is valid, but no frame was left to back it
+    OutOfMemory,
+}
+
 /// Memory area map type
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum MapType {
@@ -49,6 +84,29 @@ bitflags::bitflags! {
     }
 }
 
+/// W^X policy for user mappings (see crate::config::DEFAULT_W_XOR_X)
+static W_XOR_X: AtomicBool = AtomicBool::new(false);
+
+/// Turn the W^X policy on or off (set once at boot, see mm::init)
+pub fn set_w_xor_x(enforce: bool) {
+    W_XOR_X.store(enforce, Ordering::Relaxed);
+}
+
+/// Check whether the W^X policy is enforced
+pub fn w_xor_x() -> bool {
+    W_XOR_X.load(Ordering::Relaxed)
+}
+
+/// Error from MemorySet::from_elf() for a segment the W^X policy refuses
+pub const ERR_W_XOR_X: &str = "Writable and executable segment refused by W^X";
+
+impl MapPermission {
+    /// Check if user pages with these rights are refused by the W^X policy
+    pub fn violates_w_xor_x(self) -> bool {
+        w_xor_x() && self.contains(MapPermission::W | MapPermission::X)
+    }
+}
+
 /// Virtual page number range
 #[derive(Copy, Clone)]
 pub struct VPNRange {
@@ -85,38 +143,82 @@ impl Iterator for VPNRange {
 }
 
 /// Frame tracker - automatically frees frame when dropped
+///
+/// Map areas hold frames through `FrameRef` (an `Arc<FrameTracker>`), so a
+/// frame shared copy-on-write by several address spaces is freed when the last
+/// one drops it.
 pub struct FrameTracker {
     pub ppn: PhysPageNum,
+    /// Frames from `ppn` on that are owned (more than one for a huge page)
+    pub size: PageSize,
 }
 
 impl FrameTracker {
+    /// Track a frame from the frame allocator, which already zeroed it
     pub fn new(ppn: PhysPageNum) -> Self {
-        // Clear the frame
-        // Convert physical page number to kernel virtual address (identity mapping)
-        let kernel_va = ppn.addr().0;
-        let bytes_array = kernel_va as *mut u8;
-            unsafe {
-            core::ptr::write_bytes(bytes_array, 0, PAGE_SIZE);
+        Self {
+            ppn,
+            size: PageSize::Size4K,
         }
-        Self { ppn }
+    }
+
+    /// Track `ppn` through a reference-counted handle from the frame tracker cache
+    pub fn new_ref(ppn: PhysPageNum) -> FrameRef {
+        Arc::new_in(Self::new(ppn), FRAME_TRACKER_CACHE.allocator())
+    }
+
+    /// Track a huge page of contiguous frames starting at `ppn`
+    ///
+    /// The frames must come from alloc_contiguous(), which already zeroed them.
+    pub fn new_huge(ppn: PhysPageNum, size: PageSize) -> FrameRef {
+        Arc::new_in(Self { ppn, size }, FRAME_TRACKER_CACHE.allocator())
     }
 }
 
+/// Reference-counted frame, allocated from the frame tracker slab cache
+pub type FrameRef = Arc<FrameTracker, SlabAllocator>;
+
 impl Drop for FrameTracker {
     fn drop(&mut self) {
-        FRAME_ALLOCATOR.dealloc(self.ppn);
+        FRAME_ALLOCATOR.dealloc_contiguous(self.ppn, self.size.pages());
     }
 }
 
+/// Swap copy of a private page
+#[derive(Clone)]
+pub struct SwapEntry {
+    slot: SwapRef,
+    /// PTE flags to map the page with when it is faulted back in
+    flags: PTEFlags,
+}
+
 /// Map area
 pub struct MapArea {
     vpn_range: VPNRange,
     actual_start_va: usize,  // Actual start virtual address (before page alignment)
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    data_frames: BTreeMap<VirtPageNum, FrameRef>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// Populate pages on first touch instead of in map() (demand paging)
+    lazy: bool,
+    /// Frames are shared with other address spaces (MAP_SHARED), never copied on write
+    shared: bool,
+    /// Largest leaf size used for identity mappings; exact leaf size of framed pages
+    page_size: PageSize,
+    /// Swap copies of pages of this area: the page is swapped out if it is
+    /// not in data_frames, otherwise the copy is still valid if the page is clean
+    swap_slots: BTreeMap<VirtPageNum, SwapEntry>,
+    /// 4KB pages backed by the frames in data_frames (the area's share of the RSS)
+    resident_pages: usize,
+    /// The area is a user stack: faults right below it extend it downward
+    grows_down: bool,
+    /// The area (a stack or heap) may not become executable under W^X
+    no_exec: bool,
 }
 
+/// Map area, allocated from the map area slab cache
+pub type AreaBox = Box<MapArea, SlabAllocator>;
+
 impl MapArea {
     pub fn new(
         start_va: usize,
@@ -150,9 +252,40 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            lazy: false,
+            shared: false,
+            page_size: PageSize::Size4K,
+            swap_slots: BTreeMap::new(),
+            resident_pages: 0,
+            grows_down: false,
+            no_exec: false,
         }
     }
     
+    /// Create a framed map area whose pages are allocated on first access
+    ///
+    /// No frames are allocated by map(); each page is populated by
+    /// MemorySet::handle_page_fault() the first time it is touched.
+    pub fn new_lazy(start_va: usize, end_va: usize, map_perm: MapPermission) -> Self {
+        let mut area = Self::new(start_va, end_va, MapType::Framed, map_perm);
+        area.lazy = true;
+        area
+    }
+    
+    /// Create a lazy user stack area that can grow down (see MemorySet::grow_stack())
+    pub fn new_stack(start_va: usize, end_va: usize, map_perm: MapPermission) -> Self {
+        let mut area = Self::new_lazy(start_va, end_va, map_perm).non_executable();
+        area.grows_down = true;
+        area
+    }
+    
+    /// Keep this area from becoming executable while W^X is enforced
+    /// (for stacks and heaps, which only ever hold data)
+    pub fn non_executable(mut self) -> Self {
+        self.no_exec = true;
+        self
+    }
+    
     /// Get the start virtual address of this area
     pub fn start_va(&self) -> usize {
         self.vpn_range.start().addr().0
@@ -173,12 +306,119 @@ impl MapArea {
         self.map_perm
     }
     
+    /// Create a framed map area backed by the given shared frames
+    ///
+    /// Page i of the area maps frames[i]. The frames stay shared across fork
+    /// and are released when the last area (or shared memory object) drops them.
+    pub fn new_shared(
+        start_va: usize,
+        map_perm: MapPermission,
+        frames: &[FrameRef],
+    ) -> Self {
+        let end_va = start_va + frames.len() * PAGE_SIZE;
+        let mut area = Self::new(start_va, end_va, MapType::Framed, map_perm);
+        for (vpn, frame) in area.vpn_range.zip(frames.iter()) {
+            area.insert_frame(vpn, Arc::clone(frame));
+        }
+        area.shared = true;
+        area
+    }
+    
+    /// Use huge pages for this area
+    ///
+    /// Identical areas map each part with the largest leaf, up to `size`, that
+    /// is aligned and fits. Framed areas map only `size` pages, so their range
+    /// must be aligned to `size`.
+    pub fn with_page_size(mut self, size: PageSize) -> Self {
+        if self.map_type == MapType::Framed {
+            assert!(
+                self.vpn_range.start().0 % size.pages() == 0
+                    && self.vpn_range.end().0 % size.pages() == 0,
+                "Huge page area is not aligned"
+            );
+        }
+        self.page_size = size;
+        self
+    }
+    
+    /// Track `frame` as backing `vpn`, replacing the frame tracked before
+    fn insert_frame(&mut self, vpn: VirtPageNum, frame: FrameRef) {
+        self.resident_pages += frame.size.pages();
+        if let Some(old) = self.data_frames.insert(vpn, frame) {
+            self.resident_pages -= old.size.pages();
+        }
+    }
+    
+    /// Stop tracking the frame backing `vpn`
+    fn remove_frame(&mut self, vpn: VirtPageNum) -> Option<FrameRef> {
+        let frame = self.data_frames.remove(&vpn)?;
+        self.resident_pages -= frame.size.pages();
+        Some(frame)
+    }
+    
+    /// Number of 4KB pages of this area backed by frames
+    pub fn resident_pages(&self) -> usize {
+        self.resident_pages
+    }
+    
+    /// Check if pages of this area are allocated on demand
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+    
+    /// Check if this area is a stack that grows down
+    pub fn grows_down(&self) -> bool {
+        self.grows_down
+    }
+    
+    /// Check if a virtual page lies in this area
+    pub fn contains(&self, vpn: VirtPageNum) -> bool {
+        self.vpn_range.start() <= vpn && vpn < self.vpn_range.end()
+    }
+    
+    /// PTE flags for the pages of this area
+    ///
+    /// MapPermission doesn't include the V (Valid) bit, so it is added here.
+    /// A valid PTE without R/W/X would be a pointer to the next level, so an
+    /// area without any access right (PROT_NONE) maps its pages as kernel-only
+    /// readable instead: the page stays mapped, but every user access faults.
+    pub fn pte_flags(&self) -> PTEFlags {
+        let perm = self.map_perm;
+        if perm.intersects(MapPermission::R | MapPermission::W | MapPermission::X) {
+            PTEFlags::V | PTEFlags::from_bits(perm.bits()).unwrap()
+        } else {
+            PTEFlags::V | PTEFlags::R
+        }
+    }
+    
+    /// Map the huge page containing `vpn` (framed huge page areas)
+    fn map_one_huge(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), &'static str> {
+        let size = self.page_size;
+        let base = VirtPageNum::new(vpn.0 - vpn.0 % size.pages());
+        if self.data_frames.contains_key(&base) {
+            return Ok(());
+        }
+        let ppn = FRAME_ALLOCATOR
+            .alloc_contiguous(size.pages(), size.pages())
+            .ok_or("Out of memory")?;
+        let frame = FrameTracker::new_huge(ppn, size);
+        page_table.map_page(base, ppn, self.pte_flags(), size)?;
+        self.insert_frame(base, frame);
+        Ok(())
+    }
+    
     /// Map one page
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        // Convert MapPermission to PTEFlags
-        // MapPermission doesn't include V (Valid) bit, so we need to add it
-        let perm_bits = self.map_perm.bits();
-        let pte_flags = PTEFlags::V | PTEFlags::from_bits(perm_bits).unwrap();
+    ///
+    /// In a framed huge page area this maps the whole huge page containing `vpn`.
+    ///
+    /// # Returns
+    /// * `Err` if a frame for the page or for the page table ran out; nothing
+    ///   is left mapped for `vpn` then
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), &'static str> {
+        if self.map_type == MapType::Framed && self.page_size != PageSize::Size4K {
+            return self.map_one_huge(page_table, vpn);
+        }
+        let pte_flags = self.pte_flags();
         
         // Check if page is already mapped
         if let Some((existing_ppn, existing_flags)) = page_table.translate(vpn) {
@@ -211,11 +451,11 @@ impl MapArea {
                     unsafe {
                         let indexes = vpn.indexes();
                         let mut current_table = page_table as *mut PageTable;
-                        for &index in &indexes[..2] {
+                        for &index in &indexes[paging_mode().root_index()..3] {
                             let entry = (*current_table).entry_mut(index);
                             current_table = entry.ppn().as_ptr::<PageTable>();
                         }
-                        let leaf_entry = (*current_table).entry_mut(indexes[2]);
+                        let leaf_entry = (*current_table).entry_mut(indexes[3]);
                         *leaf_entry = PageTableEntry::new_with_ppn(existing_ppn, merged_flags);
                     }
                 }
@@ -225,7 +465,7 @@ impl MapArea {
                 // The frame will be freed when the first MapArea is dropped.
                 
                 // Skip remapping - we're reusing the existing page
-                return;
+                return Ok(());
             }
             
             // Can't reuse - this is an error case (shouldn't happen for user space)
@@ -233,45 +473,46 @@ impl MapArea {
         }
         
         // Allocate or determine PPN
-        let ppn: PhysPageNum = match self.map_type {
-            MapType::Identical => PhysPageNum::new(vpn.0),
+        // A new frame is only tracked once it is mapped, so a failed map releases it
+        let (ppn, new_frame) = match self.map_type {
+            MapType::Identical => (PhysPageNum::new(vpn.0), None),
             MapType::Framed => {
                 // Check if we already have a frame tracked
                 if let Some(frame_tracker) = self.data_frames.get(&vpn) {
-                    frame_tracker.ppn
+                    (frame_tracker.ppn, None)
                 } else {
-                    // Allocate a new frame
-                    let frame = FRAME_ALLOCATOR.alloc().expect("Failed to allocate frame");
-                    // CRITICAL: Clear the frame to ensure no garbage data
-                    let frame_va = frame.addr().0;
-                    unsafe {
-                        core::ptr::write_bytes(frame_va as *mut u8, 0, PAGE_SIZE);
-                    }
-                    self.data_frames.insert(vpn, FrameTracker::new(frame));
-                    frame
+                    // Allocate a new frame (zeroed by the allocator)
+                    let frame = FRAME_ALLOCATOR.alloc().ok_or("Out of memory")?;
+                    (frame, Some(FrameTracker::new_ref(frame)))
                 }
             }
         };
         
         // Map the page
-        if let Err(e) = page_table.map(vpn, ppn, pte_flags) {
-            panic!("Failed to map page: {}", e);
+        page_table.map(vpn, ppn, pte_flags)?;
+        if let Some(frame) = new_frame {
+            self.insert_frame(vpn, frame);
         }
+        Ok(())
     }
     
     /// Unmap one page
     /// Only unmap if this MapArea owns the page (tracked in data_frames)
     /// If multiple MapAreas share the same page, only the owner should unmap it
+    ///
+    /// A huge page is unmapped through its first page; other pages of it are skipped.
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         if self.map_type == MapType::Framed {
+            // A swapped out page has no PTE, only its slot
+            self.swap_slots.remove(&vpn);
             // Only unmap if we own this page (tracked in data_frames)
-            if self.data_frames.remove(&vpn).is_some() {
+            if self.remove_frame(vpn).is_some() {
                 // We own this page, so unmap it
                 page_table.unmap(vpn).expect("Failed to unmap page");
             }
             // If we don't own this page (not in data_frames), it's owned by another MapArea
             // Don't unmap it - let the owner handle it
-        } else {
+        } else if vpn.0 % page_table.leaf_size(vpn).map_or(1, |size| size.pages()) == 0 {
             // For Identical mapping, always unmap
             page_table.unmap(vpn).expect("Failed to unmap page");
         }
@@ -283,17 +524,207 @@ impl MapArea {
     /// - Iterates through all virtual pages in the segment
     /// - Allocates physical frames for each page
     /// - Creates page table entries mapping virtual pages to physical frames
-    pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+    ///
+    /// If memory runs out, the pages mapped so far are unmapped again and the
+    /// error is returned.
+    pub fn map(&mut self, page_table: &mut PageTable) -> Result<(), &'static str> {
+        // Lazy areas are populated page by page from the page fault handler
+        if self.lazy {
+            return Ok(());
+        }
+        if self.map_type == MapType::Identical && self.page_size != PageSize::Size4K {
+            self.map_identical_huge(page_table);
+            return Ok(());
+        }
+        let start = self.vpn_range.start();
+        for vpn in self.vpn_range.step_by(self.page_size.pages()) {
+            if let Err(e) = self.map_one(page_table, vpn) {
+                self.unmap_pages(page_table, start, vpn);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+    
+    /// Unmap the pages in [start, end) that map() or append_to() already mapped
+    fn unmap_pages(&mut self, page_table: &mut PageTable, start: VirtPageNum, end: VirtPageNum) {
+        let step = match self.map_type {
+            MapType::Framed => self.page_size.pages(),
+            MapType::Identical => 1,
+        };
+        for vpn in VPNRange::new(start, end).step_by(step) {
+            self.unmap_one(page_table, vpn);
+        }
+    }
+    
+    /// Identity-map this area with the largest leaves that fit
+    fn map_identical_huge(&mut self, page_table: &mut PageTable) {
+        let mut vpn = self.vpn_range.start();
+        let end = self.vpn_range.end();
+        while vpn < end {
+            let ppn = PhysPageNum::new(vpn.0);
+            let size = PageSize::largest_fitting(vpn, ppn, end.0 - vpn.0, self.page_size);
+            let mapped = if size == PageSize::Size4K {
+                self.map_one(page_table, vpn)
+            } else {
+                page_table.map_page(vpn, ppn, self.pte_flags(), size)
+            };
+            if let Err(e) = mapped {
+                panic!("Failed to map page: {}", e);
+            }
+            vpn = vpn + size.pages();
+        }
+    }
+    
+    /// Check whether pages of this area may be swapped out
+    ///
+    /// Only private 4KB user pages are; shared frames stay resident.
+    fn swappable(&self) -> bool {
+        self.map_type == MapType::Framed
+            && self.page_size == PageSize::Size4K
+            && !self.shared
+            && self.map_perm.contains(MapPermission::U)
+    }
+    
+    /// Check whether `vpn` is swapped out
+    fn is_swapped_out(&self, vpn: VirtPageNum) -> bool {
+        self.swap_slots.contains_key(&vpn) && !self.data_frames.contains_key(&vpn)
+    }
+    
+    /// Clock step for one page: give a recently used page a second chance,
+    /// otherwise write it to swap and release its frame
+    ///
+    /// A clean page that still has a valid swap copy is dropped without I/O.
+    /// Frames shared copy-on-write are skipped, since evicting them from one
+    /// address space would not free them.
+    ///
+    /// # Returns
+    /// * `true` if the frame was released (the caller must flush the TLB)
+    fn swap_out(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let ppn = match self.data_frames.get(&vpn) {
+            Some(frame) if Arc::strong_count(frame) == 1 => frame.ppn,
+            _ => return false,
+        };
+        let pte = match unsafe { page_table.get_pte_mut(vpn) } {
+            Some(pte) if pte.is_valid() && pte.flags().contains(PTEFlags::U) => pte,
+            _ => return false,
+        };
+        let flags = pte.flags();
+        if flags.contains(PTEFlags::A) {
+            *pte = PageTableEntry::new_with_ppn(ppn, flags.difference(PTEFlags::A));
+            return false;
         }
+        
+        let saved_flags = flags.difference(PTEFlags::A | PTEFlags::D);
+        let slot = match self.swap_slots.get(&vpn) {
+            Some(entry) if !flags.contains(PTEFlags::D) => Arc::clone(&entry.slot),
+            Some(entry) => {
+                if !entry.slot.write(ppn) {
+                    return false;
+                }
+                Arc::clone(&entry.slot)
+            }
+            None => match SwapSlot::alloc() {
+                Some(slot) if slot.write(ppn) => slot,
+                _ => return false,
+            },
+        };
+        pte.clear();
+        self.swap_slots.insert(vpn, SwapEntry { slot, flags: saved_flags });
+        self.remove_frame(vpn);
+        true
+    }
+    
+    /// Read a swapped out page back into a new frame and map it
+    ///
+    /// The swap copy is kept while the page is clean, unless another address
+    /// space still refers to the slot.
+    fn swap_in(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), FaultError> {
+        let entry = self.swap_slots.get(&vpn).ok_or(FaultError::AccessViolation)?;
+        let frame = match FRAME_ALLOCATOR.alloc() {
+            Some(ppn) => FrameTracker::new_ref(ppn),
+            None => return Err(FaultError::OutOfMemory),
+        };
+        if !entry.slot.read(frame.ppn) {
+            return Err(FaultError::AccessViolation);
+        }
+        // Only a missing page table frame can make this fail
+        if page_table.map(vpn, frame.ppn, entry.flags).is_err() {
+            return Err(FaultError::OutOfMemory);
+        }
+        if Arc::strong_count(&entry.slot) > 1 {
+            self.swap_slots.remove(&vpn);
+        }
+        self.insert_frame(vpn, frame);
+        Ok(())
     }
     
     /// Unmap all pages in this area
     pub fn unmap(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
+        self.unmap_pages(page_table, self.vpn_range.start(), self.vpn_range.end());
+    }
+    
+    /// Split this area at `at`, keeping [start, at) and returning [at, end)
+    ///
+    /// Frames of the pages at or above `at` move to the returned area; the page
+    /// table is left untouched. `at` must not cut a huge page.
+    pub fn split_off(&mut self, at: VirtPageNum) -> MapArea {
+        let data_frames = self.data_frames.split_off(&at);
+        let resident_pages = data_frames.values().map(|frame| frame.size.pages()).sum();
+        self.resident_pages -= resident_pages;
+        let tail = MapArea {
+            vpn_range: VPNRange::new(at, self.vpn_range.end()),
+            actual_start_va: at.addr().0,
+            data_frames,
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            lazy: self.lazy,
+            shared: self.shared,
+            page_size: self.page_size,
+            swap_slots: self.swap_slots.split_off(&at),
+            resident_pages,
+            grows_down: false,
+            no_exec: self.no_exec,
+        };
+        self.vpn_range = VPNRange::new(self.vpn_range.start(), at);
+        tail
+    }
+    
+    /// Shrink this area so that it ends at `new_end` (exclusive)
+    ///
+    /// Pages in [new_end, end) are unmapped and their frames released.
+    pub fn shrink_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        for vpn in VPNRange::new(new_end, self.vpn_range.end()) {
             self.unmap_one(page_table, vpn);
         }
+        self.vpn_range = VPNRange::new(self.vpn_range.start(), new_end);
+    }
+    
+    /// Grow this area so that it ends at `new_end` (exclusive)
+    ///
+    /// Pages in [end, new_end) are mapped right away unless the area is lazy.
+    /// If memory runs out, the area is left as it was.
+    pub fn append_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) -> Result<(), &'static str> {
+        let old_end = self.vpn_range.end();
+        if !self.lazy {
+            for vpn in VPNRange::new(old_end, new_end) {
+                if let Err(e) = self.map_one(page_table, vpn) {
+                    self.unmap_pages(page_table, old_end, vpn);
+                    return Err(e);
+                }
+            }
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.start(), new_end);
+        Ok(())
+    }
+    
+    /// Grow this lazy area so that it starts at `new_start`
+    ///
+    /// Nothing is mapped; the new pages are populated on first touch.
+    pub fn prepend_to(&mut self, new_start: VirtPageNum) {
+        assert!(self.lazy && new_start <= self.vpn_range.start());
+        self.vpn_range = VPNRange::new(new_start, self.vpn_range.end());
+        self.actual_start_va = new_start.addr().0;
     }
     
     /// Copy data to this area
@@ -333,10 +764,9 @@ impl MapArea {
             }
 
             // Additional check: ensure physical address is in user space range
-            // User space physical pages should be >= KERNEL_HEAP_START
-            use crate::config::memory_layout::KERNEL_HEAP_START;
-            if kernel_va < KERNEL_HEAP_START {
-                panic!("Physical address below KERNEL_HEAP_START! This should not happen for user space pages.");
+            // User space physical pages come from the frame allocator, which starts after ekernel
+            if kernel_va < ekernel_addr {
+                panic!("Physical address below ekernel! This should not happen for user space pages.");
             }
 
             // Calculate offset within the page: actual segment start VA - page start VA
@@ -367,6 +797,16 @@ impl MapArea {
     }
 }
 
+/// Memory usage of an address space, as reported by sys_mem_stats
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct MemStats {
+    /// Resident set size (RSS) in 4KB pages
+    pub resident_pages: usize,
+    /// Virtual size (VSZ) in 4KB pages
+    pub virtual_pages: usize,
+}
+
 /// Memory set
 /// 
 /// - Page table root should be allocated from frame allocator
@@ -376,14 +816,23 @@ pub struct MemorySet {
     /// Physical page number of the page table root
     /// The page table is stored in this physical frame
     root_ppn: PhysPageNum,
-    areas: Vec<MapArea>,
+    areas: Vec<AreaBox>,
+    /// ASID tagging this address space's TLB entries
+    asid: Asid,
+    /// Start of the region searched for mmap addresses
+    mmap_base: usize,
 }
 
 impl MemorySet {
     /// Create a new empty memory set
     pub fn new_bare() -> Self {
+        Self::try_new_bare().expect("Failed to allocate frame for page table root")
+    }
+    
+    /// Create a new empty memory set (None if no frame is left for the root table)
+    pub fn try_new_bare() -> Option<Self> {
         // Allocate a physical frame for page table root
-        let root_ppn = FRAME_ALLOCATOR.alloc().expect("Failed to allocate frame for page table root");
+        let root_ppn = FRAME_ALLOCATOR.alloc()?;
         
         // Get pointer to the physical frame (kernel uses identity mapping, so PA == VA)
         let page_table_ptr = root_ppn.as_ptr::<PageTable>();
@@ -393,10 +842,12 @@ impl MemorySet {
             (*page_table_ptr).clear();
         }
         
-        Self {
+        Some(Self {
             root_ppn,
             areas: Vec::new(),
-        }
+            asid: Asid::new(),
+            mmap_base: MMAP_START,
+        })
     }
     
     /// Create kernel memory set with identity mapping
@@ -415,6 +866,7 @@ impl MemorySet {
         }
         
         let mut memory_set = Self::new_bare();
+        memory_set.asid = Asid::kernel();
 
         // Map physical memory from MEMORY_START to stext (for bootloader and early init)
         use crate::config::memory_layout::MEMORY_START;
@@ -532,27 +984,60 @@ impl MemorySet {
                 MMIO_END,
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
-            ),
+            )
+            .with_page_size(PageSize::Size1G),
             None,
         );
         
+        // Map device registers outside the fixed MMIO window (UART, virtio-mmio, RTC, ...)
+        for device in crate::fdt::machine_info().devices() {
+            let start = align_down(device.base);
+            let end = align_up(device.base + device.size);
+            if MMIO_START <= start && end <= MMIO_END {
+                continue;
+            }
+            memory_set.push(
+                MapArea::new(start, end, MapType::Identical, MapPermission::R | MapPermission::W)
+                    .with_page_size(PageSize::Size1G),
+                None,
+            );
+        }
+        
         // Map physical memory (RW-)
-        // Ensure we map from ekernel to MEMORY_END, which includes the heap region
-        // Heap starts at KERNEL_HEAP_START (0x8042_0000) and ends at KERNEL_HEAP_END
-        // We need to map from ekernel (which should be <= KERNEL_HEAP_START) to MEMORY_END
+        // Ensure we map from ekernel to the end of RAM: the frame allocator and
+        // the kernel heap (carved from it) live there
+        // Huge pages are used wherever alignment allows, which keeps the kernel
+        // page table small and the TLB footprint low
         let phys_mem_start = ekernel as *const () as usize;
         // Align to page boundary
         let phys_mem_start_aligned = phys_mem_start & !(PAGE_SIZE - 1);
         memory_set.push(
             MapArea::new(
                 phys_mem_start_aligned,
-                MEMORY_END,
+                super::phys_memory_end(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
-            ),
+            )
+            .with_page_size(PageSize::Size1G),
             None,
         );
         
+        // Map RAM regions other than the kernel's (frame allocator memory)
+        for region in crate::fdt::machine_info().memory_regions() {
+            if region.start > phys_mem_start_aligned {
+                memory_set.push(
+                    MapArea::new(
+                        region.start,
+                        region.end,
+                        MapType::Identical,
+                        MapPermission::R | MapPermission::W,
+                    )
+                    .with_page_size(PageSize::Size1G),
+                    None,
+                );
+            }
+        }
+        
         // Map trampoline and kernel stacks in kernel address space
         // Kernel stacks are allocated below TRAP_CONTEXT
         // We need to map a region that includes:
@@ -562,31 +1047,21 @@ impl MemorySet {
         let trampoline_phys = strampoline as *const () as usize;
         let trampoline_virt = TRAMPOLINE;
         
-        // Map kernel stacks region (RW-)
-        // Kernel stacks are allocated from TRAP_CONTEXT going down
-        // We need to map enough space for multiple kernel stacks
-        use crate::config::memory_layout::KERNEL_STACK_SIZE;
-        const MAX_KERNEL_STACKS: usize = 16;
-        let kernel_stack_region_size = MAX_KERNEL_STACKS * (KERNEL_STACK_SIZE + PAGE_SIZE);
-        // Use wrapping_sub to avoid overflow check (TRAP_CONTEXT is near usize::MAX)
-        let kernel_stack_region_start = TRAP_CONTEXT.wrapping_sub(kernel_stack_region_size);
-        // Align down to page boundary
-        let kernel_stack_region_start_aligned = kernel_stack_region_start & !(PAGE_SIZE - 1);
-        
-        // CRITICAL FIX: Avoid overflow by mapping regions separately
-        // Problem: TRAP_CONTEXT.wrapping_add(2*PAGE_SIZE) overflows to 0x0, causing empty mapping
-        // Solution: Map each region separately
-        
-        // 1. Map kernel stacks region (RW-) - from start to TRAP_CONTEXT (excluding TRAP_CONTEXT page)
-        memory_set.push(
-            MapArea::new(
-                kernel_stack_region_start_aligned,
-                TRAP_CONTEXT, // Exclude TRAP_CONTEXT page itself
-                MapType::Framed,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
+        // 1. Map one kernel stack per task slot (RW-)
+        // Each stack is its own area, so the guard page below it stays unmapped
+        // and an overflow faults instead of running into the next stack
+        for pid in 0..crate::config::MAX_APP_NUM {
+            let (kernel_stack_bottom, kernel_stack_top) = crate::task::kernel_stack_position(pid);
+            memory_set.push(
+                MapArea::new(
+                    kernel_stack_bottom,
+                    kernel_stack_top,
+                    MapType::Framed,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            );
+        }
         
         // 2. Map TRAP_CONTEXT page (RW-) - one page for trap context storage
         memory_set.push(
@@ -620,19 +1095,34 @@ impl MemorySet {
     }
     
     /// Push a map area into memory set
-    pub fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(self.page_table_mut());
+    ///
+    /// Panics if memory runs out; used while building address spaces that
+    /// cannot do without the area (kernel space, ELF images).
+    pub fn push(&mut self, map_area: MapArea, data: Option<&[u8]>) {
+        if let Err(e) = self.try_push(map_area, data) {
+            panic!("Failed to map area: {}", e);
+        }
+    }
+    
+    /// Push a map area into memory set, failing if memory runs out
+    ///
+    /// Nothing is mapped if an error is returned.
+    pub fn try_push(&mut self, map_area: MapArea, data: Option<&[u8]>) -> Result<(), &'static str> {
+        let mut map_area =
+            Box::try_new_in(map_area, MAP_AREA_CACHE.allocator()).map_err(|_| "Out of memory")?;
+        map_area.map(self.page_table_mut())?;
         
         if let Some(data) = data {
             map_area.copy_data(self.page_table(), data);
         }
         
         self.areas.push(map_area);
+        Ok(())
     }
     
     /// Activate this memory set (write satp register)
     pub fn activate(&self) {
-        let satp = self.root_ppn.as_usize() | (8usize << 60);
+        let satp = paging_mode().satp(self.root_ppn);
         unsafe {
             asm!("csrw satp, {}", in(reg) satp);
             asm!("sfence.vma");
@@ -665,8 +1155,27 @@ impl MemorySet {
     }
     
     /// Get page table token (satp value)
+    ///
+    /// Carries the ASID this address space last ran with, which may belong to
+    /// an old generation; use asid_token() when about to enter the address space.
     pub fn token(&self) -> usize {
-        self.root_ppn.as_usize() | (8usize << 60)
+        let asid = self.asid.current().unwrap_or(0);
+        asid::satp_with_asid(paging_mode().satp(self.root_ppn), asid)
+    }
+    
+    /// Get the satp value to run this address space with
+    ///
+    /// Assigns an ASID of the current generation if needed, so this must only
+    /// be called for the address space that is about to be entered.
+    pub fn asid_token(&self) -> usize {
+        let asid = self.asid.activate();
+        asid::satp_with_asid(paging_mode().satp(self.root_ppn), asid)
+    }
+    
+    /// Drop stale TLB entries of this address space after its page table
+    /// changed, for one page (`Some(va)`) or all of it (`None`)
+    pub fn flush_tlb(&self, va: Option<usize>) {
+        self.asid.flush(va);
     }
     
     /// Remove a map area from memory set
@@ -680,6 +1189,255 @@ impl MemorySet {
         }
     }
     
+    /// Check whether [start_vpn, end_vpn) starts or ends inside a huge page
+    fn cuts_huge_page(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        self.areas.iter().any(|area| {
+            let pages = area.page_size.pages();
+            area.map_type == MapType::Framed
+                && pages > 1
+                && [start_vpn, end_vpn]
+                    .iter()
+                    .any(|&vpn| area.contains(vpn) && vpn.0 % pages != 0)
+        })
+    }
+    
+    /// Split the areas crossing the boundaries of [start_vpn, end_vpn), so that
+    /// every area lies either entirely inside or entirely outside the range
+    fn split_at_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        let mut i = 0;
+        while i < self.areas.len() {
+            let area = &mut self.areas[i];
+            let (start, end) = (area.vpn_range.start(), area.vpn_range.end());
+            // The tail is visited in the next iteration and may be split again
+            if start < start_vpn && start_vpn < end {
+                let tail = area.split_off(start_vpn);
+                self.areas.insert(i + 1, Box::new_in(tail, MAP_AREA_CACHE.allocator()));
+            } else if start < end_vpn && end_vpn < end {
+                let tail = area.split_off(end_vpn);
+                self.areas.insert(i + 1, Box::new_in(tail, MAP_AREA_CACHE.allocator()));
+            }
+            i += 1;
+        }
+    }
+    
+    /// Unmap all user pages in [start_va, end_va), like POSIX munmap
+    ///
+    /// Areas that only partly overlap the range are split, so only the pages
+    /// inside the range lose their mapping and frames. Unmapping a range that
+    /// holds no mapping at all succeeds.
+    ///
+    /// # Returns
+    /// * `false` if the range touches a kernel-only area (e.g. the trap context)
+    ///   or cuts a huge page
+    pub fn unmap_range(&mut self, start_va: usize, end_va: usize) -> bool {
+        let start_vpn = VirtAddr::new(start_va).page_number();
+        let end_vpn = VirtAddr::new(align_up(end_va)).page_number();
+        let overlaps = |area: &MapArea| {
+            area.vpn_range.start() < area.vpn_range.end()
+                && area.vpn_range.start() < end_vpn
+                && start_vpn < area.vpn_range.end()
+        };
+        if self
+            .areas
+            .iter()
+            .any(|area| overlaps(area) && !area.map_perm.contains(MapPermission::U))
+        {
+            return false;
+        }
+        if self.cuts_huge_page(start_vpn, end_vpn) {
+            return false;
+        }
+        
+        self.split_at_range(start_vpn, end_vpn);
+        let mut i = 0;
+        while i < self.areas.len() {
+            if overlaps(&self.areas[i]) {
+                self.remove_area(i);
+            } else {
+                i += 1;
+            }
+        }
+        self.flush_tlb(None);
+        true
+    }
+    
+    /// Change the permission of all user pages in [start_va, end_va), like POSIX mprotect
+    ///
+    /// Areas that only partly overlap the range are split. Present pages get
+    /// their PTE flags rewritten and the TLB is flushed; pages still shared
+    /// copy-on-write stay read-only, so the next store copies them.
+    ///
+    /// # Arguments
+    /// * `perm` - New permission (U is implied)
+    ///
+    /// # Returns
+    /// * `false` if some page of the range is not covered by a user area, the
+    ///   range cuts a huge page, or it would make a stack or heap executable
+    ///   while W^X is enforced
+    pub fn protect_range(&mut self, start_va: usize, end_va: usize, perm: MapPermission) -> bool {
+        let start_vpn = VirtAddr::new(start_va).page_number();
+        let end_vpn = VirtAddr::new(align_up(end_va)).page_number();
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            let covered = self
+                .areas
+                .iter()
+                .any(|area| area.map_perm.contains(MapPermission::U) && area.contains(vpn));
+            if !covered {
+                return false;
+            }
+        }
+        if perm.contains(MapPermission::X) && w_xor_x() {
+            let makes_data_executable = self.areas.iter().any(|area| {
+                area.no_exec && area.vpn_range.start() < end_vpn && start_vpn < area.vpn_range.end()
+            });
+            if makes_data_executable {
+                return false;
+            }
+        }
+        if self.cuts_huge_page(start_vpn, end_vpn) {
+            return false;
+        }
+        
+        self.split_at_range(start_vpn, end_vpn);
+        let root_ppn = self.root_ppn;
+        let page_table = unsafe { &mut *root_ppn.as_ptr::<PageTable>() };
+        for area in self.areas.iter_mut() {
+            let (start, end) = (area.vpn_range.start(), area.vpn_range.end());
+            if start == end || start < start_vpn || end_vpn < end {
+                continue;
+            }
+            area.map_perm = perm | MapPermission::U;
+            let flags = area.pte_flags();
+            for (&vpn, frame) in area.data_frames.iter() {
+                let flags = if !area.shared && Arc::strong_count(frame) > 1 {
+                    flags.difference(PTEFlags::W)
+                } else {
+                    flags
+                };
+                unsafe {
+                    if let Some(pte) = page_table.get_pte_mut(vpn) {
+                        if pte.is_valid() {
+                            // Keep A/D: the dirty bit decides whether a swap copy is stale
+                            let used = pte.flags().intersection(PTEFlags::A | PTEFlags::D);
+                            *pte = PageTableEntry::new_with_ppn(frame.ppn, flags | used);
+                        }
+                    }
+                }
+            }
+            for (vpn, entry) in area.swap_slots.iter_mut() {
+                if !area.data_frames.contains_key(vpn) {
+                    entry.flags = flags;
+                }
+            }
+        }
+        
+        // Stale translations may still grant the old rights
+        self.flush_tlb(None);
+        true
+    }
+    
+    /// Shrink the area starting at `start_va` so that it ends at `new_end_va`
+    ///
+    /// # Returns
+    /// * `false` if no area starts at `start_va` or `new_end_va` lies outside it
+    pub fn shrink_to(&mut self, start_va: usize, new_end_va: usize) -> bool {
+        let start_vpn = VirtAddr::new(start_va).page_number();
+        let new_end_vpn = VirtAddr::new(align_up(new_end_va)).page_number();
+        let root_ppn = self.root_ppn;
+        match self.areas.iter_mut().find(|area| area.vpn_range.start() == start_vpn) {
+            Some(area) if start_vpn <= new_end_vpn && new_end_vpn <= area.vpn_range.end() => {
+                let page_table = unsafe { &mut *root_ppn.as_ptr::<PageTable>() };
+                area.shrink_to(page_table, new_end_vpn);
+                self.flush_tlb(None);
+                true
+            }
+            _ => false,
+        }
+    }
+    
+    /// Grow the area starting at `start_va` so that it ends at `new_end_va`
+    ///
+    /// # Returns
+    /// * `Err` if no area starts at `start_va`, the grown part would overlap
+    ///   another area, or memory runs out
+    pub fn append_to(&mut self, start_va: usize, new_end_va: usize) -> Result<(), &'static str> {
+        let start_vpn = VirtAddr::new(start_va).page_number();
+        let new_end_vpn = VirtAddr::new(align_up(new_end_va)).page_number();
+        let index = self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.start() == start_vpn)
+            .ok_or("No area at start address")?;
+        let old_end_vpn = self.areas[index].vpn_range.end();
+        if new_end_vpn < old_end_vpn {
+            return Err("New end below current end");
+        }
+        let overlaps = self.areas.iter().enumerate().any(|(i, area)| {
+            i != index
+                && area.vpn_range.start() < new_end_vpn
+                && old_end_vpn < area.vpn_range.end()
+        });
+        if overlaps {
+            return Err("Overlaps another area");
+        }
+        let root_ppn = self.root_ppn;
+        let page_table = unsafe { &mut *root_ppn.as_ptr::<PageTable>() };
+        self.areas[index].append_to(page_table, new_end_vpn)
+    }
+    
+    /// Lowest page the stack area at `index` may grow down to
+    ///
+    /// One unmapped guard page is always kept above the closest area below
+    /// the stack, so running off the stack faults instead of corrupting it.
+    fn stack_floor(&self, index: usize) -> usize {
+        let start = self.areas[index].vpn_range.start();
+        let below = self
+            .areas
+            .iter()
+            .map(|area| area.vpn_range.end())
+            .filter(|&end| end <= start)
+            .max()
+            .map_or(0, |end| end.0);
+        below + 1
+    }
+    
+    /// Grow the user stack down so that it covers `va`
+    ///
+    /// The stack may reach `max_size` bytes below its top and grow by at
+    /// most `max_growth` bytes at once; the new pages are populated on first touch.
+    ///
+    /// # Returns
+    /// * `false` if `va` is not below the stack or the stack cannot grow that far
+    pub fn grow_stack(&mut self, va: usize, max_size: usize, max_growth: usize) -> bool {
+        let vpn = VirtAddr::new(va).page_number();
+        let index = match self.areas.iter().position(|area| area.grows_down()) {
+            Some(index) => index,
+            None => return false,
+        };
+        let range = &self.areas[index].vpn_range;
+        if vpn >= range.start()
+            || (range.end().0 - vpn.0) * PAGE_SIZE > max_size
+            || (range.start().0 - vpn.0) * PAGE_SIZE > max_growth
+            || vpn.0 < self.stack_floor(index)
+        {
+            return false;
+        }
+        self.areas[index].prepend_to(vpn);
+        true
+    }
+    
+    /// Check if `va` lies in the gap below the user stack, i.e. in its guard
+    /// page or in space it was not allowed to grow into
+    pub fn is_stack_overflow(&self, va: usize) -> bool {
+        let vpn = VirtAddr::new(va).page_number();
+        match self.areas.iter().position(|area| area.grows_down()) {
+            Some(index) => {
+                vpn < self.areas[index].vpn_range.start() && vpn.0 + 1 >= self.stack_floor(index)
+            }
+            None => false,
+        }
+    }
+    
     /// Clear all map areas (unmap all pages)
     /// This is used when destroying an address space
     pub fn clear_areas(&mut self) {
@@ -703,22 +1461,67 @@ impl MemorySet {
         unsafe { &mut *self.root_ppn.as_ptr::<PageTable>() }
     }
     
+    /// Start of the region searched for mmap addresses (randomized with ASLR)
+    pub fn mmap_base(&self) -> usize {
+        self.mmap_base
+    }
+    
+    /// Find the area containing `va`
+    pub fn find_area(&self, va: usize) -> Option<&MapArea> {
+        let vpn = VirtAddr::new(va).page_number();
+        self.areas.iter().find(|area| area.contains(vpn)).map(|area| &**area)
+    }
+    
     /// Get a reference to the areas vector (for inspection)
-    pub fn areas(&self) -> &Vec<MapArea> {
+    pub fn areas(&self) -> &Vec<AreaBox> {
         &self.areas
     }
     
+    /// Resident set size: 4KB pages backed by frames in this address space
+    ///
+    /// Kept up to date by the areas as pages are mapped, unmapped, swapped and
+    /// copied. Frames shared with other address spaces count in each of them.
+    pub fn resident_pages(&self) -> usize {
+        self.areas.iter().map(|area| area.resident_pages()).sum()
+    }
+    
+    /// Virtual size: 4KB pages covered by the areas of this address space,
+    /// whether populated or not
+    pub fn virtual_pages(&self) -> usize {
+        self.areas
+            .iter()
+            .map(|area| area.vpn_range.end().0 - area.vpn_range.start().0)
+            .sum()
+    }
+    
+    /// Snapshot of the memory usage counters
+    pub fn stats(&self) -> MemStats {
+        MemStats {
+            resident_pages: self.resident_pages(),
+            virtual_pages: self.virtual_pages(),
+        }
+    }
+    
     /// Clone this memory set (for fork system call)
     /// Creates a new address space with the same mappings
-    /// This is a deep copy: all pages are copied to new physical frames
-    /// 
-    /// # Note
-    /// This is a simplified version. A full implementation would:
-    /// 1. Use Copy-on-Write (COW) for shared pages
-    /// 2. Only copy pages that are actually modified
-    /// 3. Share read-only pages between parent and child
-    pub fn clone(&self) -> Self {
-        let mut new_memory_set = Self::new_bare();
+    ///
+    /// User pages are shared copy-on-write instead of being copied:
+    /// 1. Both address spaces reference the same frames (FrameRef)
+    /// 2. Writable pages are downgraded to read-only in parent and child
+    /// 3. The first store to such a page faults and is resolved by handle_cow_fault()
+    ///
+    /// Kernel-only framed pages (the trap context) are still copied eagerly,
+    /// since the kernel writes them through their physical address.
+    ///
+    /// # Returns
+    /// * `Err` if memory runs out; the partial copy is released again and the
+    ///   parent keeps working (its COW pages are taken back on the next store)
+    pub fn clone(&mut self) -> Result<Self, &'static str> {
+        let mut new_memory_set = Self::try_new_bare().ok_or("Out of memory")?;
+        new_memory_set.mmap_base = self.mmap_base;
+        
+        // The trampoline is not a MapArea, so it has to be mapped explicitly
+        new_memory_set.map_trampoline()?;
         
         // Clone all map areas
         for area in &self.areas {
@@ -729,65 +1532,288 @@ impl MemorySet {
                 area.map_type(),
                 area.map_perm(),
             );
+            new_area.lazy = area.lazy;
+            new_area.shared = area.shared;
+            new_area.grows_down = area.grows_down;
+            new_area.no_exec = area.no_exec;
+            new_area.page_size = area.page_size;
             
-            // Map the new area
-            let page_table = new_memory_set.page_table_mut();
-            new_area.map(page_table);
-            
-            // If it's a framed mapping, copy the data
-            if area.map_type() == MapType::Framed {
-                // Copy data from old pages to new pages
-                // We need to iterate through the VPN range
-                let start_vpn = VirtAddr::new(area.start_va()).page_number();
-                let end_vpn = VirtAddr::new(area.end_va()).page_number();
-                let mut current_vpn = start_vpn;
-                while current_vpn.0 < end_vpn.0 {
-                    // Get source page (from old address space)
-                    let src_page_table = self.page_table();
-                    if let Some((src_ppn, _)) = src_page_table.translate(current_vpn) {
-                        // Get destination page (from new address space)
-                        let dst_page_table = new_memory_set.page_table();
-                        if let Some((dst_ppn, _)) = dst_page_table.translate(current_vpn) {
-                            // Copy page data
-                            let src_ptr = src_ppn.as_ptr::<u8>();
-                            let dst_ptr = dst_ppn.as_ptr::<u8>();
-                            unsafe {
-                                core::ptr::copy_nonoverlapping(src_ptr, dst_ptr, PAGE_SIZE);
+            if area.shared {
+                // Shared mappings keep pointing at the same frames, with the same rights
+                for (&vpn, frame) in area.data_frames.iter() {
+                    if let Some((ppn, flags)) = self.page_table().translate(vpn) {
+                        new_memory_set
+                            .page_table_mut()
+                            .map_page(vpn, ppn, flags, frame.size)?;
+                        new_area.insert_frame(vpn, Arc::clone(frame));
+                    }
+                }
+            } else if area.map_type() == MapType::Framed && area.page_size != PageSize::Size4K {
+                // Huge pages are copied right away instead of being shared copy-on-write
+                for (&vpn, frame) in area.data_frames.iter() {
+                    new_area.map_one(new_memory_set.page_table_mut(), vpn)?;
+                    let copy = &new_area.data_frames[&vpn];
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(
+                            frame.ppn.as_ptr::<u8>(),
+                            copy.ppn.as_ptr::<u8>(),
+                            frame.size.bytes(),
+                        );
+                    }
+                }
+            } else if area.map_type() == MapType::Framed && area.map_perm().contains(MapPermission::U) {
+                // Share every frame this area owns
+                // Pages owned by another area (ELF segments sharing a page) are handled by that area
+                for (&vpn, frame) in area.data_frames.iter() {
+                    let (ppn, flags) = match self.page_table().translate(vpn) {
+                        Some(mapping) => mapping,
+                        None => continue,
+                    };
+                    let cow_flags = flags.difference(PTEFlags::W);
+                    if flags.contains(PTEFlags::W) {
+                        // Parent loses write access too, so neither side sees the other's stores
+                        unsafe {
+                            let root = self.root_ppn.as_ptr::<PageTable>();
+                            if let Some(pte) = (*root).get_pte_mut(vpn) {
+                                *pte = PageTableEntry::new_with_ppn(ppn, cow_flags);
+                            }
+                        }
+                    }
+                    new_memory_set
+                        .page_table_mut()
+                        .map(vpn, ppn, cow_flags)?;
+                    new_area.insert_frame(vpn, Arc::clone(frame));
+                }
+                // Swapped out pages share the slot; each side reads its own copy back
+                for (&vpn, entry) in area.swap_slots.iter() {
+                    if area.is_swapped_out(vpn) {
+                        new_area.swap_slots.insert(vpn, entry.clone());
+                    }
+                }
+            } else {
+                // Map the new area
+                let page_table = new_memory_set.page_table_mut();
+                new_area.map(page_table)?;
+                
+                // If it's a framed mapping, copy the data
+                if area.map_type() == MapType::Framed {
+                    for vpn in area.vpn_range {
+                        // Get source page (from old address space)
+                        if let Some((src_ppn, _)) = self.page_table().translate(vpn) {
+                            // Get destination page (from new address space)
+                            if let Some((dst_ppn, _)) = new_memory_set.page_table().translate(vpn) {
+                                // Copy page data
+                                let src_ptr = src_ppn.as_ptr::<u8>();
+                                let dst_ptr = dst_ppn.as_ptr::<u8>();
+                                unsafe {
+                                    core::ptr::copy_nonoverlapping(src_ptr, dst_ptr, PAGE_SIZE);
+                                }
                             }
                         }
                     }
-                    current_vpn.0 += 1;
                 }
             }
             
+            let new_area =
+                Box::try_new_in(new_area, MAP_AREA_CACHE.allocator()).map_err(|_| "Out of memory")?;
             new_memory_set.areas.push(new_area);
         }
         
-        new_memory_set
+        // The parent may still hold writable translations of COW pages
+        self.flush_tlb(None);
+        Ok(new_memory_set)
     }
     
-    /// Create a user memory set from ELF data
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        use xmas_elf::ElfFile;
+    /// Handle a page fault on a user address
+    ///
+    /// Three kinds of faults are recoverable:
+    /// - Access to a swapped out page: it is read back from swap
+    /// - First touch of a page in a lazy area: a zeroed frame is allocated and mapped
+    /// - Store to a copy-on-write page: see handle_cow_fault()
+    ///
+    /// # Returns
+    /// * `Ok` if the fault was resolved and the access can be retried
+    /// * `Err(AccessViolation)` if the fault is a genuine access violation
+    /// * `Err(OutOfMemory)` if the access is valid but no frame is left; it may
+    ///   be retried once memory was freed
+    pub fn handle_page_fault(&mut self, va: usize, is_write: bool) -> Result<(), FaultError> {
+        let vpn = VirtAddr::new(va).page_number();
+        if self.page_table().translate(vpn).is_some() {
+            // The page is present, so only a store to a COW page can be fixed up
+            if !is_write {
+                return Err(FaultError::AccessViolation);
+            }
+            return self.handle_cow_fault(va);
+        }
         
-        let elf = ElfFile::new(elf_data).expect("Failed to parse ELF");
-        let elf_header = elf.header;
-        let ph_count = elf_header.pt2.ph_count();
+        let root_ppn = self.root_ppn;
+        let page_table = unsafe { &mut *root_ppn.as_ptr::<PageTable>() };
+        if let Some(area) = self.areas.iter_mut().find(|area| area.is_swapped_out(vpn)) {
+            area.swap_in(page_table, vpn)?;
+            self.flush_tlb(Some(va));
+            return Ok(());
+        }
         
-        let mut memory_set = Self::new_bare();
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|area| area.is_lazy() && area.contains(vpn))
+            .ok_or(FaultError::AccessViolation)?;
+        let perm = area.map_perm();
+        if !perm.contains(MapPermission::U)
+            || (is_write && !perm.contains(MapPermission::W))
+            || (!is_write && !perm.contains(MapPermission::R))
+        {
+            return Err(FaultError::AccessViolation);
+        }
         
-        // Map trampoline and trap context
-        // Both need to be accessible from user mode
-        let trampoline_start = TRAMPOLINE;
-        let trap_context_start = TRAP_CONTEXT;
-        let user_stack_bottom = USER_STACK_BOTTOM;
-        let user_stack_top = USER_STACK_TOP;
+        area.map_one(page_table, vpn).map_err(|_| FaultError::OutOfMemory)?;
+        // The hart may have cached the invalid entry
+        self.flush_tlb(Some(va));
+        Ok(())
+    }
+    
+    /// Make sure a user buffer is present (and writable if needed) before the
+    /// kernel accesses it through its physical address
+    ///
+    /// Lazy pages are populated and COW pages are copied as if the user had
+    /// touched them.
+    ///
+    /// # Returns
+    /// * `false` if some page of the buffer is not accessible
+    pub fn prepare_user_access(&mut self, va: usize, len: usize, is_write: bool) -> bool {
+        if len == 0 {
+            return true;
+        }
+        let start_vpn = VirtAddr::new(va).page_number();
+        let end_vpn = VirtAddr::new(va.saturating_add(len - 1)).page_number() + 1;
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            let accessible = match self.page_table().translate(vpn) {
+                Some((_, flags)) if !flags.contains(PTEFlags::U) => false,
+                Some((_, flags)) => {
+                    !is_write
+                        || flags.contains(PTEFlags::W)
+                        || self.handle_cow_fault(vpn.addr().0).is_ok()
+                }
+                None => self.handle_page_fault(vpn.addr().0, is_write).is_ok(),
+            };
+            if !accessible {
+                return false;
+            }
+            if is_write {
+                // Kernel stores through the physical address don't set the dirty bit
+                unsafe {
+                    if let Some(pte) = self.page_table_mut().get_pte_mut(vpn) {
+                        *pte = PageTableEntry::new_with_ppn(pte.ppn(), pte.flags() | PTEFlags::D);
+                    }
+                }
+            }
+        }
+        true
+    }
+    
+    /// Clock sweep over this address space's private pages from `start` on,
+    /// until `want` frames were released (see MapArea::swap_out)
+    ///
+    /// # Returns
+    /// * Frames released, and the page to continue at if the sweep stopped
+    ///   early (None once the whole address space was visited)
+    pub fn swap_out_pages(&mut self, start: VirtPageNum, want: usize) -> (usize, Option<VirtPageNum>) {
+        let root_ppn = self.root_ppn;
+        let page_table = unsafe { &mut *root_ppn.as_ptr::<PageTable>() };
+        let mut order: Vec<usize> = (0..self.areas.len()).collect();
+        order.sort_unstable_by_key(|&i| self.areas[i].vpn_range.start());
         
-        // Map trampoline in user address space (same virtual address as kernel)
-        // Note: TRAMPOLINE is at usize::MAX - PAGE_SIZE + 1, which is the last page
-        // TRAMPOLINE + PAGE_SIZE would overflow to 0, so we can't use MapArea::new()
-        // Instead, we directly map the single page using page_table.map()
-        let trampoline_vpn = super::memory_layout::VirtAddr::new(trampoline_start).page_number();
+        let mut freed = 0;
+        let mut stopped_at = None;
+        'areas: for i in order {
+            let area = &mut self.areas[i];
+            if !area.swappable() {
+                continue;
+            }
+            let pages: Vec<VirtPageNum> = area.data_frames.range(start..).map(|(&vpn, _)| vpn).collect();
+            for vpn in pages {
+                if freed == want {
+                    stopped_at = Some(vpn);
+                    break 'areas;
+                }
+                if area.swap_out(page_table, vpn) {
+                    freed += 1;
+                }
+            }
+        }
+        // Evicted pages and cleared accessed bits must not stay cached
+        self.flush_tlb(None);
+        (freed, stopped_at)
+    }
+    
+    /// Resolve a store fault on a copy-on-write page
+    ///
+    /// # Returns
+    /// * `Ok` if `va` was a COW page and is now writable (the faulting store can be retried)
+    /// * `Err(AccessViolation)` if the fault is a genuine access violation
+    /// * `Err(OutOfMemory)` if no frame is left for the private copy
+    pub fn handle_cow_fault(&mut self, va: usize) -> Result<(), FaultError> {
+        let vpn = VirtAddr::new(va).page_number();
+        let (ppn, flags) = self.page_table().translate(vpn).ok_or(FaultError::AccessViolation)?;
+        if flags.contains(PTEFlags::W) || !flags.contains(PTEFlags::U) {
+            return Err(FaultError::AccessViolation);
+        }
+        
+        // A read-only PTE inside a writable area means the page is shared COW
+        let writable = self.areas.iter().any(|area| {
+            area.map_type() == MapType::Framed
+                && area.map_perm().contains(MapPermission::W)
+                && area.contains(vpn)
+        });
+        if !writable {
+            return Err(FaultError::AccessViolation);
+        }
+        
+        // The owner may be a different area when ELF segments share a page
+        // (huge pages are never shared copy-on-write)
+        let owner = self
+            .areas
+            .iter_mut()
+            .find(|area| area.page_size == PageSize::Size4K && area.data_frames.contains_key(&vpn))
+            .ok_or(FaultError::AccessViolation)?;
+        let frame = owner.data_frames.get(&vpn).unwrap();
+        let new_ppn = if Arc::strong_count(frame) == 1 {
+            // Last reference: take the page over without copying
+            ppn
+        } else {
+            let new_frame = match FRAME_ALLOCATOR.alloc() {
+                Some(frame) => FrameTracker::new_ref(frame),
+                None => return Err(FaultError::OutOfMemory),
+            };
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    ppn.as_ptr::<u8>(),
+                    new_frame.ppn.as_ptr::<u8>(),
+                    PAGE_SIZE,
+                );
+            }
+            let new_ppn = new_frame.ppn;
+            owner.insert_frame(vpn, new_frame);
+            new_ppn
+        };
+        
+        unsafe {
+            if let Some(pte) = self.page_table_mut().get_pte_mut(vpn) {
+                *pte = PageTableEntry::new_with_ppn(new_ppn, flags | PTEFlags::W);
+            }
+        }
+        self.flush_tlb(Some(va));
+        Ok(())
+    }
+    
+    /// Map the shared trampoline page into this (user) address space
+    ///
+    /// Note: TRAMPOLINE is at usize::MAX - PAGE_SIZE + 1, which is the last page
+    /// TRAMPOLINE + PAGE_SIZE would overflow to 0, so we can't use MapArea::new()
+    /// Instead, we directly map the single page using page_table.map()
+    fn map_trampoline(&mut self) -> Result<(), &'static str> {
+        let trampoline_vpn = VirtAddr::new(TRAMPOLINE).page_number();
 
         // Reuse the global trampoline frame (must already be allocated by kernel space)
         let trampoline_frame = {
@@ -804,11 +1830,47 @@ impl MemorySet {
         let pte_flags = PTEFlags::V | PTEFlags::R | PTEFlags::X;
 
         // Map the page directly to the SAME physical frame as kernel space
-        memory_set.page_table_mut().map(trampoline_vpn, trampoline_frame, pte_flags)
-            .expect("Failed to map TRAMPOLINE page");
+        self.page_table_mut().map(trampoline_vpn, trampoline_frame, pte_flags)
+    }
+    
+    /// Create a user memory set from ELF data
+    ///
+    /// Executables (ET_EXEC) are loaded at their link addresses, position
+    /// independent ones (ET_DYN) at PIE_BASE plus a random bias with ASLR.
+    ///
+    /// # Returns
+    /// * The memory set, user stack pointer and entry point
+    /// * `Err` if memory runs out, or ERR_W_XOR_X if a segment is writable and
+    ///   executable under the W^X policy; whatever was mapped so far is released
+    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, usize, usize), &'static str> {
+        use xmas_elf::ElfFile;
+        
+        let elf = ElfFile::new(elf_data).expect("Failed to parse ELF");
+        let elf_header = elf.header;
+        let ph_count = elf_header.pt2.ph_count();
+        
+        let mut memory_set = Self::try_new_bare().ok_or("Out of memory")?;
+        
+        // Pick the layout (every offset is 0 without ASLR)
+        let is_pie = elf_header.pt2.type_().as_type() == xmas_elf::header::Type::SharedObject;
+        let load_bias = match is_pie {
+            true => PIE_BASE + aslr::random_pages(aslr::PIE_RANDOM_PAGES) * PAGE_SIZE,
+            false => 0,
+        };
+        let stack_shift = aslr::random_pages(aslr::STACK_RANDOM_PAGES) * PAGE_SIZE;
+        memory_set.mmap_base = MMAP_START + aslr::random_pages(aslr::MMAP_RANDOM_PAGES) * PAGE_SIZE;
+        
+        // Map trampoline and trap context
+        // Both need to be accessible from user mode
+        let trap_context_start = TRAP_CONTEXT;
+        let user_stack_bottom = USER_STACK_BOTTOM - stack_shift;
+        let user_stack_top = USER_STACK_TOP - stack_shift;
+        
+        // Map trampoline in user address space (same virtual address as kernel)
+        memory_set.map_trampoline()?;
         
         // Map trap context (stored in user address space but accessible from kernel)
-        memory_set.push(
+        memory_set.try_push(
             MapArea::new(
                 trap_context_start,
                 trap_context_start + PAGE_SIZE,
@@ -816,23 +1878,34 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
         
-        // Map user stack
-        memory_set.push(
-            MapArea::new(
+        // Map user stack (pages are allocated on first touch, and it grows down
+        // on faults below it up to RLIMIT_STACK, see grow_stack())
+        memory_set.try_push(
+            MapArea::new_stack(
                 user_stack_bottom,
                 user_stack_top,
-                MapType::Framed,
                 MapPermission::R | MapPermission::W | MapPermission::U,
             ),
             None,
-        );
+        )?;
+        
+        // Map an empty heap right above the user stack; sys_brk grows it on demand
+        memory_set.try_push(
+            MapArea::new_lazy(
+                user_stack_top,
+                user_stack_top,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            )
+            .non_executable(),
+            None,
+        )?;
         
         // Load ELF segments (段页式管理: Segment-based Paging)
         // Each ELF Load segment becomes a MapArea (段), which is then divided into pages (页)
         let mut max_end_vaddr = 0usize;
-        let entry_point = elf.header.pt2.entry_point() as usize;
+        let entry_point = elf.header.pt2.entry_point() as usize + load_bias;
         
         for i in 0..ph_count {
             let ph = elf.program_header(i as u16).unwrap();
@@ -843,7 +1916,7 @@ impl MemorySet {
                 let file_size = ph.file_size();
                 let file_offset = ph.offset();
                 
-                let start_va = vaddr as usize;  // Actual start address (may not be page-aligned)
+                let start_va = vaddr as usize + load_bias;  // Actual start address (may not be page-aligned)
                 // Align to page boundary (段 -> 页的转换)
                 let aligned_start_va = super::memory_layout::align_down(start_va);
                 let end_va = super::memory_layout::align_up(start_va + mem_size as usize);
@@ -868,6 +1941,9 @@ impl MemorySet {
                 if is_execute {
                     perm |= MapPermission::X;
                 }
+                if perm.violates_w_xor_x() {
+                    return Err(ERR_W_XOR_X);
+                }
                 
                 // Extract segment data from ELF file
                 // The ELF file structure is preserved - we just read the segment content
@@ -881,20 +1957,57 @@ impl MemorySet {
                 // Use actual_start_va (vaddr) to preserve segment offset for proper data placement
                 // aligned_start_va is used for page table mapping (must be page-aligned)
                 // MapArea.push() will later create page table entries (页) for all pages in this segment
-                memory_set.push(
+                memory_set.try_push(
                     MapArea::new_with_actual_start(start_va, aligned_start_va, end_va, MapType::Framed, perm),
                     Some(segment_data),
-                );
+                )?;
             }
         }
         
-        let user_sp = USER_STACK_TOP;
-        (memory_set, user_sp, entry_point)
+        if is_pie {
+            memory_set.relocate(&elf, load_bias);
+        }
+        
+        let user_sp = user_stack_top;
+        Ok((memory_set, user_sp, entry_point))
+    }
+    
+    /// Apply the relative relocations of a position independent executable
+    /// loaded at `load_bias`
+    ///
+    /// Static PIEs only carry R_RISCV_RELATIVE entries (*addr = bias + addend);
+    /// other relocation types would need a dynamic linker and are skipped.
+    fn relocate(&self, elf: &xmas_elf::ElfFile, load_bias: usize) {
+        use xmas_elf::sections::{SectionData, ShType};
+        const R_RISCV_RELATIVE: u32 = 3;
+        
+        for section in elf.section_iter() {
+            if section.get_type() != Ok(ShType::Rela) {
+                continue;
+            }
+            let entries = match section.get_data(elf) {
+                Ok(SectionData::Rela64(entries)) => entries,
+                _ => continue,
+            };
+            for entry in entries.iter().filter(|entry| entry.get_type() == R_RISCV_RELATIVE) {
+                let va = entry.get_offset() as usize + load_bias;
+                let value = (entry.get_addend() as usize).wrapping_add(load_bias);
+                match self.translate(va) {
+                    // The ELF segments are mapped eagerly; PA == VA in the kernel
+                    Some(pa) => unsafe { (pa as *mut usize).write_unaligned(value) },
+                    None => println!("[ELF] relocation at 0x{:x} outside the image", va),
+                }
+            }
+        }
     }
 }
 
 // User memory layout constants
 const USER_STACK_BOTTOM: usize = 0x10000000;
+/// Default start of the region searched for mmap addresses
+const MMAP_START: usize = 0x20000000;
+/// Load address of position independent executables (before the ASLR bias)
+const PIE_BASE: usize = 0x10000;
 const USER_STACK_TOP: usize = USER_STACK_BOTTOM + USER_STACK_SIZE;
 const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
@@ -905,15 +2018,16 @@ impl Drop for MemorySet {
     /// 1. All mapped pages (via FrameTracker::drop)
     /// 2. All intermediate page tables
     /// 3. The root page table itself
+    ///
+    /// Also covers address spaces abandoned half-built (e.g. a failed clone)
     fn drop(&mut self) {
         // First, unmap all areas to release physical frames
         // FrameTracker::drop will automatically deallocate frames
         self.clear_areas();
         
-        // Then, deallocate all intermediate page tables
-        // Note: We don't deallocate the root page table itself here,
-        // as it might be allocated on the stack or in a special way
-        // The root page table should be deallocated by the caller if needed
+        // Then, deallocate all intermediate page tables and the root, which
+        // try_new_bare() took from the frame allocator
         self.page_table_mut().dealloc_intermediate_tables();
+        FRAME_ALLOCATOR.dealloc(self.root_ppn);
     }
 }
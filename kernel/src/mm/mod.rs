@@ -3,15 +3,25 @@
 //! This module provides comprehensive memory management functionality:
 //! - Physical memory allocation (frame allocator)
 //! - Virtual memory management (page tables)
+//! - Address space identifiers (ASID) for tagged TLB entries
 //! - Heap allocation
+//! - Slab caches for fixed-size kernel objects
 //! - Memory layout definitions
 //! - Memory set management
+//! - Shared memory objects
+//! - Swapping private pages out to a block device
+//! - Address space layout randomization (ASLR)
 
+pub mod asid;
+pub mod aslr;
 pub mod frame_allocator;
 pub mod heap;
 pub mod memory_layout;
 pub mod memory_set;
 pub mod page_table;
+pub mod shm;
+pub mod slab;
+pub mod swap;
 
 pub use frame_allocator::FRAME_ALLOCATOR;
 #[allow(unused_imports)]
@@ -19,12 +29,13 @@ pub use frame_allocator::FrameAllocator;
 pub use memory_set::MemorySet;
 // Re-export page table types (may be used by other modules)
 #[allow(unused_imports)]
-pub use page_table::{PTEFlags, PageTable, PageTableEntry};
+pub use page_table::{paging_mode, PTEFlags, PageSize, PageTable, PageTableEntry, PagingMode};
 // Re-export commonly used types
 #[allow(unused_imports)]
-pub use memory_set::{MapPermission, MapType};
+pub use memory_set::{FaultError, MapPermission, MapType};
 
-use crate::config::memory_layout::*;
+use memory_layout::{align_up, PhysPageNum};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 use spin::Mutex;
 
@@ -46,28 +57,73 @@ pub fn get_kernel_space_token() -> Option<usize> {
     KERNEL_SPACE_INTERNAL.lock().as_ref().map(|ks| ks.token())
 }
 
+/// End of physical memory, discovered from the device tree in init()
+static PHYS_MEMORY_END: AtomicUsize = AtomicUsize::new(0);
+
+/// End of the physical memory region the kernel runs in
+pub fn phys_memory_end() -> usize {
+    PHYS_MEMORY_END.load(Ordering::Relaxed)
+}
+
 /// Initialize the memory management system
 ///
-/// # Arguments
-/// * `dtb` - Device Tree Blob address to determine available memory
-pub fn init(_dtb: usize) {
-    // Parse DTB to get memory regions (simplified - assume 128MB at 0x80000000)
-    let mem_start = KERNEL_HEAP_START;
-    let mem_end = MEMORY_END;
+/// The device tree must already be parsed (crate::fdt::init), so that the
+/// amount of RAM is known.
+pub fn init() {
+    extern "C" {
+        fn ekernel();
+    }
+    let kernel_end = align_up(ekernel as *const () as usize);
+
+    // Use the memory region the kernel was loaded into
+    let info = crate::fdt::machine_info();
+    let mem_end = match info.memory_end_of(kernel_end) {
+        Some(end) => end,
+        None => panic!(
+            "[MM] no /memory region holding the kernel (ends at {:#x}) in the device tree",
+            kernel_end
+        ),
+    };
+    PHYS_MEMORY_END.store(mem_end, Ordering::Relaxed);
+    let want_sv48 = info.boot_flag("sv48", "sv39").unwrap_or(crate::config::DEFAULT_SV48);
+    let w_xor_x = info.boot_flag("wxorx", "nowxorx").unwrap_or(crate::config::DEFAULT_W_XOR_X);
+    memory_set::set_w_xor_x(w_xor_x);
 
     // Initialize frame allocator (before creating kernel address space)
+    // The kernel's region is managed from the end of the kernel image on; other
+    // regions above the kernel are managed whole (regions below it belong to
+    // the firmware)
     unsafe {
-        frame_allocator::init(mem_start, mem_end);
+        frame_allocator::add_region(kernel_end, mem_end);
+        for region in info.memory_regions() {
+            if region.start > kernel_end {
+                frame_allocator::add_region(region.start, region.end);
+            }
+        }
     }
+    drop(info);
 
     // Initialize heap allocator BEFORE creating kernel address space
     // This is necessary because MemorySet::new_kernel() uses Vec and BTreeMap
     // which require heap allocation
-    // We use KERNEL_HEAP_START as a safe estimate (will be remapped after paging is enabled)
+    // The heap is carved from the frame allocator (identity mapped once paging is enabled)
     unsafe {
         heap::init_heap();
     }
 
+    // The paging mode must be fixed before the first page table is built
+    if want_sv48 {
+        if sv48_supported() {
+            page_table::set_paging_mode(PagingMode::Sv48);
+        } else {
+            println!("[MM] Sv48 not supported, using Sv39");
+        }
+    }
+    println!("[MM] paging mode: {:?}", paging_mode());
+    if memory_set::w_xor_x() {
+        println!("[MM] W^X enforced for user mappings");
+    }
+
     // Create and activate kernel address space
     // This is the critical transition from physical address access to virtual address access
     let kernel_space = MemorySet::new_kernel();
@@ -81,6 +137,7 @@ pub fn init(_dtb: usize) {
         let ks = KERNEL_SPACE_INTERNAL.lock();
         ks.as_ref().unwrap().activate();
     }
+    asid::init();
 
     // Kernel satp is embedded into the trampoline page in MemorySet::new_kernel().
 
@@ -88,6 +145,31 @@ pub fn init(_dtb: usize) {
     verify_address_translation();
 }
 
+/// Check whether the hart implements Sv48
+///
+/// Writing an unsupported mode to satp has no effect, so Sv48 is switched on
+/// briefly with a root table whose single 512GB leaf identity-maps the
+/// kernel, and satp is read back. Runs before paging is enabled.
+fn sv48_supported() -> bool {
+    let root = match FRAME_ALLOCATOR.alloc() {
+        Some(ppn) => ppn,
+        None => return false,
+    };
+    let flags = PTEFlags::V | PTEFlags::R | PTEFlags::W | PTEFlags::X | PTEFlags::A | PTEFlags::D;
+    unsafe {
+        *(*root.as_ptr::<PageTable>()).entry_mut(0) = PageTableEntry::new_with_ppn(PhysPageNum::new(0), flags);
+    }
+    let satp = PagingMode::Sv48.satp(root);
+    let current: usize;
+    unsafe {
+        core::arch::asm!("csrw satp, {}", in(reg) satp);
+        core::arch::asm!("csrr {}, satp", out(reg) current);
+        core::arch::asm!("csrw satp, zero");
+        core::arch::asm!("sfence.vma");
+    }
+    FRAME_ALLOCATOR.dealloc(root);
+    current == satp
+}
 
 /// Verify that address translation is working correctly
 fn verify_address_translation() {
@@ -97,9 +179,9 @@ fn verify_address_translation() {
     let current_satp = satp::read();
 
     // Verify that satp is set (paging is enabled)
-    // Check if mode is Sv39 (value 8 in bits 60-63)
+    // Check that the selected mode (Sv39 = 8, Sv48 = 9) is in bits 60-63
     let mode = (current_satp.bits() >> 60) & 0xF;
-    if mode != 8 {
+    if mode != paging_mode().satp_mode() {
         panic!("Paging mode not enabled");
     }
 
@@ -142,6 +224,15 @@ pub fn test() {
         FRAME_ALLOCATOR.dealloc(frame1);
     }
 
+    // Test contiguous aligned allocation (buddies must merge back on free)
+    let free_before = FRAME_ALLOCATOR.free_frames();
+    if let Some(frames) = FRAME_ALLOCATOR.alloc_contiguous(3, 8) {
+        assert_eq!(frames.as_usize() % 8, 0, "contiguous frames misaligned");
+        assert_eq!(FRAME_ALLOCATOR.free_frames(), free_before - 3);
+        FRAME_ALLOCATOR.dealloc_contiguous(frames, 3);
+    }
+    assert_eq!(FRAME_ALLOCATOR.free_frames(), free_before, "frames leaked");
+
     // Test heap allocation (skip for now to avoid potential issues)
     // Heap allocator should be initialized, but we'll test it separately
     // use alloc::vec::Vec;
@@ -150,6 +241,8 @@ pub fn test() {
     //     v.push(i);
     // }
     // println!("  Heap allocation test: vec = {:?}", v);
-    let _ = FRAME_ALLOCATOR.free_frames();
-    let _ = FRAME_ALLOCATOR.total_frames();
+    assert!(
+        FRAME_ALLOCATOR.free_frames() <= FRAME_ALLOCATOR.total_frames(),
+        "more free frames than managed ones"
+    );
 }
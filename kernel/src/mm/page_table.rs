@@ -1,12 +1,18 @@
 //! Page Table Management
 //!
-//! Implements SV39 page table for RISC-V 64-bit systems.
-//! SV39 uses 3-level page tables with 39-bit virtual addresses.
+//! Implements the RISC-V Sv39 and Sv48 page tables, selected once at boot
+//! (see PagingMode). Sv39 uses 3 levels and 39-bit virtual addresses, Sv48
+//! adds a fourth level for 48-bit virtual addresses; everything above the
+//! walk is the same for both.
+//!
+//! Leaves may sit at any level: besides 4KB pages, level 1 leaves map 2MB
+//! megapages and level 2 leaves map 1GB gigapages (see PageSize).
 
 use super::frame_allocator::FRAME_ALLOCATOR;
 use super::memory_layout::*;
 use crate::config::memory_layout::*;
 use core::fmt::{self, Debug, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use alloc::vec::Vec;
 
 /// Page Table Entry (PTE) flags
@@ -51,6 +57,14 @@ impl PTEFlags {
     pub const fn union(self, other: Self) -> Self {
         Self(self.0 | other.0)
     }
+
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
 }
 
 impl core::ops::BitOr for PTEFlags {
@@ -97,6 +111,125 @@ impl Debug for PTEFlags {
     }
 }
 
+/// Virtual memory scheme used by every page table
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PagingMode {
+    /// 3 levels, 39-bit virtual addresses
+    Sv39,
+    /// 4 levels, 48-bit virtual addresses
+    Sv48,
+}
+
+impl PagingMode {
+    /// Number of page table levels
+    pub const fn levels(self) -> usize {
+        match self {
+            Self::Sv39 => 3,
+            Self::Sv48 => 4,
+        }
+    }
+
+    /// Position of the root level in VirtPageNum::indexes()
+    pub const fn root_index(self) -> usize {
+        4 - self.levels()
+    }
+
+    /// MODE field of satp
+    pub const fn satp_mode(self) -> usize {
+        match self {
+            Self::Sv39 => 8,
+            Self::Sv48 => 9,
+        }
+    }
+
+    /// satp value for the page table rooted at `root_ppn` (ASID 0)
+    pub fn satp(self, root_ppn: PhysPageNum) -> usize {
+        root_ppn.as_usize() | (self.satp_mode() << 60)
+    }
+
+    /// End of the lower (user) half of the address space
+    pub const fn user_space_end(self) -> usize {
+        1 << (PAGE_SIZE_BITS + 9 * self.levels() - 1)
+    }
+}
+
+/// Levels of the active paging mode (3 = Sv39, 4 = Sv48)
+static PAGING_LEVELS: AtomicUsize = AtomicUsize::new(3);
+
+/// Paging mode of all page tables
+pub fn paging_mode() -> PagingMode {
+    match PAGING_LEVELS.load(Ordering::Relaxed) {
+        4 => PagingMode::Sv48,
+        _ => PagingMode::Sv39,
+    }
+}
+
+/// Select the paging mode
+///
+/// Must happen before the first page table is built (see mm::init).
+pub fn set_paging_mode(mode: PagingMode) {
+    PAGING_LEVELS.store(mode.levels(), Ordering::Relaxed);
+}
+
+/// Size of a leaf mapping
+///
+/// A huge page must be aligned to its size both virtually and physically.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PageSize {
+    /// 4KB page (level 0 leaf)
+    Size4K,
+    /// 2MB megapage (level 1 leaf)
+    Size2M,
+    /// 1GB gigapage (level 2 leaf)
+    Size1G,
+}
+
+impl PageSize {
+    /// Number of 4KB pages covered
+    pub const fn pages(self) -> usize {
+        match self {
+            Self::Size4K => 1,
+            Self::Size2M => 512,
+            Self::Size1G => 512 * 512,
+        }
+    }
+
+    /// Size in bytes
+    pub const fn bytes(self) -> usize {
+        self.pages() * PAGE_SIZE
+    }
+
+    /// Position of the leaf's level in VirtPageNum::indexes()
+    const fn leaf_index(self) -> usize {
+        match self {
+            Self::Size4K => 3,
+            Self::Size2M => 2,
+            Self::Size1G => 1,
+        }
+    }
+
+    /// Size of a leaf found at `index` (512GB Sv48 leaves are never created)
+    const fn from_leaf_index(index: usize) -> Self {
+        match index {
+            0 | 1 => Self::Size1G,
+            2 => Self::Size2M,
+            _ => Self::Size4K,
+        }
+    }
+
+    /// Largest size, up to `max`, of a leaf mapping `vpn` to `ppn` that
+    /// is aligned and fits in `remaining` pages
+    pub fn largest_fitting(vpn: VirtPageNum, ppn: PhysPageNum, remaining: usize, max: PageSize) -> Self {
+        [Self::Size1G, Self::Size2M]
+            .into_iter()
+            .filter(|size| size.pages() <= max.pages())
+            .find(|size| {
+                vpn.0 % size.pages() == 0 && ppn.0 % size.pages() == 0 && size.pages() <= remaining
+            })
+            .unwrap_or(Self::Size4K)
+    }
+}
+
 /// Page Table Entry
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -153,7 +286,7 @@ impl Debug for PageTableEntry {
     }
 }
 
-/// Page Table (512 entries for SV39)
+/// Page Table (512 entries at every level)
 #[repr(C)]
 #[repr(align(4096))]
 pub struct PageTable {
@@ -197,11 +330,32 @@ impl PageTable {
         ppn: PhysPageNum,
         flags: PTEFlags,
     ) -> Result<(), &'static str> {
+        self.map_page(vpn, ppn, flags, PageSize::Size4K)
+    }
+
+    /// Map a page of the given size
+    ///
+    /// # Arguments
+    /// * `vpn` - First virtual page number (aligned to `size`)
+    /// * `ppn` - First physical page number (aligned to `size`)
+    /// * `flags` - Page table entry flags
+    /// * `size` - Leaf size (4KB, 2MB or 1GB)
+    pub fn map_page(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+        size: PageSize,
+    ) -> Result<(), &'static str> {
+        if vpn.0 % size.pages() != 0 || ppn.0 % size.pages() != 0 {
+            return Err("Misaligned huge page");
+        }
         let indexes = vpn.indexes();
+        let leaf_index = size.leaf_index();
         let mut current_table = self as *mut PageTable;
 
         // Traverse page table levels
-        for (_level, &index) in indexes.iter().enumerate().take(2) {
+        for &index in indexes.iter().take(leaf_index).skip(paging_mode().root_index()) {
             let entry = unsafe { (*current_table).entry_mut(index) };
 
             if !entry.is_valid() {
@@ -226,8 +380,8 @@ impl PageTable {
             current_table = entry.ppn().as_ptr::<PageTable>();
         }
 
-        // Set the leaf entry
-        let leaf_entry = unsafe { (*current_table).entry_mut(indexes[2]) };
+        // Set the leaf entry (a huge page cannot replace an existing table either)
+        let leaf_entry = unsafe { (*current_table).entry_mut(indexes[leaf_index]) };
         if leaf_entry.is_valid() {
             return Err("Page already mapped");
         }
@@ -236,32 +390,32 @@ impl PageTable {
         Ok(())
     }
 
-    /// Unmap a virtual page
-    pub fn unmap(&mut self, vpn: VirtPageNum) -> Result<PhysPageNum, &'static str> {
+    /// Find the leaf entry mapping `vpn`, at whatever level it is
+    fn find_leaf(&self, vpn: VirtPageNum) -> Option<(*mut PageTableEntry, PageSize)> {
         let indexes = vpn.indexes();
-        let mut current_table = self as *mut PageTable;
-
-        // Traverse page table levels
-        for &index in indexes.iter().take(2) {
-            let entry = unsafe { (*current_table).entry(index) };
-
+        let mut current_table = self as *const PageTable as *mut PageTable;
+        for (level, &index) in indexes.iter().enumerate().skip(paging_mode().root_index()) {
+            let entry = unsafe { (*current_table).entry_mut(index) };
             if !entry.is_valid() {
-                return Err("Page not mapped");
+                return None;
             }
-
-            if entry.is_leaf() {
-                return Err("Encountered leaf PTE in intermediate level");
+            if entry.is_leaf() || level == indexes.len() - 1 {
+                return Some((entry as *mut PageTableEntry, PageSize::from_leaf_index(level)));
             }
-
             current_table = entry.ppn().as_ptr::<PageTable>();
         }
+        None
+    }
 
-        // Clear the leaf entry
-        let leaf_entry = unsafe { (*current_table).entry_mut(indexes[2]) };
-        if !leaf_entry.is_valid() {
-            return Err("Page not mapped");
+    /// Unmap a virtual page
+    ///
+    /// A huge page is unmapped as a whole, and only through its first page.
+    pub fn unmap(&mut self, vpn: VirtPageNum) -> Result<PhysPageNum, &'static str> {
+        let (leaf_entry, size) = self.find_leaf(vpn).ok_or("Page not mapped")?;
+        if vpn.0 % size.pages() != 0 {
+            return Err("Unmapping part of a huge page");
         }
-
+        let leaf_entry = unsafe { &mut *leaf_entry };
         let ppn = leaf_entry.ppn();
         leaf_entry.clear();
         Ok(ppn)
@@ -282,7 +436,12 @@ impl PageTable {
         let mut current_table = self as *const PageTable;
         let mut is_root = true;  // Track if we're accessing root page table
 
-        for &index in indexes.iter().take(2) {
+        for (level, &index) in indexes
+            .iter()
+            .enumerate()
+            .take(indexes.len() - 1)
+            .skip(paging_mode().root_index())
+        {
             // Print root page table contents if debug is enabled and this is the first access
             if debug && is_root {
                 self.print_root_table();
@@ -296,13 +455,16 @@ impl PageTable {
             }
 
             if entry.is_leaf() {
-                return Some((entry.ppn(), entry.flags()));
+                // Huge page: the 4KB page's frame lies at the same offset
+                let size = PageSize::from_leaf_index(level);
+                let ppn = PhysPageNum::new(entry.ppn().0 + vpn.0 % size.pages());
+                return Some((ppn, entry.flags()));
             }
 
             current_table = entry.ppn().as_ptr::<PageTable>();
         }
 
-        let leaf_entry = unsafe { (*current_table).entry(indexes[2]) };
+        let leaf_entry = unsafe { (*current_table).entry(indexes[3]) };
         if leaf_entry.is_valid() {
             Some((leaf_entry.ppn(), leaf_entry.flags()))
         } else {
@@ -310,7 +472,7 @@ impl PageTable {
         }
     }
     
-    /// Print root page table (Level 2) contents for debugging
+    /// Print root page table contents for debugging
     #[allow(dead_code)]
     pub fn print_root_table(&self) {
         // Intentionally left blank; used only during manual debugging.
@@ -323,24 +485,15 @@ impl PageTable {
     
     /// Get a mutable reference to a leaf entry (for modifying flags)
     /// This is unsafe because it bypasses the normal page table traversal
+    ///
+    /// For a page inside a huge page this is the huge page's entry.
     pub unsafe fn get_pte_mut(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
-        let indexes = vpn.indexes();
-        let mut current_table = self as *mut PageTable;
-        
-        // Traverse to the leaf entry
-        for &index in &indexes[..2] {
-            let entry = (*current_table).entry(index);
-            if !entry.is_valid() {
-                return None;
-            }
-            if entry.is_leaf() {
-                return None; // Not a leaf entry
-            }
-            current_table = entry.ppn().as_ptr::<PageTable>();
-        }
-        
-        // Get the leaf entry
-        Some((*current_table).entry_mut(indexes[2]))
+        self.find_leaf(vpn).map(|(entry, _)| &mut *entry)
+    }
+
+    /// Size of the leaf mapping `vpn` (None if unmapped)
+    pub fn leaf_size(&self, vpn: VirtPageNum) -> Option<PageSize> {
+        self.find_leaf(vpn).map(|(_, size)| size)
     }
     
     /// Recursively deallocate this page table and all intermediate page tables
@@ -487,7 +640,7 @@ impl PageTable {
         
         buffers
     }
-    
+
     /// Print page table contents (for debugging)
     #[allow(dead_code)]
     pub fn print_contents(&self, _max_entries: usize) {
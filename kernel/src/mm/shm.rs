@@ -0,0 +1,117 @@
+//! Shared memory objects
+//!
+//! Frames for MAP_SHARED mappings. Anonymous shared mappings get a fresh set
+//! of frames that is inherited across fork; named objects (`shm_open`-like)
+//! live in a global table, so unrelated tasks can map the same frames by name.
+//!
+//! An object holds its frames through `FrameRef`, like the map areas
+//! that map it, so unlinking a name only drops the table's reference: the
+//! frames are freed once the last mapping is gone.
+
+use super::memory_set::{FrameRef, FrameTracker};
+use super::frame_allocator::FRAME_ALLOCATOR;
+use alloc::string::String;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+/// Maximum number of named shared memory objects
+const MAX_SHM_OBJECTS: usize = 16;
+
+/// A named shared memory object
+struct ShmObject {
+    name: String,
+    frames: Vec<FrameRef>,
+}
+
+lazy_static! {
+    /// Named objects; the index is the id returned by shm_open()
+    static ref SHM_OBJECTS: Mutex<Vec<Option<ShmObject>>> = Mutex::new(Vec::new());
+}
+
+/// Allocate `pages` zeroed frames to be shared between address spaces
+///
+/// # Returns
+/// * `None` if physical memory runs out (frames allocated so far are released)
+pub fn alloc_shared_frames(pages: usize) -> Option<Vec<FrameRef>> {
+    let mut frames = Vec::with_capacity(pages);
+    for _ in 0..pages {
+        let ppn = FRAME_ALLOCATOR.alloc()?;
+        frames.push(FrameTracker::new_ref(ppn));
+    }
+    Some(frames)
+}
+
+/// Open a named shared memory object, creating it if requested
+///
+/// Ids are used like file descriptors, so only ids below `max_id` (the
+/// caller's RLIMIT_NOFILE) are handed out.
+///
+/// # Arguments
+/// * `name` - Object name
+/// * `pages` - Size in pages, used only when the object is created
+/// * `create` - Create the object if it does not exist
+/// * `max_id` - Upper bound (exclusive) for the returned id
+///
+/// # Returns
+/// * Object id, or `None` if it does not exist (and may not be created), its
+///   id is not below `max_id`, or memory runs out
+pub fn shm_open(name: &str, pages: usize, create: bool, max_id: usize) -> Option<usize> {
+    let mut objects = SHM_OBJECTS.lock();
+    if let Some(id) = objects
+        .iter()
+        .position(|object| matches!(object, Some(object) if object.name == name))
+    {
+        return if id < max_id { Some(id) } else { None };
+    }
+    if !create || pages == 0 {
+        return None;
+    }
+
+    let id = objects
+        .iter()
+        .position(|object| object.is_none())
+        .unwrap_or(objects.len());
+    if id >= MAX_SHM_OBJECTS.min(max_id) {
+        return None;
+    }
+    let object = ShmObject {
+        name: String::from(name),
+        frames: alloc_shared_frames(pages)?,
+    };
+    if id == objects.len() {
+        objects.push(Some(object));
+    } else {
+        objects[id] = Some(object);
+    }
+    Some(id)
+}
+
+/// Remove a name from the table
+///
+/// Existing mappings keep the frames alive; the name can be reused right away.
+///
+/// # Returns
+/// * `false` if no object has this name
+pub fn shm_unlink(name: &str) -> bool {
+    let mut objects = SHM_OBJECTS.lock();
+    for object in objects.iter_mut() {
+        if matches!(object, Some(shm) if shm.name == name) {
+            *object = None;
+            return true;
+        }
+    }
+    false
+}
+
+/// Frames of object `id` covering `pages` pages from page `first_page` on
+///
+/// # Returns
+/// * `None` if the object does not exist or the range exceeds its size
+pub fn shm_frames(id: usize, first_page: usize, pages: usize) -> Option<Vec<FrameRef>> {
+    let objects = SHM_OBJECTS.lock();
+    let object = objects.get(id)?.as_ref()?;
+    let end_page = first_page.checked_add(pages)?;
+    let frames = object.frames.get(first_page..end_page)?;
+    Some(frames.to_vec())
+}
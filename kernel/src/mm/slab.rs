@@ -0,0 +1,332 @@
+//! Slab Allocator
+//!
+//! Object caches for fixed-size kernel objects that are created and destroyed
+//! at a high rate (task control blocks on fork/exit, frame trackers on every
+//! page fault, map areas on mmap/munmap and fork). Each cache takes whole slabs from the frame allocator and cuts
+//! them into equal slots, so these objects never touch the buddy heap.
+//!
+//! - A slab is a naturally aligned block of frames starting with a
+//!   SlabHeader; the slab of an object is found by masking its address
+//! - Free slots of a slab form an intrusive singly linked list
+//! - Slabs with free slots sit on the cache's doubly linked partial list;
+//!   full slabs are on no list
+//! - One empty slab is kept for reuse, further empty slabs go back to the
+//!   frame allocator
+//! - SlabAllocator plugs a cache into `Box::new_in` / `Arc::new_in`
+
+use super::frame_allocator::FRAME_ALLOCATOR;
+use crate::config::memory_layout::PAGE_SIZE;
+use crate::mm::memory_layout::PhysAddr;
+use crate::mm::memory_set::{FrameTracker, MapArea};
+use crate::task::TaskControlBlock;
+use alloc::alloc::{AllocError, Allocator, Global, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr::{self, NonNull};
+use spin::Mutex;
+
+/// A slab is made large enough to hold at least this many objects
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// Length of the name field in SlabStats
+pub const SLAB_NAME_LEN: usize = 16;
+
+/// Header at the start of every slab
+struct SlabHeader {
+    prev: *mut SlabHeader,
+    next: *mut SlabHeader,
+    /// First free slot (null if the slab is full)
+    free: *mut FreeSlot,
+    /// Number of allocated slots
+    in_use: usize,
+}
+
+/// Free list link, stored in the first bytes of each free slot
+struct FreeSlot {
+    next: *mut FreeSlot,
+}
+
+struct CacheInner {
+    /// Slabs with at least one free slot
+    partial: *mut SlabHeader,
+    /// A completely free slab kept for reuse (not on the partial list)
+    empty: *mut SlabHeader,
+    slabs: usize,
+    active_objects: usize,
+    allocs: usize,
+    frees: usize,
+}
+
+// The raw pointers refer to slab frames owned by the cache
+unsafe impl Send for CacheInner {}
+
+/// Cache of equally sized objects
+pub struct SlabCache {
+    name: &'static str,
+    /// Slot size (object size rounded up to its alignment)
+    object_size: usize,
+    align: usize,
+    /// Frames per slab (power of two)
+    slab_frames: usize,
+    inner: Mutex<CacheInner>,
+}
+
+/// Per-cache statistics, as reported by sys_slab_stats
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SlabStats {
+    /// Cache name, NUL-padded
+    pub name: [u8; SLAB_NAME_LEN],
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    /// Slabs currently held from the frame allocator
+    pub slabs: usize,
+    /// Objects currently allocated
+    pub active_objects: usize,
+    /// Allocations since boot
+    pub allocs: usize,
+    /// Frees since boot
+    pub frees: usize,
+}
+
+impl SlabCache {
+    /// Create an empty cache for objects of `size` bytes aligned to `align`
+    pub const fn new(name: &'static str, size: usize, align: usize) -> Self {
+        // A free slot must hold a FreeSlot link
+        let align = if align > align_of::<FreeSlot>() {
+            align
+        } else {
+            align_of::<FreeSlot>()
+        };
+        let size = if size > size_of::<FreeSlot>() {
+            size
+        } else {
+            size_of::<FreeSlot>()
+        };
+        let object_size = (size + align - 1) & !(align - 1);
+        let mut slab_frames = 1;
+        while slab_frames * PAGE_SIZE < Self::header_size(align) + MIN_OBJECTS_PER_SLAB * object_size {
+            slab_frames *= 2;
+        }
+        Self {
+            name,
+            object_size,
+            align,
+            slab_frames,
+            inner: Mutex::new(CacheInner {
+                partial: ptr::null_mut(),
+                empty: ptr::null_mut(),
+                slabs: 0,
+                active_objects: 0,
+                allocs: 0,
+                frees: 0,
+            }),
+        }
+    }
+
+    /// Bytes reserved for the header, so that slots stay aligned
+    const fn header_size(align: usize) -> usize {
+        (size_of::<SlabHeader>() + align - 1) & !(align - 1)
+    }
+
+    fn slab_bytes(&self) -> usize {
+        self.slab_frames * PAGE_SIZE
+    }
+
+    fn objects_per_slab(&self) -> usize {
+        (self.slab_bytes() - Self::header_size(self.align)) / self.object_size
+    }
+
+    /// Whether an allocation with `layout` fits in one slot
+    pub fn fits(&self, layout: &Layout) -> bool {
+        layout.size() <= self.object_size && layout.align() <= self.align
+    }
+
+    /// Take a slab from the frame allocator and thread its free list
+    fn new_slab(&self) -> Option<*mut SlabHeader> {
+        let ppn = FRAME_ALLOCATOR.alloc_contiguous(self.slab_frames, self.slab_frames)?;
+        let base = ppn.as_addr();
+        let first = base + Self::header_size(self.align);
+        let count = self.objects_per_slab();
+        unsafe {
+            for i in 0..count {
+                let slot = (first + i * self.object_size) as *mut FreeSlot;
+                let next = if i + 1 < count {
+                    (first + (i + 1) * self.object_size) as *mut FreeSlot
+                } else {
+                    ptr::null_mut()
+                };
+                (*slot).next = next;
+            }
+            let slab = base as *mut SlabHeader;
+            *slab = SlabHeader {
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+                free: first as *mut FreeSlot,
+                in_use: 0,
+            };
+            Some(slab)
+        }
+    }
+
+    /// Allocate one object slot (contents are unspecified)
+    pub fn alloc(&self) -> Option<NonNull<u8>> {
+        let mut inner = self.inner.lock();
+        if inner.partial.is_null() {
+            let slab = if !inner.empty.is_null() {
+                core::mem::replace(&mut inner.empty, ptr::null_mut())
+            } else {
+                let slab = self.new_slab()?;
+                inner.slabs += 1;
+                slab
+            };
+            unsafe { inner.push(slab) };
+        }
+
+        let slab = inner.partial;
+        let slot = unsafe {
+            let slot = (*slab).free;
+            (*slab).free = (*slot).next;
+            (*slab).in_use += 1;
+            if (*slab).free.is_null() {
+                inner.unlink(slab);
+            }
+            slot
+        };
+        inner.active_objects += 1;
+        inner.allocs += 1;
+        NonNull::new(slot as *mut u8)
+    }
+
+    /// Return an object slot
+    ///
+    /// # Safety
+    /// `object` must come from alloc() on this cache and must not be used afterwards.
+    pub unsafe fn dealloc(&self, object: NonNull<u8>) {
+        let slot = object.as_ptr() as *mut FreeSlot;
+        let slab = (slot as usize & !(self.slab_bytes() - 1)) as *mut SlabHeader;
+        let mut inner = self.inner.lock();
+        let was_full = (*slab).free.is_null();
+        (*slot).next = (*slab).free;
+        (*slab).free = slot;
+        (*slab).in_use -= 1;
+        inner.active_objects -= 1;
+        inner.frees += 1;
+
+        if (*slab).in_use == 0 {
+            if !was_full {
+                inner.unlink(slab);
+            }
+            if inner.empty.is_null() {
+                inner.empty = slab;
+            } else {
+                inner.slabs -= 1;
+                FRAME_ALLOCATOR
+                    .dealloc_contiguous(PhysAddr::new(slab as usize).page_number(), self.slab_frames);
+            }
+        } else if was_full {
+            inner.push(slab);
+        }
+    }
+
+    /// Allocator handle for `Box::new_in` / `Arc::new_in`
+    pub const fn allocator(&'static self) -> SlabAllocator {
+        SlabAllocator(self)
+    }
+
+    /// Snapshot of this cache's statistics
+    pub fn stats(&self) -> SlabStats {
+        let inner = self.inner.lock();
+        let mut name = [0u8; SLAB_NAME_LEN];
+        let len = self.name.len().min(SLAB_NAME_LEN - 1);
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        SlabStats {
+            name,
+            object_size: self.object_size,
+            objects_per_slab: self.objects_per_slab(),
+            slabs: inner.slabs,
+            active_objects: inner.active_objects,
+            allocs: inner.allocs,
+            frees: inner.frees,
+        }
+    }
+}
+
+impl CacheInner {
+    /// Put a slab at the head of the partial list
+    unsafe fn push(&mut self, slab: *mut SlabHeader) {
+        (*slab).prev = ptr::null_mut();
+        (*slab).next = self.partial;
+        if !self.partial.is_null() {
+            (*self.partial).prev = slab;
+        }
+        self.partial = slab;
+    }
+
+    /// Remove a slab from the partial list
+    unsafe fn unlink(&mut self, slab: *mut SlabHeader) {
+        let SlabHeader { prev, next, .. } = ptr::read(slab);
+        if prev.is_null() {
+            self.partial = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+    }
+}
+
+/// Allocator backed by a slab cache
+///
+/// Layouts that do not fit the cache's slots (e.g. if an `Arc` header turns
+/// out larger than expected) fall back to the kernel heap, so the handle is
+/// always safe to use.
+#[derive(Copy, Clone)]
+pub struct SlabAllocator(&'static SlabCache);
+
+unsafe impl Allocator for SlabAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.0.fits(&layout) {
+            return Global.allocate(layout);
+        }
+        let object = self.0.alloc().ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(object, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.0.fits(&layout) {
+            self.0.dealloc(ptr);
+        } else {
+            Global.deallocate(ptr, layout);
+        }
+    }
+}
+
+/// Strong and weak counts in front of the value of an `Arc`
+const ARC_HEADER: usize = 2 * size_of::<usize>();
+
+/// Task control blocks (boxed in TaskManager)
+pub static TASK_CACHE: SlabCache = SlabCache::new(
+    "task",
+    size_of::<TaskControlBlock>(),
+    align_of::<TaskControlBlock>(),
+);
+
+/// `Arc<FrameTracker>` allocations of map areas and shared memory objects
+pub static FRAME_TRACKER_CACHE: SlabCache = SlabCache::new(
+    "frame_tracker",
+    ARC_HEADER + size_of::<FrameTracker>(),
+    align_of::<usize>(),
+);
+
+/// Map areas (boxed in MemorySet; created by mmap, fork and area splits)
+pub static MAP_AREA_CACHE: SlabCache =
+    SlabCache::new("map_area", size_of::<MapArea>(), align_of::<MapArea>());
+
+/// All kernel object caches, in sys_slab_stats order
+static CACHES: [&SlabCache; 3] = [&TASK_CACHE, &FRAME_TRACKER_CACHE, &MAP_AREA_CACHE];
+
+/// Statistics of the `id`-th cache (None past the last cache)
+pub fn cache_stats(id: usize) -> Option<SlabStats> {
+    CACHES.get(id).map(|cache| cache.stats())
+}
@@ -0,0 +1,167 @@
+//! Swap Space
+//!
+//! Backs evicted user pages with slots on the virtio-blk disk. The whole disk
+//! is the swap area, divided into page-sized slots.
+//!
+//! - Slots are handed out from a bitmap and referenced through `SwapRef`
+//!   (an `Arc<SwapSlot>`), so a slot inherited across fork is freed when the
+//!   last address space drops it
+//! - Which pages to evict is decided by MemorySet::swap_out_pages(), a clock
+//!   sweep over the accessed bits; task::reclaim_frames() moves the hand
+//!   across address spaces
+//! - Evicted pages come back through the page fault handler
+
+use super::memory_layout::{PhysPageNum, VirtPageNum};
+use crate::config::memory_layout::PAGE_SIZE;
+use crate::drivers::virtio_blk::{BLOCK_DEVICE, SECTOR_SIZE};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Largest swap area used (64K slots = 256MB)
+const MAX_SWAP_SLOTS: usize = 0x10000;
+
+/// Reclaim when fewer frames than this are free
+pub const SWAP_LOW_WATERMARK: usize = 64;
+
+/// Frames freed by one reclaim run
+pub const SWAP_BATCH: usize = 32;
+
+const SECTORS_PER_SLOT: usize = PAGE_SIZE / SECTOR_SIZE;
+
+struct SwapSpace {
+    /// One bit per slot, set if the slot is in use
+    bitmap: Vec<u64>,
+    slots: usize,
+    used: usize,
+    /// Pages written out / read back since boot
+    swapped_out: usize,
+    swapped_in: usize,
+}
+
+static SWAP: Mutex<Option<SwapSpace>> = Mutex::new(None);
+
+/// Where the clock sweep continues: (pid, vpn)
+pub static CLOCK_HAND: Mutex<(usize, VirtPageNum)> = Mutex::new((0, VirtPageNum(0)));
+
+/// Use the block device found by drivers::init() as swap area
+pub fn init() {
+    let capacity = match BLOCK_DEVICE.lock().as_ref() {
+        Some(device) => device.capacity() as usize,
+        None => {
+            println!("[Swap] no block device, swapping disabled");
+            return;
+        }
+    };
+    let slots = (capacity / SECTORS_PER_SLOT).min(MAX_SWAP_SLOTS);
+    if slots == 0 {
+        return;
+    }
+    *SWAP.lock() = Some(SwapSpace {
+        bitmap: vec![0; (slots + 63) / 64],
+        slots,
+        used: 0,
+        swapped_out: 0,
+        swapped_in: 0,
+    });
+    println!("[Swap] {} slots ({} KB)", slots, slots * PAGE_SIZE / 1024);
+}
+
+/// Check whether a swap area is available
+pub fn enabled() -> bool {
+    SWAP.lock().is_some()
+}
+
+/// A slot of the swap area, freed when dropped
+pub struct SwapSlot(usize);
+
+/// Reference-counted swap slot (shared by address spaces forked from one another)
+pub type SwapRef = Arc<SwapSlot>;
+
+impl SwapSlot {
+    /// Take a free slot (None if swap is disabled or full)
+    pub fn alloc() -> Option<SwapRef> {
+        let mut swap = SWAP.lock();
+        let swap = swap.as_mut()?;
+        let (word, bits) = swap
+            .bitmap
+            .iter_mut()
+            .enumerate()
+            .find(|(_, bits)| **bits != u64::MAX)?;
+        let bit = bits.trailing_ones() as usize;
+        let slot = word * 64 + bit;
+        if slot >= swap.slots {
+            return None;
+        }
+        *bits |= 1 << bit;
+        swap.used += 1;
+        Some(Arc::new(SwapSlot(slot)))
+    }
+
+    /// Write the frame `ppn` to this slot
+    pub fn write(&self, ppn: PhysPageNum) -> bool {
+        let data = unsafe { core::slice::from_raw_parts(ppn.as_ptr::<u8>(), PAGE_SIZE) };
+        let ok = match BLOCK_DEVICE.lock().as_mut() {
+            Some(device) => device.write((self.0 * SECTORS_PER_SLOT) as u64, data).is_ok(),
+            None => false,
+        };
+        if ok {
+            if let Some(swap) = SWAP.lock().as_mut() {
+                swap.swapped_out += 1;
+            }
+        }
+        ok
+    }
+
+    /// Read this slot into the frame `ppn`
+    pub fn read(&self, ppn: PhysPageNum) -> bool {
+        let data = unsafe { core::slice::from_raw_parts_mut(ppn.as_ptr::<u8>(), PAGE_SIZE) };
+        let ok = match BLOCK_DEVICE.lock().as_mut() {
+            Some(device) => device.read((self.0 * SECTORS_PER_SLOT) as u64, data).is_ok(),
+            None => false,
+        };
+        if ok {
+            if let Some(swap) = SWAP.lock().as_mut() {
+                swap.swapped_in += 1;
+            }
+        }
+        ok
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        if let Some(swap) = SWAP.lock().as_mut() {
+            swap.bitmap[self.0 / 64] &= !(1 << (self.0 % 64));
+            swap.used -= 1;
+        }
+    }
+}
+
+/// Swap statistics, as reported by sys_swap_stats
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct SwapStats {
+    /// Slots in the swap area (0 if swapping is disabled)
+    pub total_slots: usize,
+    /// Slots holding a page
+    pub used_slots: usize,
+    /// Pages written to swap since boot
+    pub swapped_out: usize,
+    /// Pages read back from swap since boot
+    pub swapped_in: usize,
+}
+
+/// Snapshot of the swap statistics
+pub fn stats() -> SwapStats {
+    match SWAP.lock().as_ref() {
+        Some(swap) => SwapStats {
+            total_slots: swap.slots,
+            used_slots: swap.used,
+            swapped_out: swap.swapped_out,
+            swapped_in: swap.swapped_in,
+        },
+        None => SwapStats::default(),
+    }
+}
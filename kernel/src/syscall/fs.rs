@@ -19,7 +19,7 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     match fd {
         FD_STDOUT => {
             // Get current task's page table to translate user virtual address
-            let task_manager = TASK_MANAGER.lock();
+            let mut task_manager = TASK_MANAGER.lock();
             let current_pid = match task_manager.get_current_task() {
                 Some(pid) => pid,
                 None => {
@@ -27,13 +27,18 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
                 }
             };
 
-            let task = match task_manager.get_task(current_pid) {
+            let task = match task_manager.get_task_mut(current_pid) {
                 Some(task) => task,
                 None => {
                     return -1;
                 }
             };
             
+            // Populate lazy pages the buffer may live in (e.g. untouched stack pages)
+            if !task.memory_set.prepare_user_access(buf as usize, len, false) {
+                return -1;
+            }
+            
             // Get user page table
             let user_page_table = &task.memory_set.page_table();
             
@@ -1,10 +1,21 @@
 //! Memory mapping system calls
 //!
-//! Implements mmap and munmap system calls for memory mapping
+//! Implements mmap, munmap, mprotect, brk and shared memory system calls for memory mapping,
+//! and reports kernel heap, slab cache, swap and per-task memory usage
 
-use crate::mm::memory_set::{MapArea, MapPermission, MapType};
+use super::ENOMEM;
+use crate::mm::memory_layout::align_up;
+use crate::mm::memory_set::{MapArea, MapPermission, MemStats, MemorySet};
+use crate::mm::{paging_mode, PageSize};
 use crate::config::memory_layout::PAGE_SIZE;
-use crate::task::TASK_MANAGER;
+use crate::config::PATH_MAX;
+use crate::mm::heap::{self, HeapStats};
+use crate::mm::shm;
+use crate::mm::slab::{self, SlabStats};
+use crate::mm::swap::{self, SwapStats};
+use crate::task::{RLIMIT_NOFILE, TASK_MANAGER};
+use alloc::string::String;
+use alloc::vec::Vec;
 
 /// Protection flags (from Linux)
 pub const PROT_READ: usize = 0x1;
@@ -12,41 +23,226 @@ pub const PROT_WRITE: usize = 0x2;
 pub const PROT_EXEC: usize = 0x4;
 
 /// Mapping flags (from Linux)
-#[allow(dead_code)]
 pub const MAP_PRIVATE: usize = 0x02;
-#[allow(dead_code)]
 pub const MAP_SHARED: usize = 0x01;
 pub const MAP_ANONYMOUS: usize = 0x20;
 pub const MAP_FIXED: usize = 0x10;
+pub const MAP_HUGETLB: usize = 0x40000;
 
 /// Error return value (same as Linux MAP_FAILED)
 pub const MAP_FAILED: isize = -1;
 
+/// shm_open flag: create the object if it does not exist (same value as O_CREAT)
+pub const SHM_CREATE: usize = 0o100;
+
+/// Copy `value` into user memory at `ptr`
+///
+/// The copy is done byte-wise, so `ptr` needs no alignment and the target may
+/// straddle a page boundary. Lazy and COW pages are resolved first.
+///
+/// # Returns
+/// * `false` if `ptr` is null or the target is not writable user memory
+pub fn copy_to_user<T: Copy>(memory_set: &mut MemorySet, ptr: *mut T, value: &T) -> bool {
+    let len = core::mem::size_of::<T>();
+    if ptr.is_null() || !memory_set.prepare_user_access(ptr as usize, len, true) {
+        return false;
+    }
+    let buffers = memory_set.page_table().translated_byte_buffer(ptr as usize, len);
+    if buffers.iter().map(|buffer| buffer.len()).sum::<usize>() != len {
+        return false;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, len) };
+    let mut copied = 0;
+    for buffer in buffers {
+        buffer.copy_from_slice(&bytes[copied..copied + buffer.len()]);
+        copied += buffer.len();
+    }
+    true
+}
+
+/// Read a value of type `T` from user memory at `ptr`
+///
+/// Like copy_to_user(), byte-wise and across page boundaries. `T` must be
+/// plain data that any bit pattern is valid for.
+///
+/// # Returns
+/// * `None` if `ptr` is null or the source is not readable user memory
+pub fn copy_from_user<T: Copy>(memory_set: &mut MemorySet, ptr: *const T) -> Option<T> {
+    let len = core::mem::size_of::<T>();
+    if ptr.is_null() || !memory_set.prepare_user_access(ptr as usize, len, false) {
+        return None;
+    }
+    let buffers = memory_set
+        .page_table()
+        .translated_byte_buffer_readonly(ptr as usize, len);
+    if buffers.iter().map(|buffer| buffer.len()).sum::<usize>() != len {
+        return None;
+    }
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let bytes = value.as_mut_ptr() as *mut u8;
+    let mut copied = 0;
+    for buffer in buffers {
+        unsafe { core::ptr::copy_nonoverlapping(buffer.as_ptr(), bytes.add(copied), buffer.len()) };
+        copied += buffer.len();
+    }
+    Some(unsafe { value.assume_init() })
+}
+
+/// Read a NUL-terminated string from user memory at `ptr`
+///
+/// At most `max_len` bytes (including the NUL) are read, page by page, with
+/// lazy pages populated first.
+///
+/// # Returns
+/// * `None` if `ptr` is null, the string runs into memory that is not
+///   readable user memory, has no NUL within `max_len` bytes or is not UTF-8
+pub fn copy_str_from_user(memory_set: &mut MemorySet, ptr: *const u8, max_len: usize) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    let mut va = ptr as usize;
+    while bytes.len() < max_len {
+        let len = (PAGE_SIZE - va % PAGE_SIZE).min(max_len - bytes.len());
+        if !memory_set.prepare_user_access(va, len, false) {
+            return None;
+        }
+        let buffers = memory_set.page_table().translated_byte_buffer_readonly(va, len);
+        if buffers.iter().map(|buffer| buffer.len()).sum::<usize>() != len {
+            return None;
+        }
+        for buffer in buffers {
+            if let Some(nul) = buffer.iter().position(|&byte| byte == 0) {
+                bytes.extend_from_slice(&buffer[..nul]);
+                return String::from_utf8(bytes).ok();
+            }
+            bytes.extend_from_slice(buffer);
+        }
+        va += len;
+    }
+    None
+}
+
+/// Non-empty areas of an address space as sorted [start, end) ranges
+///
+/// The user stack's range includes its guard page, so no mapping is placed there.
+fn mapped_ranges(memory_set: &MemorySet) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = memory_set
+        .areas()
+        .iter()
+        .filter(|area| area.start_va() < area.end_va())
+        .map(|area| match area.grows_down() {
+            true => (area.start_va().saturating_sub(PAGE_SIZE), area.end_va()),
+            false => (area.start_va(), area.end_va()),
+        })
+        .collect();
+    ranges.sort_unstable();
+    ranges
+}
+
+/// Check that [start, start + length) is user space not covered by any area
+fn is_range_free(memory_set: &MemorySet, start: usize, length: usize) -> bool {
+    let end = match start.checked_add(length) {
+        Some(end) if end <= paging_mode().user_space_end() => end,
+        _ => return false,
+    };
+    mapped_ranges(memory_set)
+        .iter()
+        .all(|&(area_start, area_end)| end <= area_start || area_end <= start)
+}
+
+/// Find the lowest free range of `length` bytes at or above the mmap base (first fit)
+///
+/// The range starts at a multiple of `align` (a power of two).
+fn find_free_range(memory_set: &MemorySet, length: usize, align: usize) -> Option<usize> {
+    let mut candidate = memory_set.mmap_base();
+    for (area_start, area_end) in mapped_ranges(memory_set) {
+        if area_end <= candidate {
+            continue;
+        }
+        if candidate.checked_add(length)? <= area_start {
+            break;
+        }
+        candidate = area_end.checked_add(align - 1)? & !(align - 1);
+    }
+    match candidate.checked_add(length) {
+        Some(end) if end <= paging_mode().user_space_end() => Some(candidate),
+        _ => None,
+    }
+}
+
+/// Convert PROT_* flags to a user MapPermission
+///
+/// RISC-V has no write-only pages, so PROT_WRITE implies read access.
+///
+/// # Returns
+/// * `None` if the combination is refused by the W^X policy
+fn prot_to_perm(prot: usize) -> Option<MapPermission> {
+    let mut perm = MapPermission::U; // User mode
+    if (prot & PROT_READ) != 0 {
+        perm |= MapPermission::R;
+    }
+    if (prot & PROT_WRITE) != 0 {
+        perm |= MapPermission::R | MapPermission::W;
+    }
+    if (prot & PROT_EXEC) != 0 {
+        perm |= MapPermission::X;
+    }
+    if perm.violates_w_xor_x() {
+        return None;
+    }
+    Some(perm)
+}
+
 /// Map memory region
+///
+/// Without MAP_FIXED the mapping never overlaps an existing one: the hint is
+/// used only if the whole range is free, otherwise the lowest free range at or
+/// above the mmap base (randomized with ASLR) is chosen. With MAP_FIXED,
+/// existing mappings in the range are replaced; a request refused for its
+/// arguments or by the W^X policy leaves them alone.
+///
+/// MAP_HUGETLB backs a private anonymous mapping with 2MB pages: the length is
+/// rounded up and the address aligned to 2MB.
+///
+/// The mapping must fit in the task's RLIMIT_AS. PROT_WRITE | PROT_EXEC is
+/// refused while W^X is enforced.
 /// 
 /// # Arguments
 /// * `addr` - Suggested virtual address (0 means let kernel choose)
 /// * `length` - Size of mapping in bytes
 /// * `prot` - Protection flags (PROT_READ, PROT_WRITE, PROT_EXEC)
-/// * `flags` - Mapping flags (MAP_PRIVATE, MAP_SHARED, MAP_ANONYMOUS, MAP_FIXED)
-/// * `fd` - File descriptor (ignored for anonymous mappings)
-/// * `offset` - File offset (ignored for anonymous mappings)
+/// * `flags` - Mapping flags (MAP_PRIVATE, MAP_SHARED, MAP_ANONYMOUS, MAP_FIXED, MAP_HUGETLB)
+/// * `fd` - Shared memory object id from sys_shm_open (ignored for anonymous mappings)
+/// * `offset` - Offset into the object, page-aligned (ignored for anonymous mappings)
 /// 
 /// # Returns
 /// * Success: Virtual address of mapped region
-/// * Failure: MAP_FAILED (-1)
+/// * Failure: -ENOMEM if memory runs out, MAP_FAILED (-1) otherwise
 pub fn sys_mmap(
     addr: usize,
     length: usize,
     prot: usize,
     flags: usize,
-    _fd: usize,
-    _offset: usize,
+    fd: usize,
+    offset: usize,
 ) -> isize {
-    // Only support anonymous mappings for now
-    if (flags & MAP_ANONYMOUS) == 0 {
+    let shared = (flags & MAP_SHARED) != 0;
+    if shared && (flags & MAP_PRIVATE) != 0 {
+        return MAP_FAILED;
+    }
+
+    // Non-anonymous mappings are only supported for shared memory objects
+    if (flags & MAP_ANONYMOUS) == 0 && !shared {
+        return MAP_FAILED;
+    }
+
+    // Huge pages are only supported for private anonymous mappings
+    let huge = (flags & MAP_HUGETLB) != 0;
+    if huge && (shared || (flags & MAP_ANONYMOUS) == 0) {
         return MAP_FAILED;
     }
+    let page_bytes = if huge { PageSize::Size2M.bytes() } else { PAGE_SIZE };
 
     // Validate length
     if length == 0 {
@@ -54,7 +250,45 @@ pub fn sys_mmap(
     }
 
     // Align length to page boundary
-    let aligned_length = (length + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let aligned_length = match length.checked_add(page_bytes - 1) {
+        Some(length) => length & !(page_bytes - 1),
+        None => {
+            return MAP_FAILED;
+        }
+    };
+    let pages = aligned_length / PAGE_SIZE;
+
+    // Shared mappings are backed by frames that other address spaces can map too
+    let shared_frames = if (flags & MAP_ANONYMOUS) != 0 {
+        if shared {
+            match shm::alloc_shared_frames(pages) {
+                Some(frames) => Some(frames),
+                None => {
+                    return -ENOMEM;
+                }
+            }
+        } else {
+            None
+        }
+    } else {
+        if offset % PAGE_SIZE != 0 {
+            return MAP_FAILED;
+        }
+        match shm::shm_frames(fd, offset / PAGE_SIZE, pages) {
+            Some(frames) => Some(frames),
+            None => {
+                return MAP_FAILED;
+            }
+        }
+    };
+
+    // Convert protection flags to MapPermission
+    let perm = match prot_to_perm(prot) {
+        Some(perm) => perm,
+        None => {
+            return MAP_FAILED;
+        }
+    };
 
     // Get current task
     let mut task_manager = TASK_MANAGER.lock();
@@ -72,42 +306,60 @@ pub fn sys_mmap(
         }
     };
 
+    if !task.within_as_limit(aligned_length) {
+        return -ENOMEM;
+    }
+
     // Determine virtual address
-    let virt_addr = if addr != 0 && (flags & MAP_FIXED) != 0 {
-        // Use specified address (must be page-aligned)
-        if addr % PAGE_SIZE != 0 {
+    let fixed = (flags & MAP_FIXED) != 0;
+    let virt_addr = if fixed {
+        // Use specified address (must be page-aligned); whatever is mapped there
+        // is replaced below, once nothing else can refuse the request
+        if addr == 0 || addr % page_bytes != 0 {
             return MAP_FAILED;
         }
+        match addr.checked_add(aligned_length) {
+            Some(end) if end <= paging_mode().user_space_end() => {}
+            _ => {
+                return MAP_FAILED;
+            }
+        }
         addr
-    } else if addr != 0 {
-        // Try to use suggested address (if available)
-        addr & !(PAGE_SIZE - 1) // Align to page boundary
     } else {
-        // Let kernel choose an address
-        const MMAP_START: usize = 0x20000000; // Start of mmap region
-        MMAP_START
+        // Use the suggested address only if the whole range is free
+        let hint = addr & !(page_bytes - 1); // Align to page boundary
+        if hint != 0 && is_range_free(&task.memory_set, hint, aligned_length) {
+            hint
+        } else {
+            match find_free_range(&task.memory_set, aligned_length, page_bytes) {
+                Some(start) => start,
+                None => {
+                    return MAP_FAILED;
+                }
+            }
+        }
     };
 
     let start_va = virt_addr;
     let end_va = start_va + aligned_length;
 
-    // Convert protection flags to MapPermission
-    let mut perm = MapPermission::U; // User mode
-    if (prot & PROT_READ) != 0 {
-        perm |= MapPermission::R;
-    }
-    if (prot & PROT_WRITE) != 0 {
-        perm |= MapPermission::W;
-    }
-    if (prot & PROT_EXEC) != 0 {
-        perm |= MapPermission::X;
-    }
+    // Create map area (private frames are allocated on first access)
+    let map_area = match shared_frames {
+        Some(frames) => MapArea::new_shared(start_va, perm, &frames),
+        None if huge => MapArea::new_lazy(start_va, end_va, perm).with_page_size(PageSize::Size2M),
+        None => MapArea::new_lazy(start_va, end_va, perm),
+    };
 
-    // Create map area
-    let map_area = MapArea::new(start_va, end_va, MapType::Framed, perm);
+    // Only running out of memory for page tables (shared mappings) can fail
+    // after the old mappings are gone
+    if fixed && !task.memory_set.unmap_range(start_va, end_va) {
+        return MAP_FAILED;
+    }
 
     // Add to memory set
-    task.memory_set.push(map_area, None);
+    if task.memory_set.try_push(map_area, None).is_err() {
+        return -ENOMEM;
+    }
 
     drop(task_manager);
 
@@ -115,10 +367,14 @@ pub fn sys_mmap(
 }
 
 /// Unmap memory region
+///
+/// The range may cover part of a mapping or span several mappings; only the
+/// pages inside it are released.
+/// A 2MB page of a MAP_HUGETLB mapping can only be unmapped as a whole.
 /// 
 /// # Arguments
-/// * `addr` - Virtual address of mapped region (must be page-aligned)
-/// * `length` - Size of region to unmap in bytes
+/// * `addr` - Start of the range to unmap (must be page-aligned)
+/// * `length` - Size of range to unmap in bytes
 /// 
 /// # Returns
 /// * Success: 0
@@ -137,7 +393,12 @@ pub fn sys_munmap(addr: usize, length: usize) -> isize {
     // Align length to page boundary
     let aligned_length = (length + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
     let start_va = addr;
-    let end_va = start_va + aligned_length;
+    let end_va = match start_va.checked_add(aligned_length) {
+        Some(end_va) => end_va,
+        None => {
+            return -1;
+        }
+    };
 
     // Get current task
     let mut task_manager = TASK_MANAGER.lock();
@@ -153,41 +414,358 @@ pub fn sys_munmap(addr: usize, length: usize) -> isize {
         None => {
             return -1;
         }
-    };    
-    // Find and remove the map area that contains this address
-    // We need to find the area that contains [start_va, end_va)
-    let mut found = false;
-    let mut area_index = 0;
-    
-    for (i, area) in task.memory_set.areas().iter().enumerate() {
-        let area_start = area.start_va();
-        let area_end = area.end_va();
-        
-        // Check if the region to unmap overlaps with this area
-        if start_va < area_end && end_va > area_start {
-            // Found overlapping area
-            // For simplicity, we'll only unmap if the region exactly matches an area
-            // In a real OS, we'd handle partial unmapping
-            if start_va == area_start && end_va == area_end {
-                area_index = i;
-                found = true;
-                break;
-            } else {
-                // Partial unmapping not supported yet
-                return -1;
-            }
-        }
+    };
+
+    // Split or remove every area overlapping the range
+    if !task.memory_set.unmap_range(start_va, end_va) {
+        return -1;
     }
 
-    if !found {
+    drop(task_manager);
+
+    0
+}
+
+/// Change the access protection of a memory region
+///
+/// The range may cover part of a mapping or span several mappings, but every
+/// page in it must be mapped.
+/// A 2MB page of a MAP_HUGETLB mapping can only change as a whole.
+/// While W^X is enforced, PROT_WRITE | PROT_EXEC is refused, and so is
+/// PROT_EXEC on the stack or heap.
+///
+/// # Arguments
+/// * `addr` - Start of the range (must be page-aligned)
+/// * `length` - Size of the range in bytes
+/// * `prot` - New protection flags (PROT_READ, PROT_WRITE, PROT_EXEC)
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_mprotect(addr: usize, length: usize, prot: usize) -> isize {
+    // Validate address
+    if addr % PAGE_SIZE != 0 {
         return -1;
     }
 
-    // Remove the area
-    task.memory_set.remove_area(area_index);
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return -1;
+    }
 
-    drop(task_manager);
+    let perm = match prot_to_perm(prot) {
+        Some(perm) => perm,
+        None => {
+            return -1;
+        }
+    };
+
+    // Align length to page boundary
+    let aligned_length = (length + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let start_va = addr;
+    let end_va = match start_va.checked_add(aligned_length) {
+        Some(end_va) => end_va,
+        None => {
+            return -1;
+        }
+    };
+
+    // Get current task
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    if !task.memory_set.protect_range(start_va, end_va, perm) {
+        return -1;
+    }
 
     0
 }
 
+/// Set the program break (end of the heap)
+///
+/// The heap is a lazy map area starting at `heap_bottom`; growing it only
+/// extends the area, and pages are allocated when first touched. The grown
+/// heap must fit in the task's RLIMIT_AS.
+///
+/// # Arguments
+/// * `addr` - New program break, or 0 to query the current one
+///
+/// # Returns
+/// * The program break after the call (unchanged if it could not be lowered)
+/// * -ENOMEM if the heap cannot grow to `addr`
+pub fn sys_brk(addr: usize) -> isize {
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    let heap_bottom = task.heap_bottom;
+    let old_brk = task.program_brk;
+    if addr < heap_bottom || addr == old_brk {
+        return old_brk as isize;
+    }
+
+    if addr > old_brk {
+        if addr > paging_mode().user_space_end() {
+            return -ENOMEM;
+        }
+        let grown = align_up(addr) - align_up(old_brk);
+        if !task.within_as_limit(grown) || task.memory_set.append_to(heap_bottom, addr).is_err() {
+            return -ENOMEM;
+        }
+        task.program_brk = addr;
+    } else if task.memory_set.shrink_to(heap_bottom, addr) {
+        task.program_brk = addr;
+    }
+    task.program_brk as isize
+}
+
+/// Open a named shared memory object
+///
+/// The returned id is passed as `fd` to mmap with MAP_SHARED to map the
+/// object's frames; every task opening the same name maps the same memory.
+///
+/// # Arguments
+/// * `name` - Pointer to a NUL-terminated object name of at most PATH_MAX
+///   bytes (user virtual address)
+/// * `size` - Object size in bytes, used only when the object is created
+/// * `flags` - SHM_CREATE to create the object if it does not exist
+///
+/// Ids are used like file descriptors and must stay below RLIMIT_NOFILE.
+///
+/// # Returns
+/// * Success: object id
+/// * Failure: -1
+pub fn sys_shm_open(name: *const u8, size: usize, flags: usize) -> isize {
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    let name = match copy_str_from_user(&mut task.memory_set, name, PATH_MAX) {
+        Some(name) => name,
+        None => return -1,
+    };
+    let max_id = task.rlimits.cur(RLIMIT_NOFILE);
+    drop(task_manager);
+
+    let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+    match shm::shm_open(name.as_str(), pages, (flags & SHM_CREATE) != 0, max_id) {
+        Some(id) => id as isize,
+        None => -1,
+    }
+}
+
+/// Remove the name of a shared memory object
+///
+/// Existing mappings stay valid; the memory is freed when the last one is unmapped.
+///
+/// # Arguments
+/// * `name` - Pointer to a NUL-terminated object name of at most PATH_MAX
+///   bytes (user virtual address)
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_shm_unlink(name: *const u8) -> isize {
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    let name = match copy_str_from_user(&mut task.memory_set, name, PATH_MAX) {
+        Some(name) => name,
+        None => return -1,
+    };
+    drop(task_manager);
+
+    if shm::shm_unlink(name.as_str()) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Report kernel heap usage
+///
+/// # Arguments
+/// * `stats` - Where to store a HeapStats (user virtual address)
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_heap_stats(stats: *mut HeapStats) -> isize {
+    // Sample before touching user memory, which may itself allocate
+    let current = heap::stats();
+
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    if copy_to_user(&mut task.memory_set, stats, &current) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Report the statistics of one kernel slab cache
+///
+/// # Arguments
+/// * `id` - Cache index, starting at 0
+/// * `stats` - Where to store a SlabStats (user virtual address)
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1 (also past the last cache)
+pub fn sys_slab_stats(id: usize, stats: *mut SlabStats) -> isize {
+    let current = match slab::cache_stats(id) {
+        Some(current) => current,
+        None => {
+            return -1;
+        }
+    };
+
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    if copy_to_user(&mut task.memory_set, stats, &current) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Report swap area usage
+///
+/// # Arguments
+/// * `stats` - Where to store a SwapStats (user virtual address)
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_swap_stats(stats: *mut SwapStats) -> isize {
+    let current = swap::stats();
+
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    if copy_to_user(&mut task.memory_set, stats, &current) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Report the memory usage (RSS and VSZ) of the current task
+///
+/// # Arguments
+/// * `stats` - Where to store a MemStats (user virtual address)
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_mem_stats(stats: *mut MemStats) -> isize {
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    // Populate the target page first, so that the numbers include it
+    let len = core::mem::size_of::<MemStats>();
+    if stats.is_null() || !task.memory_set.prepare_user_access(stats as usize, len, true) {
+        return -1;
+    }
+    let current = task.memory_set.stats();
+    if copy_to_user(&mut task.memory_set, stats, &current) {
+        0
+    } else {
+        -1
+    }
+}
@@ -7,14 +7,42 @@ mod memory;
 use fs::*;
 use process::*;
 use memory::*;
+use crate::mm::heap::HeapStats;
+use crate::mm::memory_set::MemStats;
+use crate::mm::slab::SlabStats;
+use crate::mm::swap::SwapStats;
+use crate::task::RLimit;
 
 /// System call numbers
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_YIELD: usize = 124;
+const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_GETRLIMIT: usize = 163;
+const SYSCALL_SETRLIMIT: usize = 164;
 const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_GETPID: usize = 172;
+const SYSCALL_GETPPID: usize = 173;
+const SYSCALL_FORK: usize = 220;
+const SYSCALL_EXEC: usize = 221;
+const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_MMAP: usize = 222;
 const SYSCALL_MUNMAP: usize = 215;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MPROTECT: usize = 226;
+// Not Linux syscalls: shared memory objects without a file system
+const SYSCALL_SHM_OPEN: usize = 490;
+const SYSCALL_SHM_UNLINK: usize = 491;
+// Not Linux syscalls: kernel statistics
+const SYSCALL_HEAP_STATS: usize = 492;
+const SYSCALL_SLAB_STATS: usize = 493;
+const SYSCALL_SWAP_STATS: usize = 494;
+const SYSCALL_MEM_STATS: usize = 495;
+// Not Linux syscall: name of the scheduling policy selected at boot
+const SYSCALL_SCHED_POLICY: usize = 496;
+
+/// Error numbers (from Linux), returned negated
+pub const ENOMEM: isize = 12;
 
 /// System call dispatcher
 /// 
@@ -26,9 +54,26 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
         SYSCALL_YIELD => sys_yield(),
+        SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
+        SYSCALL_GETRLIMIT => sys_getrlimit(args[0], args[1] as *mut RLimit),
+        SYSCALL_SETRLIMIT => sys_setrlimit(args[0], args[1] as *const RLimit),
         SYSCALL_GET_TIME => sys_get_time(),
+        SYSCALL_GETPID => sys_getpid(),
+        SYSCALL_GETPPID => sys_getppid(),
+        SYSCALL_FORK => sys_fork(),
+        SYSCALL_EXEC => sys_exec(args[0] as *const u8),
+        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
         SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
         SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_BRK => sys_brk(args[0]),
+        SYSCALL_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
+        SYSCALL_SHM_OPEN => sys_shm_open(args[0] as *const u8, args[1], args[2]),
+        SYSCALL_SHM_UNLINK => sys_shm_unlink(args[0] as *const u8),
+        SYSCALL_HEAP_STATS => sys_heap_stats(args[0] as *mut HeapStats),
+        SYSCALL_SLAB_STATS => sys_slab_stats(args[0], args[1] as *mut SlabStats),
+        SYSCALL_SWAP_STATS => sys_swap_stats(args[0] as *mut SwapStats),
+        SYSCALL_MEM_STATS => sys_mem_stats(args[0] as *mut MemStats),
+        SYSCALL_SCHED_POLICY => sys_sched_policy(args[0] as *mut [u8; SCHED_POLICY_NAME_LEN]),
         _ => {
             println!("[syscall] Unsupported syscall_id: {}", syscall_id);
             -1
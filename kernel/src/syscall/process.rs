@@ -1,5 +1,16 @@
+use super::memory::{copy_from_user, copy_str_from_user, copy_to_user};
+use super::ENOMEM;
+use crate::config::memory_layout::USER_STACK_SIZE;
+use crate::config::PATH_MAX;
+use crate::mm::memory_set::ERR_W_XOR_X;
 use crate::sbi;
-use crate::task::exit_current_and_run_next;
+use crate::task::{
+    add_task, exit_current_and_run_next, get_app_data_by_name, RLimit, MAX_PRIORITY, MIN_PRIORITY,
+    RLIMIT_STACK, SCHEDULER, TASK_MANAGER,
+};
+
+/// Size of the buffer sys_sched_policy fills
+pub const SCHED_POLICY_NAME_LEN: usize = 16;
 
 pub fn sys_exit(exit_code: i32) -> ! {
     exit_current_and_run_next(exit_code);
@@ -12,6 +23,305 @@ pub fn sys_yield() -> isize {
     0
 }
 
+/// Set the scheduling priority of the current task
+///
+/// Under the stride and cfs policies the task's share of the CPU is
+/// proportional to its priority; the other policies ignore it.
+///
+/// # Returns
+/// * Success: the new priority
+/// * Failure: -1 if `prio` is below MIN_PRIORITY (2) or above MAX_PRIORITY
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < MIN_PRIORITY as isize || prio > MAX_PRIORITY as isize {
+        return -1;
+    }
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    match task_manager.get_task_mut(current_pid) {
+        Some(task) => {
+            task.priority = prio as usize;
+            prio
+        }
+        None => -1,
+    }
+}
+
+/// Report the scheduling policy selected at boot
+///
+/// # Arguments
+/// * `name` - Receives the policy name ("rr", "stride", "mlfq" or "cfs"),
+///   padded with NUL bytes (user virtual address)
+///
+/// # Returns
+/// * Success: length of the name
+/// * Failure: -1
+pub fn sys_sched_policy(name: *mut [u8; SCHED_POLICY_NAME_LEN]) -> isize {
+    let policy = SCHEDULER.lock().policy_name();
+    let mut buf = [0u8; SCHED_POLICY_NAME_LEN];
+    buf[..policy.len()].copy_from_slice(policy.as_bytes());
+
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    if copy_to_user(&mut task.memory_set, name, &buf) {
+        policy.len() as isize
+    } else {
+        -1
+    }
+}
+
 pub fn sys_get_time() -> isize {
     sbi::get_time() as isize
 }
+
+/// Get the PID of the current task
+pub fn sys_getpid() -> isize {
+    match TASK_MANAGER.lock().get_current_task() {
+        Some(pid) => pid as isize,
+        None => -1,
+    }
+}
+
+/// Get the PID of the current task's parent
+///
+/// # Returns
+/// * Parent PID, or 0 if the task has no parent
+pub fn sys_getppid() -> isize {
+    let task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    match task_manager.get_task(current_pid) {
+        Some(task) => task.parent.unwrap_or(0) as isize,
+        None => -1,
+    }
+}
+
+/// Duplicate the current task
+///
+/// # Returns
+/// * Parent: PID of the child
+/// * Child: 0
+/// * Failure: -ENOMEM if memory runs out, -1 otherwise
+pub fn sys_fork() -> isize {
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let child_pid = match task_manager.alloc_pid() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let child = match task_manager.get_task_mut(current_pid) {
+        Some(task) => match task.fork(child_pid) {
+            Ok(child) => child,
+            Err(_) => {
+                return -ENOMEM;
+            }
+        },
+        None => {
+            return -1;
+        }
+    };
+
+    add_task(&mut task_manager, child);
+    task_manager.add_child(current_pid, child_pid);
+    child_pid as isize
+}
+
+/// Replace the current task's program with an embedded app
+///
+/// # Arguments
+/// * `path` - Pointer to a NUL-terminated app name (user virtual address)
+///
+/// # Returns
+/// * Success: 0 (returned to the new program)
+/// * Failure: -ENOMEM if the user stack exceeds RLIMIT_STACK or memory runs
+///   out (the old program keeps running), -1 otherwise (including a path that
+///   is not readable or longer than PATH_MAX, and an app the W^X policy refuses)
+pub fn sys_exec(path: *const u8) -> isize {
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    if task.rlimits.cur(RLIMIT_STACK) < USER_STACK_SIZE {
+        return -ENOMEM;
+    }
+
+    let name = match copy_str_from_user(&mut task.memory_set, path, PATH_MAX) {
+        Some(name) => name,
+        None => return -1,
+    };
+    match get_app_data_by_name(name.as_str()) {
+        Some(elf_data) => match task.exec(elf_data) {
+            Ok(()) => 0,
+            Err(ERR_W_XOR_X) => {
+                println!("[Exec] W^X: {} has a writable and executable segment", name);
+                -1
+            }
+            Err(_) => -ENOMEM,
+        },
+        None => -1,
+    }
+}
+
+/// Wait for a child task to exit and reap it
+///
+/// # Arguments
+/// * `pid` - PID of the child to wait for, or -1 for any child
+/// * `exit_code_ptr` - Where to store the child's exit code (user virtual address, may be null)
+///
+/// # Returns
+/// * PID of the reaped child
+/// * -1 if there is no matching child
+/// * -2 if matching children exist but none has exited yet
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let child_pid = match task_manager.find_zombie_child(current_pid, pid) {
+        Ok(Some(child_pid)) => child_pid,
+        Ok(None) => {
+            return -2;
+        }
+        Err(()) => {
+            return -1;
+        }
+    };
+
+    if !exit_code_ptr.is_null() {
+        let exit_code = task_manager.get_task(child_pid).unwrap().exit_code;
+        let task = task_manager.get_task_mut(current_pid).unwrap();
+        if !copy_to_user(&mut task.memory_set, exit_code_ptr, &exit_code) {
+            return -1;
+        }
+    }
+
+    // Release the zombie's slot (and with it, its page tables)
+    task_manager.reap_child(current_pid, child_pid);
+
+    child_pid as isize
+}
+
+/// Get the limits of a resource for the current task
+///
+/// # Arguments
+/// * `resource` - Resource number (RLIMIT_*)
+/// * `rlim` - Where to store the limits (user virtual address)
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_getrlimit(resource: usize, rlim: *mut RLimit) -> isize {
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    let limit = match task.rlimits.get(resource) {
+        Some(limit) => limit,
+        None => {
+            return -1;
+        }
+    };
+    if copy_to_user(&mut task.memory_set, rlim, &limit) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Set the limits of a resource for the current task
+///
+/// The soft limit may be set anywhere up to the hard limit; the hard limit
+/// can only be lowered. Limits are inherited by children.
+///
+/// # Arguments
+/// * `resource` - Resource number (RLIMIT_*)
+/// * `rlim` - New limits (user virtual address)
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_setrlimit(resource: usize, rlim: *const RLimit) -> isize {
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => {
+            return -1;
+        }
+    };
+
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => {
+            return -1;
+        }
+    };
+
+    let limit = match copy_from_user(&mut task.memory_set, rlim) {
+        Some(limit) => limit,
+        None => {
+            return -1;
+        }
+    };
+    match task.rlimits.set(resource, limit) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
@@ -3,7 +3,7 @@
 //! Loads user programs from embedded binaries via link_app.S
 
 use super::task::TaskControlBlock;
-use crate::task::TASK_MANAGER;
+use crate::task::{add_task, INITPROC_PID, TASK_MANAGER};
 
 // External symbols from link_app.S (manually maintained)
 extern "C" {
@@ -32,19 +32,56 @@ fn get_app_data(app_id: usize) -> &'static [u8] {
     unsafe { core::slice::from_raw_parts(start as *const u8, end - start) }
 }
 
-/// Load user programs in batch
-pub fn load_apps() {
+/// Get app name by index (from the `_app_names` table in link_app.S)
+fn get_app_name(app_id: usize) -> &'static str {
+    extern "C" {
+        fn _app_names();
+    }
     let num_app = get_num_app();
-
-    for i in 0..num_app {
-        let app_data = get_app_data(i);
-        if app_data.is_empty() {
-            continue;
+    assert!(app_id < num_app, "App ID {} out of range (max: {})", app_id, num_app);
+    let mut ptr = _app_names as *const () as usize as *const u8;
+    for _ in 0..app_id {
+        // Skip over one NUL-terminated name
+        unsafe {
+            while ptr.read_volatile() != 0 {
+                ptr = ptr.add(1);
+            }
+            ptr = ptr.add(1);
         }
-
-        let task = TaskControlBlock::new(app_data, i);
-        TASK_MANAGER.lock().add_task(task);
     }
+    let mut len = 0;
+    unsafe {
+        while ptr.add(len).read_volatile() != 0 {
+            len += 1;
+        }
+        core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).unwrap_or("")
+    }
+}
+
+/// Get app data by name (used by exec)
+pub fn get_app_data_by_name(name: &str) -> Option<&'static [u8]> {
+    (0..get_num_app())
+        .find(|&i| get_app_name(i) == name)
+        .map(get_app_data)
+}
+
+/// Name of the first user program, which becomes PID 1
+const INITPROC_NAME: &str = "initproc";
+
+/// Load the first user program
+///
+/// Only initproc is loaded, as PID 1; it runs every other embedded app
+/// through fork and exec, one at a time.
+pub fn load_apps() {
+    let initproc_data =
+        get_app_data_by_name(INITPROC_NAME).expect("initproc not found in embedded apps");
+    let mut task_manager = TASK_MANAGER.lock();
+    let pid = task_manager.alloc_pid().expect("No available task slot");
+    assert_eq!(pid, INITPROC_PID, "initproc must be the first task");
+    let task = TaskControlBlock::new(initproc_data, pid);
+    add_task(&mut task_manager, task);
+    let task_count = task_manager.task_count();
+    drop(task_manager);
 
-    println!("[Loader] Loaded {} programs", TASK_MANAGER.lock().task_count());
+    println!("[Loader] Loaded {} program(s), {} apps embedded", task_count, get_num_app());
 }
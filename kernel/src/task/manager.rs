@@ -2,14 +2,28 @@
 //! 
 //! Manages all tasks in the system
 
+use super::context::TaskContext;
 use super::task::TaskControlBlock;
 use super::TaskStatus;
 use crate::config::MAX_APP_NUM;
+use crate::mm::slab::{SlabAllocator, TASK_CACHE};
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 
+/// PID 0 is reserved for the idle context and never holds a task
+const IDLE_PID: usize = 0;
+
+/// PID of the init process, which adopts orphaned tasks
+pub const INITPROC_PID: usize = 1;
+
+/// Task control blocks live in the task slab cache
+type TaskBox = Box<TaskControlBlock, SlabAllocator>;
+
 pub struct TaskManager {
-    tasks: Vec<Option<TaskControlBlock>>,
+    tasks: Vec<Option<TaskBox>>,
     current_task: Option<usize>,
+    /// Context switched away from when no task is running (boot, or after exit)
+    idle_task_cx: TaskContext,
 }
 
 impl TaskManager {
@@ -23,17 +37,30 @@ impl TaskManager {
         Self {
             tasks,
             current_task: None,
+            idle_task_cx: TaskContext::zero_init(),
         }
     }
     
+    /// Find a free PID (task slot) for a new task
+    pub fn alloc_pid(&self) -> Option<usize> {
+        self.tasks
+            .iter()
+            .enumerate()
+            .skip(IDLE_PID + 1)
+            .find(|(_, slot)| slot.is_none())
+            .map(|(pid, _)| pid)
+    }
+    
+    /// Add a task into the slot given by its PID
     pub fn add_task(&mut self, task: TaskControlBlock) -> usize {
-        for (idx, slot) in self.tasks.iter_mut().enumerate() {
-            if slot.as_ref().is_none() {
-                *slot = Some(task);
-                return idx;
+        let pid = task.pid;
+        match self.tasks.get_mut(pid) {
+            Some(slot) if slot.is_none() && pid != IDLE_PID => {
+                *slot = Some(Box::new_in(task, TASK_CACHE.allocator()));
+                pid
             }
+            _ => panic!("No available task slot"),
         }
-        panic!("No available task slot");
     }
     
     pub fn remove_task(&mut self, pid: usize) {
@@ -46,11 +73,11 @@ impl TaskManager {
     }
     
     pub fn get_task(&self, pid: usize) -> Option<&TaskControlBlock> {
-        self.tasks.get(pid)?.as_ref()
+        self.tasks.get(pid)?.as_deref()
     }
     
     pub fn get_task_mut(&mut self, pid: usize) -> Option<&mut TaskControlBlock> {
-        self.tasks.get_mut(pid)?.as_mut()
+        self.tasks.get_mut(pid)?.as_deref_mut()
     }
     
     pub fn get_current_task(&self) -> Option<usize> {
@@ -61,17 +88,80 @@ impl TaskManager {
         self.current_task = pid;
     }
     
-    pub fn find_next_task(&self, start: usize) -> Option<usize> {
-        let len = self.tasks.len();
-        for i in 0..len {
-            let idx = (start + i + 1) % len;
-            if let Some(Some(task)) = self.tasks.get(idx) {
-                if task.task_status == TaskStatus::Ready {
-                    return Some(idx);
+    /// Get a pointer to the idle context used when no task is running
+    pub fn get_idle_task_cx_ptr(&mut self) -> *mut TaskContext {
+        &mut self.idle_task_cx as *mut TaskContext
+    }
+    
+    /// Record `child` as a child of `parent`
+    pub fn add_child(&mut self, parent: usize, child: usize) {
+        if let Some(task) = self.get_task_mut(child) {
+            task.parent = Some(parent);
+        }
+        if let Some(task) = self.get_task_mut(parent) {
+            task.children.push(child);
+        }
+    }
+    
+    /// Find a zombie child of `parent` matching `pid` (-1 matches any child)
+    ///
+    /// # Returns
+    /// * `Ok(Some(pid))` - a matching child has exited
+    /// * `Ok(None)` - matching children exist but none has exited yet
+    /// * `Err(())` - no matching child
+    pub fn find_zombie_child(&self, parent: usize, pid: isize) -> Result<Option<usize>, ()> {
+        let children = match self.get_task(parent) {
+            Some(task) => &task.children,
+            None => return Err(()),
+        };
+        let mut found = false;
+        for &child in children.iter() {
+            if pid != -1 && child != pid as usize {
+                continue;
+            }
+            found = true;
+            if let Some(task) = self.get_task(child) {
+                if task.task_status == TaskStatus::Zombie {
+                    return Ok(Some(child));
                 }
             }
         }
-        None
+        if found {
+            Ok(None)
+        } else {
+            Err(())
+        }
+    }
+    
+    /// Reap a zombie child: unlink it from its parent and free its slot
+    ///
+    /// # Returns
+    /// * The child's exit code, or None if `child` is not a zombie child of `parent`
+    pub fn reap_child(&mut self, parent: usize, child: usize) -> Option<i32> {
+        let exit_code = match self.get_task(child) {
+            Some(task) if task.parent == Some(parent) && task.task_status == TaskStatus::Zombie => {
+                task.exit_code
+            }
+            _ => return None,
+        };
+        if let Some(task) = self.get_task_mut(parent) {
+            task.children.retain(|&pid| pid != child);
+        }
+        self.remove_task(child);
+        Some(exit_code)
+    }
+    
+    /// Hand the children of an exiting task over to initproc
+    ///
+    /// Zombies among them will be reaped by initproc's wait loop.
+    pub fn reparent_children(&mut self, parent: usize) {
+        let children = match self.get_task_mut(parent) {
+            Some(task) => core::mem::take(&mut task.children),
+            None => return,
+        };
+        for child in children {
+            self.add_child(INITPROC_PID, child);
+        }
     }
     
     pub fn mark_zombie(&mut self, pid: usize) {
@@ -81,7 +171,7 @@ impl TaskManager {
     }
     
     pub fn task_count(&self) -> usize {
-        self.tasks.iter().filter(|t: &&Option<TaskControlBlock>| t.is_some()).count()
+        self.tasks.iter().filter(|t: &&Option<TaskBox>| t.is_some()).count()
     }
 }
 
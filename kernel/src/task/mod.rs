@@ -3,16 +3,22 @@
 mod context;
 mod loader;
 mod manager;
+mod rlimit;
 mod scheduler;
 mod task;
 
 pub use context::TaskContext;
-pub use loader::load_apps;
-pub use manager::TaskManager;
-pub use scheduler::Scheduler;
-pub use task::TaskStatus;
+pub use loader::{get_app_data_by_name, load_apps};
+pub use manager::{TaskManager, INITPROC_PID};
+pub use rlimit::{RLimit, RLIMIT_CPU, RLIMIT_NOFILE, RLIMIT_STACK, RLIM_INFINITY};
+pub use scheduler::{SchedPolicy, Scheduler, MAX_PRIORITY, MIN_PRIORITY};
+pub use task::{kernel_stack_guard_owner, kernel_stack_position, TaskControlBlock, TaskStatus};
 
+use crate::config::{MAX_APP_NUM, TICKS_PER_SEC};
 use crate::global_asm;
+use crate::mm::memory_layout::VirtPageNum;
+use crate::mm::swap::{self, SWAP_BATCH, SWAP_LOW_WATERMARK};
+use crate::mm::{FaultError, MapPermission, FRAME_ALLOCATOR};
 use lazy_static::*;
 use spin::Mutex;
 
@@ -22,19 +28,11 @@ extern "C" {
     fn __switch(current_task_cx_ptr: *mut TaskContext, next_task_cx_ptr: *const TaskContext);
 }
 
-/// Helper function to jump to __restore with proper register assignment
-/// Using a separate function prevents the compiler from optimizing incorrectly
-#[inline(never)]
-unsafe fn jump_to_restore(trap_cx: usize, restore_addr: usize) -> ! {
-    use core::arch::asm;
-    asm!(
-        "mv a0, {trap_cx}",
-        "jr {restore}",
-        trap_cx = in(reg) trap_cx,
-        restore = in(reg) restore_addr,
-        options(noreturn)
-    );
-}
+/// Exit code of a task killed by the OOM killer (as if by SIGKILL)
+pub const OOM_EXIT_CODE: i32 = -9;
+
+/// Exit code of a task killed for exceeding RLIMIT_CPU (as if by SIGXCPU)
+pub const CPU_LIMIT_EXIT_CODE: i32 = -24;
 
 lazy_static! {
     pub static ref TASK_MANAGER: Mutex<TaskManager> = Mutex::new(TaskManager::new());
@@ -47,6 +45,16 @@ pub fn init() {
 
     // Update KERNEL_SPACE to match the activated one
     // The actual kernel space is already created and activated in mm::init()
+
+    // Pick the scheduling policy before any task is enqueued
+    let info = crate::fdt::machine_info();
+    let mut scheduler = SCHEDULER.lock();
+    if let Some(name) = info.boot_arg("sched") {
+        if !scheduler.set_policy(name) {
+            println!("[Task] unknown scheduling policy '{}', ignored", name);
+        }
+    }
+    println!("[Task] scheduling policy: {}", scheduler.policy_name());
 }
 
 /// Switch to next task
@@ -57,18 +65,15 @@ pub fn switch_task() {
     let current_pid = task_manager.get_current_task();
 
     if let Some(current) = current_pid {
-        // Mark current task as ready
+        // Mark current task as ready and put it back into the run queue
         if let Some(task) = task_manager.get_task_mut(current) {
             task.task_status = TaskStatus::Ready;
+            scheduler.enqueue(current);
         }
     }
 
-    // Find next task
-    let next_pid = if let Some(current) = current_pid {
-        scheduler.schedule_next(current, &*task_manager)
-    } else {
-        scheduler.schedule_next(0, &*task_manager)
-    };
+    // Find next task; it starts with a fresh time slice, whoever used up the last one
+    let next_pid = scheduler.pick_next(&mut task_manager);
 
     if let Some(next) = next_pid {
         // Mark next task as running
@@ -80,165 +85,293 @@ pub fn switch_task() {
 
         task_manager.set_current_task(Some(next));
 
-        let current_cx = if let Some(current) = current_pid {
-            task_manager.get_task(current).map(|t| &t.task_cx as *const TaskContext)
-        } else {
-            None
+        // With no current task (boot, or the previous task has exited) we switch
+        // away from the idle context, which is never resumed
+        let curr_ptr = match current_pid {
+            Some(current) => {
+                &task_manager.get_task(current).unwrap().task_cx as *const TaskContext
+                    as *mut TaskContext
+            }
+            None => task_manager.get_idle_task_cx_ptr(),
         };
+        let next_ptr = &task_manager.get_task(next).unwrap().task_cx as *const TaskContext;
 
-        let next_cx = task_manager.get_task(next).map(|t| &t.task_cx as *const TaskContext);
+        let next_task = task_manager.get_task(next).unwrap();
+        let next_kernel_sp = next_task.task_cx.sp;
+        let next_user_token = next_task.get_user_token();
 
-        if let (Some(curr_ptr), Some(next_ptr)) = (current_cx, next_cx) {
-            // Task switch between running tasks
-            let next_task = task_manager.get_task(next).unwrap();
-            let next_kernel_sp = next_task.task_cx.sp;
-            let next_user_token = next_task.get_user_token();
-
-            let kernel_token = crate::mm::get_kernel_token();
+        let kernel_token = crate::mm::get_kernel_token();
 
-            // Switch to kernel address space before accessing trap context
-            unsafe {
-                use core::arch::asm;
-                asm!("csrw satp, {}", in(reg) kernel_token);
+        // Switch to kernel address space before accessing trap context
+        // User TLB entries are tagged with their ASID and can stay
+        unsafe {
+            use core::arch::asm;
+            asm!("csrw satp, {}", in(reg) kernel_token);
+            if !crate::mm::asid::enabled() {
                 asm!("sfence.vma");
             }
+        }
 
-            // Save current task's trap context from kernel stack to its trap_cx page
-            if let Some(current) = current_pid {
-                if let Some(current_task) = task_manager.get_task(current) {
-                    let current_trap_cx_kernel = (current_task.task_cx.sp
-                        - core::mem::size_of::<crate::trap::TrapContext>())
-                        as *mut crate::trap::TrapContext;
-                    let current_trap_cx_data = unsafe { &*current_trap_cx_kernel };
-                    let current_trap_cx_page = current_task.get_trap_cx();
-                    *current_trap_cx_page = crate::trap::TrapContext {
-                        x: current_trap_cx_data.x,
-                        sstatus: current_trap_cx_data.sstatus,
-                        sepc: current_trap_cx_data.sepc,
-                        user_satp: current_trap_cx_data.user_satp,
-                        kernel_sp: current_trap_cx_data.kernel_sp,
-                    };
-                }
+        // Save current task's trap context from kernel stack to its trap_cx page
+        if let Some(current) = current_pid {
+            if let Some(current_task) = task_manager.get_task(current) {
+                let current_trap_cx_kernel = (current_task.task_cx.sp
+                    - core::mem::size_of::<crate::trap::TrapContext>())
+                    as *mut crate::trap::TrapContext;
+                let current_trap_cx_data = unsafe { &*current_trap_cx_kernel };
+                let current_trap_cx_page = current_task.get_trap_cx();
+                *current_trap_cx_page = crate::trap::TrapContext {
+                    x: current_trap_cx_data.x,
+                    sstatus: current_trap_cx_data.sstatus,
+                    sepc: current_trap_cx_data.sepc,
+                    user_satp: current_trap_cx_data.user_satp,
+                    kernel_sp: current_trap_cx_data.kernel_sp,
+                };
             }
+        }
 
-            let next_trap_cx_data = {
-                let trap_cx = next_task.get_trap_cx();
-                crate::trap::TrapContext {
-                    x: trap_cx.x,
-                    sstatus: trap_cx.sstatus,
-                    sepc: trap_cx.sepc,
-                    user_satp: trap_cx.user_satp,
-                    kernel_sp: trap_cx.kernel_sp,
-                }
-            };
-
-            drop(task_manager);
-            drop(scheduler);
+        let next_trap_cx_data = {
+            let trap_cx = next_task.get_trap_cx();
+            crate::trap::TrapContext {
+                x: trap_cx.x,
+                sstatus: trap_cx.sstatus,
+                sepc: trap_cx.sepc,
+                user_satp: trap_cx.user_satp,
+                kernel_sp: trap_cx.kernel_sp,
+            }
+        };
 
-            unsafe {
-                let next_trap_cx_kernel = (next_kernel_sp
-                    - core::mem::size_of::<crate::trap::TrapContext>())
-                    as *mut crate::trap::TrapContext;
+        drop(task_manager);
+        drop(scheduler);
 
-                *next_trap_cx_kernel = next_trap_cx_data;
-                (*next_trap_cx_kernel).user_satp = next_user_token;
+        unsafe {
+            let next_trap_cx_kernel = (next_kernel_sp
+                - core::mem::size_of::<crate::trap::TrapContext>())
+                as *mut crate::trap::TrapContext;
 
-                __switch(
-                    curr_ptr as *mut TaskContext,
-                    next_ptr,
-                );
-            }
-        } else {
-            // First task - need to set up trap context and jump to __restore
-            let kernel_sp = task_manager.get_task(next).unwrap().task_cx.sp;
-            let kernel_token = crate::mm::get_kernel_token();
-            
-            let (entry_point, user_sp, user_token, kernel_stack_top) = {
-                let task = task_manager.get_task(next).unwrap();
-                (task.entry_point, task.user_sp, task.get_user_token(), task.task_cx.sp)
-            };
-
-            extern "C" {
-                fn trap_handler();
-            }
+            *next_trap_cx_kernel = next_trap_cx_data;
+            (*next_trap_cx_kernel).user_satp = next_user_token;
 
-            // Disable interrupts during context setup
-            unsafe {
-                use riscv::register::{sie, sstatus};
-                sstatus::clear_sie();
-                sie::clear_stimer();
+            // Coming from the idle context nobody has armed the timer yet
+            if current_pid.is_none() {
+                crate::trap::set_next_timer();
             }
 
-            let mut trap_cx_data = crate::trap::TrapContext::app_init_context(
-                entry_point,
-                user_sp,
-                kernel_token,
-                kernel_stack_top,
-                trap_handler as *const () as usize,
+            __switch(
+                curr_ptr,
+                next_ptr,
             );
-            trap_cx_data.user_satp = user_token;
-            trap_cx_data.kernel_sp = kernel_stack_top;
-
-            // Re-enable timer interrupt
-            unsafe {
-                use riscv::register::{sie, sstatus};
-                sstatus::clear_sie();
-                sie::set_stimer();
+        }
+    } else {
+        // No tasks to run
+        let kernel_token = crate::mm::get_kernel_token();
+        unsafe {
+            use core::arch::asm;
+            asm!("csrw satp, {}", in(reg) kernel_token);
+            if !crate::mm::asid::enabled() {
+                asm!("sfence.vma");
             }
+        }
+    }
+}
 
-            drop(task_manager);
-            drop(scheduler);
+/// Charge a timer tick to the current task
+///
+/// Called on timer interrupts taken in user mode. A task that has used up its
+/// RLIMIT_CPU is killed and this does not return.
+pub fn account_tick() {
+    let mut task_manager = TASK_MANAGER.lock();
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => return,
+    };
+    let task = match task_manager.get_task_mut(current_pid) {
+        Some(task) => task,
+        None => return,
+    };
+    task.cpu_ticks += 1;
+    let limit = task.rlimits.cur(RLIMIT_CPU);
+    if limit != RLIM_INFINITY && task.cpu_ticks >= limit.saturating_mul(TICKS_PER_SEC) {
+        drop(task_manager);
+        println!("[Kernel] pid {} exceeded its CPU time limit ({}s), killed", current_pid, limit);
+        exit_current_and_run_next(CPU_LIMIT_EXIT_CODE);
+    }
+}
 
-            unsafe {
-                use core::arch::asm;
+/// Charge a timer tick to the scheduling policy on behalf of the current task
+///
+/// # Returns
+/// * `true` if the current task should be preempted
+pub fn scheduler_tick() -> bool {
+    let task_manager = TASK_MANAGER.lock();
+    let task = match task_manager.get_current_task() {
+        Some(pid) => task_manager.get_task(pid),
+        None => None,
+    };
+    match task {
+        Some(task) => SCHEDULER.lock().tick(task),
+        None => false,
+    }
+}
 
-                // Switch to kernel address space
-                asm!("csrw satp, {}", in(reg) kernel_token);
-                asm!("sfence.vma");
+/// Add a new task and put it into the run queue
+pub fn add_task(task_manager: &mut TaskManager, task: TaskControlBlock) -> usize {
+    let pid = task_manager.add_task(task);
+    SCHEDULER.lock().enqueue(pid);
+    pid
+}
 
-                // Prepare trap context on kernel stack
-                let trap_cx_size = core::mem::size_of::<crate::trap::TrapContext>();
-                let trap_cx_kernel = (kernel_sp - trap_cx_size) as *mut crate::trap::TrapContext;
+/// Resolve a page fault of the current task
+///
+/// Populates lazy pages on first touch, copies copy-on-write pages on store and
+/// reads swapped out pages back. Frames are reclaimed first if memory runs low.
+///
+/// A valid access that finds no free frame, even after swapping out another
+/// batch of pages, invokes the OOM killer: the largest user task is killed and
+/// the fault retried. If the current task is picked, this does not return.
+///
+/// # Returns
+/// * `true` if the fault was resolved and the access can be retried
+pub fn handle_page_fault(va: usize, is_write: bool) -> bool {
+    let mut task_manager = TASK_MANAGER.lock();
+    // Keep some frames free for page tables and for the page faulted in
+    if swap::enabled() && FRAME_ALLOCATOR.free_frames() < SWAP_LOW_WATERMARK {
+        reclaim_frames(&mut task_manager, SWAP_BATCH);
+    }
+    let current_pid = match task_manager.get_current_task() {
+        Some(pid) => pid,
+        None => return false,
+    };
+    // A fault right below the user stack grows it
+    if let Some(task) = task_manager.get_task_mut(current_pid) {
+        task.grow_stack(va);
+    }
+    let mut reclaimed = false;
+    loop {
+        let result = match task_manager.get_task_mut(current_pid) {
+            Some(task) => task.memory_set.handle_page_fault(va, is_write),
+            None => return false,
+        };
+        match result {
+            Ok(()) => return true,
+            Err(FaultError::AccessViolation) => return false,
+            Err(FaultError::OutOfMemory) => {}
+        }
+
+        // Scattered frames don't help a huge page, so swapping is tried only once
+        if !reclaimed && swap::enabled() {
+            reclaimed = true;
+            if reclaim_frames(&mut task_manager, SWAP_BATCH) > 0 {
+                continue;
+            }
+        }
+        let victim = match select_oom_victim(&task_manager) {
+            Some(pid) => pid,
+            None => return false,
+        };
+        if let Some(task) = task_manager.get_task(victim) {
+            println!(
+                "[OOM] out of memory at va=0x{:x} in pid {}, killing pid {} ({} pages resident)",
+                va,
+                current_pid,
+                victim,
+                task.memory_set.resident_pages()
+            );
+        }
+        if victim == current_pid {
+            drop(task_manager);
+            exit_current_and_run_next(OOM_EXIT_CODE);
+            return false;
+        }
+        exit_task(&mut task_manager, victim, OOM_EXIT_CODE);
+    }
+}
 
-                // Write trap context to kernel stack
-                *trap_cx_kernel = trap_cx_data;
-                (*trap_cx_kernel).user_satp = user_token;
+/// Check if a fault at `va` is an overflow of the current task's user stack
+///
+/// See MemorySet::is_stack_overflow().
+pub fn is_stack_overflow(va: usize) -> bool {
+    let task_manager = TASK_MANAGER.lock();
+    task_manager
+        .get_current_task()
+        .and_then(|pid| task_manager.get_task(pid))
+        .map_or(false, |task| task.memory_set.is_stack_overflow(va))
+}
 
-                // Set the first timer before switching to user mode
-                crate::trap::set_next_timer();
+/// Name the memory a failed instruction fetch of the current task at `va`
+/// hit, if it is mapped but not executable: "stack", "heap" or "memory"
+pub fn non_executable_area(va: usize) -> Option<&'static str> {
+    let task_manager = TASK_MANAGER.lock();
+    let task = task_manager.get_task(task_manager.get_current_task()?)?;
+    let area = task.memory_set.find_area(va)?;
+    let perm = area.map_perm();
+    if !perm.contains(MapPermission::U) || perm.contains(MapPermission::X) {
+        return None;
+    }
+    if area.grows_down() {
+        Some("stack")
+    } else if area.start_va() == task.heap_bottom {
+        Some("heap")
+    } else {
+        Some("memory")
+    }
+}
 
-                // Get __restore's trampoline address from trap module
-                let __restore_trampoline_addr = crate::trap::get_restore_trampoline_addr();
+/// Pick the task for the OOM killer: the user task with the most resident pages
+///
+/// initproc is never picked, since the system shuts down when it exits.
+fn select_oom_victim(task_manager: &TaskManager) -> Option<usize> {
+    (INITPROC_PID + 1..MAX_APP_NUM)
+        .filter_map(|pid| Some((pid, task_manager.get_task(pid)?)))
+        .filter(|(_, task)| task.task_status != TaskStatus::Zombie)
+        .max_by_key(|(_, task)| task.memory_set.resident_pages())
+        .map(|(pid, _)| pid)
+}
 
-                // Jump to __restore at trampoline address
-                // Use a helper function to force correct register assignment
-                jump_to_restore(trap_cx_kernel as usize, __restore_trampoline_addr);
+/// Swap out user pages until `target` frames were released
+///
+/// Continues the clock sweep where the last one stopped, visiting address
+/// spaces in pid order. Gives up after looking at every page twice: the
+/// first look only clears accessed bits.
+///
+/// # Returns
+/// * Number of frames released
+pub fn reclaim_frames(task_manager: &mut TaskManager, target: usize) -> usize {
+    let mut hand = swap::CLOCK_HAND.lock();
+    let (mut pid, mut vpn) = *hand;
+    let mut freed = 0;
+    for _ in 0..=2 * MAX_APP_NUM {
+        if let Some(task) = task_manager.get_task_mut(pid) {
+            let (count, stopped_at) = task.memory_set.swap_out_pages(vpn, target - freed);
+            freed += count;
+            if let Some(stopped_at) = stopped_at {
+                *hand = (pid, stopped_at);
+                return freed;
             }
         }
-    } else {
-        // No tasks to run
-        let kernel_token = crate::mm::get_kernel_token();
-        unsafe {
-            use core::arch::asm;
-            asm!("csrw satp, {}", in(reg) kernel_token);
-            asm!("sfence.vma");
+        pid = (pid + 1) % MAX_APP_NUM;
+        vpn = VirtPageNum::new(0);
+        if freed == target {
+            break;
         }
     }
+    *hand = (pid, vpn);
+    freed
 }
 
 /// Exit current task and run next
-pub fn exit_current_and_run_next(_exit_code: i32) {
+///
+/// The task stays around as a zombie until its parent reaps it with waitpid;
+/// its user address space is released right away and its children are handed
+/// over to initproc. The kernel shuts down when initproc itself exits.
+pub fn exit_current_and_run_next(exit_code: i32) {
     let mut task_manager = TASK_MANAGER.lock();
     let current_pid = task_manager.get_current_task();
 
     if let Some(pid) = current_pid {
-        task_manager.mark_zombie(pid);
-        task_manager.remove_task(pid);
-        task_manager.set_current_task(None);
-
-        // If no more tasks, shutdown (disable timer interrupt first)
-        if task_manager.task_count() == 0 {
+        if pid == INITPROC_PID {
             drop(task_manager);
+            println!("[Kernel] initproc exited with code {}, shutting down...", exit_code);
 
             // Disable timer interrupt before shutdown
             unsafe {
@@ -249,8 +382,32 @@ pub fn exit_current_and_run_next(_exit_code: i32) {
 
             crate::sbi::shutdown(); // shutdown() returns !, so code after this is unreachable
         }
+
+        exit_task(&mut task_manager, pid, exit_code);
+        task_manager.set_current_task(None);
     }
 
     drop(task_manager);
     switch_task();
 }
+
+/// Turn task `pid` into a zombie with `exit_code`
+///
+/// Its user address space is released and its children are handed over to
+/// initproc; without a parent to reap it, the slot is freed right away.
+fn exit_task(task_manager: &mut TaskManager, pid: usize, exit_code: i32) {
+    SCHEDULER.lock().dequeue(pid);
+    task_manager.mark_zombie(pid);
+    let has_parent = match task_manager.get_task_mut(pid) {
+        Some(task) => {
+            task.exit_code = exit_code;
+            task.memory_set.clear_areas();
+            task.parent.is_some()
+        }
+        None => false,
+    };
+    task_manager.reparent_children(pid);
+    if !has_parent {
+        task_manager.remove_task(pid);
+    }
+}
@@ -0,0 +1,91 @@
+//! Resource Limits
+//!
+//! Every task carries a soft (`cur`) and hard (`max`) limit per resource, as
+//! with Linux setrlimit/getrlimit. Limits are inherited across fork and kept
+//! across exec. The enforced ones are:
+//!
+//! - RLIMIT_AS: bytes of address space; mmap and brk fail with ENOMEM beyond it
+//! - RLIMIT_STACK: bytes of user stack; it grows on demand up to this size, and
+//!   exec fails if the initial stack does not fit
+//! - RLIMIT_NOFILE: descriptor numbers (shared memory ids) must stay below it
+//! - RLIMIT_CPU: seconds of CPU time; the task is killed once it is used up
+//!
+//! Other resources can be set and read back but have no effect.
+
+/// Resource numbers (from Linux)
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_AS: usize = 9;
+
+/// Number of resources (same as Linux RLIM_NLIMITS)
+pub const RLIM_NLIMITS: usize = 16;
+
+/// No limit
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Default stack limit (8MB, as on Linux)
+const DEFAULT_STACK_LIMIT: usize = 8 * 1024 * 1024;
+
+/// Default limit on open files
+const DEFAULT_NOFILE_LIMIT: usize = 1024;
+
+/// Soft and hard limit of one resource (same layout as Linux struct rlimit)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RLimit {
+    /// Soft limit: the one enforced
+    pub cur: usize,
+    /// Hard limit: ceiling for the soft limit
+    pub max: usize,
+}
+
+impl RLimit {
+    /// Soft and hard limit set to the same value
+    pub const fn fixed(limit: usize) -> Self {
+        Self { cur: limit, max: limit }
+    }
+}
+
+/// Limits of one task, indexed by resource number
+#[derive(Copy, Clone)]
+pub struct ResourceLimits {
+    limits: [RLimit; RLIM_NLIMITS],
+}
+
+impl ResourceLimits {
+    /// Limits of the first task: unlimited except for stack and open files
+    pub fn new() -> Self {
+        let mut limits = [RLimit::fixed(RLIM_INFINITY); RLIM_NLIMITS];
+        limits[RLIMIT_STACK] = RLimit { cur: DEFAULT_STACK_LIMIT, max: RLIM_INFINITY };
+        limits[RLIMIT_NOFILE] = RLimit::fixed(DEFAULT_NOFILE_LIMIT);
+        Self { limits }
+    }
+
+    /// Get the limits of `resource` (None if there is no such resource)
+    pub fn get(&self, resource: usize) -> Option<RLimit> {
+        self.limits.get(resource).copied()
+    }
+
+    /// Soft limit of `resource`
+    pub fn cur(&self, resource: usize) -> usize {
+        self.limits[resource].cur
+    }
+
+    /// Replace the limits of `resource`
+    ///
+    /// # Returns
+    /// * `Err` if there is no such resource, the soft limit exceeds the hard
+    ///   one, or the hard limit would be raised
+    pub fn set(&mut self, resource: usize, limit: RLimit) -> Result<(), &'static str> {
+        let old = self.limits.get_mut(resource).ok_or("No such resource")?;
+        if limit.cur > limit.max {
+            return Err("Soft limit above hard limit");
+        }
+        if limit.max > old.max {
+            return Err("Hard limit cannot be raised");
+        }
+        *old = limit;
+        Ok(())
+    }
+}
@@ -1,39 +1,438 @@
 //! Scheduler
-//! 
-//! Implements round-robin scheduling
+//!
+//! Scheduling decisions are made by a SchedPolicy picked at boot with the
+//! boot argument `sched=<name>` (default: crate::config::DEFAULT_SCHED_POLICY):
+//!
+//! - `rr`: round-robin with a fixed time slice
+//! - `stride`: stride scheduling, CPU shares proportional to priorities
+//! - `mlfq`: multi-level feedback queue, tasks that yield or block run ahead
+//!   of CPU hogs
+//! - `cfs`: CFS-like, the task with the least weighted runtime runs next
+//!
+//! Ready tasks sit in the policy's run queue. A task is enqueued when it is
+//! created and whenever it gives up the CPU without exiting; pick_next()
+//! takes the task it returns out of the queue, so the running task is never
+//! queued.
 
 use super::manager::TaskManager;
+use super::task::TaskControlBlock;
+use super::TaskStatus;
+use crate::config::{DEFAULT_SCHED_POLICY, MAX_APP_NUM};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 
+/// Ticks a task runs before it is preempted (100ms at 10ms per tick), under
+/// the policies with fixed time slices
+pub const TIME_SLICE: usize = 10;
+
+/// Stride of a task with priority 1; strides of all priorities divide it down
+pub const BIG_STRIDE: usize = 0x10_0000;
+
+/// Priority of boot-loaded tasks (children inherit their parent's)
+pub const DEFAULT_PRIORITY: usize = 16;
+
+/// Lowest priority sys_set_priority accepts
+///
+/// Keeps every stride at most BIG_STRIDE / 2, so that passes of ready tasks
+/// never drift more than half the usize range apart and can be compared
+/// across overflow.
+pub const MIN_PRIORITY: usize = 2;
+
+/// Highest priority sys_set_priority accepts
+///
+/// Keeps every stride at least 1, so that a running task's pass always
+/// advances and it cannot win every pick.
+pub const MAX_PRIORITY: usize = BIG_STRIDE;
+
+/// Number of MLFQ levels; new tasks start at level 0, the highest
+pub const MLFQ_LEVELS: usize = 4;
+
+/// Time slice of each MLFQ level, in ticks: short at the top, where tasks
+/// that yield often stay, and long at the bottom, where CPU hogs end up
+pub const MLFQ_TIME_SLICES: [usize; MLFQ_LEVELS] = [2, 4, 8, 16];
+
+/// Ticks between two MLFQ priority boosts (1s), which lift every task back
+/// to the top so that CPU hogs are not starved for good
+pub const MLFQ_BOOST_INTERVAL: usize = 100;
+
+/// Virtual runtime a tick adds to a CFS task of DEFAULT_PRIORITY
+const CFS_TICK_VRUNTIME: usize = 1024;
+
+/// Period in which every ready CFS task should get to run, in ticks
+const CFS_SCHED_LATENCY: usize = 20;
+
+/// Shortest CFS time slice, in ticks
+const CFS_MIN_GRANULARITY: usize = 2;
+
+/// A scheduling policy
+///
+/// Policies only deal in pids. A pid is valid from the enqueue() that brings
+/// a new task in until the dequeue() after it exits; the slot may then be
+/// reused by another task.
+pub trait SchedPolicy: Send {
+    /// Name accepted by the `sched=` boot argument
+    fn name(&self) -> &'static str;
+
+    /// Put task `pid` into the run queue
+    ///
+    /// Called for new tasks, and for the running task when it gives up the
+    /// CPU (its time slice expired or it yielded).
+    fn enqueue(&mut self, pid: usize);
+
+    /// Forget task `pid`, which has exited
+    fn dequeue(&mut self, pid: usize);
+
+    /// Take the task to run next out of the run queue
+    fn pick_next(&mut self, task_manager: &mut TaskManager) -> Option<usize>;
+
+    /// Charge a timer tick to the running task
+    ///
+    /// # Returns
+    /// * `true` if the task should be preempted
+    fn tick(&mut self, task: &TaskControlBlock) -> bool;
+}
+
+/// Create the policy named `name` ("rr", "stride", "mlfq" or "cfs")
+pub fn policy_by_name(name: &str) -> Option<Box<dyn SchedPolicy>> {
+    match name {
+        "rr" => Some(Box::new(RoundRobin::new())),
+        "stride" => Some(Box::new(Stride::new())),
+        "mlfq" => Some(Box::new(Mlfq::new())),
+        "cfs" => Some(Box::new(Cfs::new())),
+        _ => None,
+    }
+}
+
+/// Remove `pid` from a run queue, if it is there
+fn remove_pid(queue: &mut VecDeque<usize>, pid: usize) {
+    queue.retain(|&queued| queued != pid);
+}
+
+/// Round-robin: ready tasks take turns in FIFO order
+struct RoundRobin {
+    queue: VecDeque<usize>,
+    /// Ticks the running task has used of its time slice
+    ticks: usize,
+}
+
+impl RoundRobin {
+    fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            ticks: 0,
+        }
+    }
+}
+
+impl SchedPolicy for RoundRobin {
+    fn name(&self) -> &'static str {
+        "rr"
+    }
+
+    fn enqueue(&mut self, pid: usize) {
+        self.queue.push_back(pid);
+    }
+
+    fn dequeue(&mut self, pid: usize) {
+        remove_pid(&mut self.queue, pid);
+    }
+
+    fn pick_next(&mut self, _task_manager: &mut TaskManager) -> Option<usize> {
+        self.ticks = 0;
+        self.queue.pop_front()
+    }
+
+    fn tick(&mut self, _task: &TaskControlBlock) -> bool {
+        self.ticks += 1;
+        self.ticks >= TIME_SLICE
+    }
+}
+
+/// Check if pass `a` is behind pass `b`, allowing for overflow
+fn pass_before(a: usize, b: usize) -> bool {
+    (a.wrapping_sub(b) as isize) < 0
+}
+
+/// Stride scheduling
+///
+/// Every task has a priority and a pass value (kept in its TCB, so children
+/// inherit them). The ready task with the smallest pass runs next, and its
+/// pass then grows by its stride, BIG_STRIDE / priority. Over time each task
+/// gets a share of the CPU proportional to its priority. Tasks with equal
+/// passes take turns in FIFO order.
+struct Stride {
+    queue: VecDeque<usize>,
+    /// Ticks the running task has used of its time slice
+    ticks: usize,
+}
+
+impl Stride {
+    fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            ticks: 0,
+        }
+    }
+}
+
+impl SchedPolicy for Stride {
+    fn name(&self) -> &'static str {
+        "stride"
+    }
+
+    fn enqueue(&mut self, pid: usize) {
+        self.queue.push_back(pid);
+    }
+
+    fn dequeue(&mut self, pid: usize) {
+        remove_pid(&mut self.queue, pid);
+    }
+
+    /// Pick the ready task with the smallest pass and charge it one stride
+    fn pick_next(&mut self, task_manager: &mut TaskManager) -> Option<usize> {
+        self.ticks = 0;
+        let mut next: Option<(usize, usize)> = None;
+        for (index, &pid) in self.queue.iter().enumerate() {
+            let pass = match task_manager.get_task(pid) {
+                Some(task) => task.pass,
+                None => continue,
+            };
+            match next {
+                Some((_, best)) if !pass_before(pass, best) => {}
+                _ => next = Some((index, pass)),
+            }
+        }
+        let pid = self.queue.remove(next?.0)?;
+        let task = task_manager.get_task_mut(pid)?;
+        task.pass = task.pass.wrapping_add(BIG_STRIDE / task.priority);
+        Some(pid)
+    }
+
+    fn tick(&mut self, _task: &TaskControlBlock) -> bool {
+        self.ticks += 1;
+        self.ticks >= TIME_SLICE
+    }
+}
+
+/// Multi-level feedback queue
+///
+/// One FIFO run queue per level; the highest non-empty level runs first, for
+/// the time slice of that level. New tasks start at the top. A task that uses
+/// up its full time slice drops a level, and one that gives up the CPU early
+/// (it yielded, or waits for a child) rises a level. Every
+/// MLFQ_BOOST_INTERVAL ticks all tasks go back to the top.
+struct Mlfq {
+    queues: [VecDeque<usize>; MLFQ_LEVELS],
+    /// Current level of each task, by pid
+    level: [usize; MAX_APP_NUM],
+    /// The task picked last, until it is enqueued again or exits
+    running: Option<usize>,
+    /// Ticks the running task has used of its time slice
+    ticks: usize,
+    /// Ticks since the last priority boost
+    since_boost: usize,
+}
+
+impl Mlfq {
+    fn new() -> Self {
+        Self {
+            queues: Default::default(),
+            level: [0; MAX_APP_NUM],
+            running: None,
+            ticks: 0,
+            since_boost: 0,
+        }
+    }
+
+    /// Move every task to the top level, keeping their order
+    fn boost(&mut self) {
+        let (top, lower) = self.queues.split_at_mut(1);
+        for queue in lower.iter_mut() {
+            top[0].append(queue);
+        }
+        self.level = [0; MAX_APP_NUM];
+    }
+}
+
+impl SchedPolicy for Mlfq {
+    fn name(&self) -> &'static str {
+        "mlfq"
+    }
+
+    /// New tasks join their level's queue as they are; the running task first
+    /// drops a level if it used up its time slice and rises one otherwise
+    fn enqueue(&mut self, pid: usize) {
+        if self.running == Some(pid) {
+            self.running = None;
+            let level = &mut self.level[pid];
+            if self.ticks >= MLFQ_TIME_SLICES[*level] {
+                *level = (*level + 1).min(MLFQ_LEVELS - 1);
+            } else {
+                *level = level.saturating_sub(1);
+            }
+        }
+        self.queues[self.level[pid]].push_back(pid);
+    }
+
+    fn dequeue(&mut self, pid: usize) {
+        for queue in self.queues.iter_mut() {
+            remove_pid(queue, pid);
+        }
+        self.level[pid] = 0;
+        if self.running == Some(pid) {
+            self.running = None;
+        }
+    }
+
+    fn pick_next(&mut self, _task_manager: &mut TaskManager) -> Option<usize> {
+        self.ticks = 0;
+        self.running = self.queues.iter_mut().find_map(|queue| queue.pop_front());
+        self.running
+    }
+
+    fn tick(&mut self, task: &TaskControlBlock) -> bool {
+        self.since_boost += 1;
+        if self.since_boost >= MLFQ_BOOST_INTERVAL {
+            // The running task gets a fresh time slice at the top as well
+            self.since_boost = 0;
+            self.boost();
+            self.ticks = 0;
+            return false;
+        }
+        self.ticks += 1;
+        self.ticks >= MLFQ_TIME_SLICES[self.level[task.pid]]
+    }
+}
+
+/// CFS-like fair scheduling
+///
+/// Each task accumulates virtual runtime, the ticks it ran weighted inversely
+/// by its priority. The ready task with the least virtual runtime runs next,
+/// for a slice of CFS_SCHED_LATENCY shared among the ready tasks (at least
+/// CFS_MIN_GRANULARITY). New tasks start at the smallest virtual runtime
+/// around, so they neither starve the others nor wait behind them.
+struct Cfs {
+    queue: VecDeque<usize>,
+    /// Virtual runtime of each task, by pid (None until it is first enqueued)
+    vruntime: [Option<usize>; MAX_APP_NUM],
+    /// Virtual runtime of the task picked last; never goes backwards
+    min_vruntime: usize,
+    /// Ticks the running task has used of its time slice
+    ticks: usize,
+    /// Length of the running task's time slice, in ticks
+    slice: usize,
+}
+
+impl Cfs {
+    fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            vruntime: [None; MAX_APP_NUM],
+            min_vruntime: 0,
+            ticks: 0,
+            slice: CFS_SCHED_LATENCY,
+        }
+    }
+}
+
+impl SchedPolicy for Cfs {
+    fn name(&self) -> &'static str {
+        "cfs"
+    }
+
+    fn enqueue(&mut self, pid: usize) {
+        self.vruntime[pid].get_or_insert(self.min_vruntime);
+        self.queue.push_back(pid);
+    }
+
+    fn dequeue(&mut self, pid: usize) {
+        remove_pid(&mut self.queue, pid);
+        self.vruntime[pid] = None;
+    }
+
+    /// Pick the ready task with the least virtual runtime
+    fn pick_next(&mut self, _task_manager: &mut TaskManager) -> Option<usize> {
+        let vruntime = &self.vruntime;
+        let (index, _) = self
+            .queue
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, &pid)| vruntime[pid].unwrap_or(0))?;
+        let pid = self.queue.remove(index)?;
+        self.min_vruntime = self.min_vruntime.max(self.vruntime[pid].unwrap_or(0));
+        self.ticks = 0;
+        self.slice = (CFS_SCHED_LATENCY / (self.queue.len() + 1)).max(CFS_MIN_GRANULARITY);
+        Some(pid)
+    }
+
+    fn tick(&mut self, task: &TaskControlBlock) -> bool {
+        let vruntime = self.vruntime[task.pid].get_or_insert(self.min_vruntime);
+        // At least 1, so that a task of a very high priority still advances
+        *vruntime += (CFS_TICK_VRUNTIME * DEFAULT_PRIORITY / task.priority).max(1);
+        self.ticks += 1;
+        self.ticks >= self.slice
+    }
+}
+
+/// The scheduler: run queue operations on the policy selected at boot
 pub struct Scheduler {
-    time_slice: usize,
-    current_time_slice: usize,
+    policy: Box<dyn SchedPolicy>,
 }
 
 impl Scheduler {
     pub fn new() -> Self {
         Self {
-            time_slice: 10, // 10 ticks per time slice (100ms at 10ms per tick)
-            current_time_slice: 0,
+            policy: policy_by_name(DEFAULT_SCHED_POLICY).expect("Unknown default scheduling policy"),
         }
     }
-    
-    pub fn schedule_next(&mut self, current: usize, task_manager: &TaskManager) -> Option<usize> {
-        // Round-robin: find next ready task
-        task_manager.find_next_task(current)
+
+    /// Switch to the policy named `name`
+    ///
+    /// Must happen before any task is enqueued: tasks queued so far are lost.
+    ///
+    /// # Returns
+    /// * `false` if there is no such policy
+    pub fn set_policy(&mut self, name: &str) -> bool {
+        match policy_by_name(name) {
+            Some(policy) => {
+                self.policy = policy;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Name of the policy in use
+    pub fn policy_name(&self) -> &'static str {
+        self.policy.name()
+    }
+
+    /// See SchedPolicy::enqueue()
+    pub fn enqueue(&mut self, pid: usize) {
+        self.policy.enqueue(pid);
+    }
+
+    /// See SchedPolicy::dequeue()
+    pub fn dequeue(&mut self, pid: usize) {
+        self.policy.dequeue(pid);
     }
-    
-    pub fn tick(&mut self) -> bool {
-        self.current_time_slice += 1;
-        if self.current_time_slice >= self.time_slice {
-            self.current_time_slice = 0;
-            true // Time slice expired, need to switch
-        } else {
-            false
+
+    /// Take the next task to run out of the run queue
+    ///
+    /// Tasks that are not ready any more are skipped.
+    pub fn pick_next(&mut self, task_manager: &mut TaskManager) -> Option<usize> {
+        loop {
+            let pid = self.policy.pick_next(task_manager)?;
+            if let Some(task) = task_manager.get_task(pid) {
+                if task.task_status == TaskStatus::Ready {
+                    return Some(pid);
+                }
+            }
         }
     }
-    
-    pub fn reset_time_slice(&mut self) {
-        self.current_time_slice = 0;
+
+    /// See SchedPolicy::tick()
+    pub fn tick(&mut self, task: &TaskControlBlock) -> bool {
+        self.policy.tick(task)
     }
 }
-
@@ -3,10 +3,14 @@
 //! Defines the structure and operations for tasks (processes)
 
 use super::context::TaskContext;
+use super::rlimit::{ResourceLimits, RLIMIT_AS, RLIMIT_STACK, RLIM_INFINITY};
+use super::scheduler::DEFAULT_PRIORITY;
 use crate::config::memory_layout::{KERNEL_STACK_SIZE, PAGE_SIZE};
+use crate::config::MAX_APP_NUM;
 use crate::mm::memory_layout::PhysPageNum;
 use crate::mm::MemorySet;
 use crate::trap::TrapContext;
+use alloc::vec::Vec;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum TaskStatus {
@@ -16,6 +20,14 @@ pub enum TaskStatus {
 }
 
 pub struct TaskControlBlock {
+    /// Process id (also the slot index in TaskManager and kernel stack index)
+    pub pid: usize,
+    /// Parent process id (None only for initproc)
+    pub parent: Option<usize>,
+    /// Process ids of children that have not been reaped yet
+    pub children: Vec<usize>,
+    /// Exit code, valid once the task becomes a zombie
+    pub exit_code: i32,
     pub task_status: TaskStatus,
     pub task_cx: TaskContext,
     pub memory_set: MemorySet,
@@ -26,6 +38,16 @@ pub struct TaskControlBlock {
     // Store entry point and user stack for trap context initialization
     pub entry_point: usize,
     pub user_sp: usize,
+    /// Resource limits (setrlimit/getrlimit)
+    pub rlimits: ResourceLimits,
+    /// Timer ticks spent running in user mode (checked against RLIMIT_CPU)
+    pub cpu_ticks: usize,
+    /// Scheduling priority (at least 2): the CPU share is proportional to it
+    /// under the stride and cfs policies
+    pub priority: usize,
+    /// Stride scheduling pass: advanced by BIG_STRIDE / priority whenever the
+    /// task is picked; the ready task with the smallest pass runs next
+    pub pass: usize,
 }
 
 impl TaskControlBlock {
@@ -56,14 +78,51 @@ impl TaskControlBlock {
         unsafe { &mut *(kernel_va as *mut TrapContext) }
     }
 
-    /// Get user token (satp value)
+    /// Get user token (satp value) to enter this task's address space with
+    ///
+    /// May assign a new ASID, so only call this for the task that is about to
+    /// run (see mm::asid).
     pub fn get_user_token(&self) -> usize {
-        self.memory_set.token()
+        self.memory_set.asid_token()
+    }
+
+    /// Check that growing the address space by `bytes` stays within RLIMIT_AS
+    pub fn within_as_limit(&self, bytes: usize) -> bool {
+        let limit = self.rlimits.cur(RLIMIT_AS);
+        limit == RLIM_INFINITY
+            || self
+                .memory_set
+                .virtual_pages()
+                .saturating_mul(PAGE_SIZE)
+                .saturating_add(bytes)
+                <= limit
+    }
+
+    /// Grow the user stack down so that it covers `va`, within RLIMIT_STACK
+    /// and RLIMIT_AS
+    pub fn grow_stack(&mut self, va: usize) -> bool {
+        let max_size = self.rlimits.cur(RLIMIT_STACK);
+        let max_growth = self
+            .rlimits
+            .cur(RLIMIT_AS)
+            .saturating_sub(self.memory_set.virtual_pages().saturating_mul(PAGE_SIZE));
+        self.memory_set.grow_stack(va, max_size, max_growth)
+    }
+
+    /// Get the trap context saved on this task's kernel stack
+    ///
+    /// This is the copy that __restore uses when the task returns to user mode
+    /// (allocate_trap_context puts it right below the kernel stack top).
+    pub fn get_kernel_stack_trap_cx(&self) -> &'static mut TrapContext {
+        let (_kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(self.pid);
+        let trap_cx_kernel = kernel_stack_top - core::mem::size_of::<TrapContext>();
+        unsafe { &mut *(trap_cx_kernel as *mut TrapContext) }
     }
 
     /// Create a new task from ELF data
-    pub fn new(elf_data: &[u8], app_id: usize) -> Self {
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+    pub fn new(elf_data: &[u8], pid: usize) -> Self {
+        let (memory_set, user_sp, entry_point) =
+            MemorySet::from_elf(elf_data).unwrap_or_else(|err| panic!("Failed to load an app: {}", err));
 
         // Verify entry point page is mapped
         use crate::mm::memory_layout::VirtAddr;
@@ -99,10 +158,14 @@ impl TaskControlBlock {
         }
 
         let task_status = TaskStatus::Ready;
-        let (_kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(app_id);
+        let (_kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
         let task_cx = TaskContext::goto_trap_return(kernel_stack_top);
 
         let tcb = Self {
+            pid,
+            parent: None,
+            children: Vec::new(),
+            exit_code: 0,
             task_status,
             task_cx,
             memory_set,
@@ -112,11 +175,15 @@ impl TaskControlBlock {
             program_brk: user_sp,
             entry_point,
             user_sp,
+            rlimits: ResourceLimits::new(),
+            cpu_ticks: 0,
+            priority: DEFAULT_PRIORITY,
+            pass: 0,
         };
 
         // Initialize trap context with user_satp
         let trap_cx = tcb.get_trap_cx();
-        let user_token = tcb.get_user_token();
+        let user_token = tcb.memory_set.token();
         *trap_cx = TrapContext::app_init_context(
             entry_point,
             user_sp,
@@ -132,24 +199,138 @@ impl TaskControlBlock {
 
         tcb
     }
+
+    /// Create a child task sharing this task's address space copy-on-write (fork)
+    ///
+    /// The child resumes at the same user pc as the parent, with a0 = 0.
+    ///
+    /// # Returns
+    /// * `Err` if memory runs out while copying the address space
+    pub fn fork(&mut self, child_pid: usize) -> Result<Self, &'static str> {
+        let memory_set = self.memory_set.clone()?;
+        let trap_cx_pa = memory_set
+            .translate(TRAP_CONTEXT)
+            .expect("Failed to translate TRAP_CONTEXT address");
+        let trap_cx_ppn = PhysPageNum::new(trap_cx_pa >> PAGE_SIZE.trailing_zeros() as usize);
+
+        let (_kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(child_pid);
+        let task_cx = TaskContext::goto_trap_return(kernel_stack_top);
+
+        let tcb = Self {
+            pid: child_pid,
+            parent: Some(self.pid),
+            children: Vec::new(),
+            exit_code: 0,
+            task_status: TaskStatus::Ready,
+            task_cx,
+            memory_set,
+            trap_cx_ppn,
+            base_size: self.base_size,
+            heap_bottom: self.heap_bottom,
+            program_brk: self.program_brk,
+            entry_point: self.entry_point,
+            user_sp: self.user_sp,
+            // Limits are inherited, CPU time starts over
+            rlimits: self.rlimits,
+            cpu_ticks: 0,
+            // Starting at the parent's pass, the child neither jumps the queue nor waits behind it
+            priority: self.priority,
+            pass: self.pass,
+        };
+
+        // The parent is inside a syscall, so its live user context is the one
+        // on its kernel stack (sepc already points past the ecall)
+        let parent_trap_cx = self.get_kernel_stack_trap_cx();
+        let trap_cx = tcb.get_trap_cx();
+        *trap_cx = TrapContext {
+            x: parent_trap_cx.x,
+            sstatus: parent_trap_cx.sstatus,
+            sepc: parent_trap_cx.sepc,
+            // Replaced by switch_task once the child is scheduled
+            user_satp: tcb.memory_set.token(),
+            kernel_sp: kernel_stack_top,
+        };
+        // fork() returns 0 in the child
+        trap_cx.x[10] = 0;
+
+        Ok(tcb)
+    }
+
+    /// Replace this task's address space with a new ELF image (exec)
+    ///
+    /// The new user context is written both to the trap context page and to the
+    /// kernel stack, so the task enters the new program when the syscall returns.
+    ///
+    /// # Returns
+    /// * `Err` if memory runs out while building the new address space or the
+    ///   image breaks the W^X policy; the task keeps running its old program then
+    pub fn exec(&mut self, elf_data: &[u8]) -> Result<(), &'static str> {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data)?;
+        let trap_cx_pa = memory_set
+            .translate(TRAP_CONTEXT)
+            .expect("Failed to translate TRAP_CONTEXT address");
+
+        // Dropping the old MemorySet releases all of its frames
+        self.memory_set = memory_set;
+        self.trap_cx_ppn = PhysPageNum::new(trap_cx_pa >> PAGE_SIZE.trailing_zeros() as usize);
+        self.base_size = user_sp;
+        self.heap_bottom = user_sp;
+        self.program_brk = user_sp;
+        self.entry_point = entry_point;
+        self.user_sp = user_sp;
+
+        let (_kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(self.pid);
+        let user_token = self.get_user_token();
+        let trap_cx = self.get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            0, // kernel_satp - not used
+            kernel_stack_top,
+            trap_handler as *const () as usize,
+        );
+        trap_cx.user_satp = user_token;
+        trap_cx.kernel_sp = kernel_stack_top;
+
+        let kernel_stack_trap_cx = self.get_kernel_stack_trap_cx();
+        *kernel_stack_trap_cx = TrapContext {
+            x: trap_cx.x,
+            sstatus: trap_cx.sstatus,
+            sepc: trap_cx.sepc,
+            user_satp: trap_cx.user_satp,
+            kernel_sp: trap_cx.kernel_sp,
+        };
+        Ok(())
+    }
 }
 
-/// Get kernel stack position for app
+/// Get kernel stack position for a task
 /// Kernel stacks are allocated below TRAP_CONTEXT
 /// TRAP_CONTEXT is at TRAMPOLINE - PAGE_SIZE
 /// So we allocate stacks starting from TRAP_CONTEXT - PAGE_SIZE
-pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
+pub fn kernel_stack_position(pid: usize) -> (usize, usize) {
     // Start from TRAP_CONTEXT - PAGE_SIZE and go down
     // Each stack needs KERNEL_STACK_SIZE + PAGE_SIZE (guard page)
     // Note: We start from TRAP_CONTEXT - PAGE_SIZE (not TRAP_CONTEXT) to leave space for trap context
     let top = TRAP_CONTEXT
         .wrapping_sub(PAGE_SIZE)
-        .wrapping_sub(app_id * (KERNEL_STACK_SIZE + PAGE_SIZE));
+        .wrapping_sub(pid * (KERNEL_STACK_SIZE + PAGE_SIZE));
     let bottom = top.wrapping_sub(KERNEL_STACK_SIZE);
 
     (bottom, top)
 }
 
+/// Find the task whose kernel stack guard page contains `addr`
+///
+/// The guard page right below each kernel stack is left unmapped, so a
+/// kernel stack overflow faults there.
+pub fn kernel_stack_guard_owner(addr: usize) -> Option<usize> {
+    (0..MAX_APP_NUM).find(|&pid| {
+        let (bottom, _top) = kernel_stack_position(pid);
+        bottom.wrapping_sub(PAGE_SIZE) <= addr && addr < bottom
+    })
+}
+
 const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 
@@ -17,6 +17,11 @@ pub fn init() {
         static mut KERNEL_SATP: usize;
         static mut TRAP_HANDLER_KERNEL_ADDR: usize;
         static mut RESTORE_TRAMPOLINE_ADDR: usize;
+        static mut OVERFLOW_STACK_TOP_ADDR: usize;
+        static mut KERNEL_STACKS_TOP: usize;
+        static mut KERNEL_STACK_STRIDE: usize;
+        static mut KERNEL_STACK_GUARD_SIZE: usize;
+        fn kernel_overflow_stack_top();
     }
 
     // CRITICAL: Set KERNEL_SATP so that __alltraps can switch back to kernel address space
@@ -35,6 +40,16 @@ pub fn init() {
         let restore_offset = __restore as usize - strampoline as usize;
         let restore_trampoline = crate::config::TRAMPOLINE + restore_offset;
         RESTORE_TRAMPOLINE_ADDR = restore_trampoline;
+
+        // __alltraps switches to this stack when the kernel stack overflows
+        OVERFLOW_STACK_TOP_ADDR = kernel_overflow_stack_top as *const () as usize;
+
+        // __alltraps tells a kernel stack overflow from other page faults by these
+        let (stack0_bottom, stack0_top) = crate::task::kernel_stack_position(0);
+        let (_, stack1_top) = crate::task::kernel_stack_position(1);
+        KERNEL_STACKS_TOP = stack0_top;
+        KERNEL_STACK_STRIDE = stack0_top - stack1_top;
+        KERNEL_STACK_GUARD_SIZE = KERNEL_STACK_STRIDE - (stack0_top - stack0_bottom);
     }
 
     // CRITICAL: Set sscratch to current kernel stack pointer
@@ -107,20 +122,13 @@ pub fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
             set_next_timer();
             
             if is_user_mode {
+                // The tick is charged to the task it interrupted
+                crate::task::account_tick();
+
                 // User mode interrupt: can trigger preemptive scheduling
-                let task_manager = crate::task::TASK_MANAGER.lock();
-                let task_count = task_manager.task_count();
-                drop(task_manager);
-                
-                if task_count > 0 {
-                    let mut scheduler = crate::task::SCHEDULER.lock();
-                    if scheduler.tick() {
-                        // Time slice expired, switch task
-                        drop(scheduler);
-                        crate::task::switch_task();
-                    } else {
-                        drop(scheduler);
-                    }
+                if crate::task::scheduler_tick() {
+                    // Time slice expired, switch task
+                    crate::task::switch_task();
                 }
             } else {
                 // Kernel mode interrupt: do NOT trigger task switching
@@ -139,27 +147,66 @@ pub fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
         scause::Trap::Exception(scause::Exception::StoreFault)
         | scause::Trap::Exception(scause::Exception::StorePageFault) => {
             if is_user_mode {
-                println!("[Trap] Store fault in user: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc);
+                // A store to a lazy or copy-on-write page: fix it up and retry the instruction
+                let is_page_fault = matches!(
+                    scause.cause(),
+                    scause::Trap::Exception(scause::Exception::StorePageFault)
+                );
+                if is_page_fault && crate::task::handle_page_fault(stval, true) {
+                    return cx;
+                }
+                if !(is_page_fault && report_stack_overflow(stval, cx.sepc, true)) {
+                    println!("[Trap] Store fault in user: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc);
+                }
                 crate::task::exit_current_and_run_next(-1);
             } else {
-                println!("[Trap] Store fault in kernel: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc);
+                if !report_stack_overflow(stval, cx.sepc, false) {
+                    println!("[Trap] Store fault in kernel: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc);
+                }
                 crate::sbi::shutdown();
             }
         }
         scause::Trap::Exception(scause::Exception::LoadFault)
         | scause::Trap::Exception(scause::Exception::LoadPageFault) => {
             if is_user_mode {
-                println!("[Trap] Load fault in user: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc);
+                // First read of a lazy page: populate it and retry the instruction
+                let is_page_fault = matches!(
+                    scause.cause(),
+                    scause::Trap::Exception(scause::Exception::LoadPageFault)
+                );
+                if is_page_fault && crate::task::handle_page_fault(stval, false) {
+                    return cx;
+                }
+                if !(is_page_fault && report_stack_overflow(stval, cx.sepc, true)) {
+                    println!("[Trap] Load fault in user: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc);
+                }
                 crate::task::exit_current_and_run_next(-1);
             } else {
-                println!("[Trap] Load fault in kernel: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc);
+                if !report_stack_overflow(stval, cx.sepc, false) {
+                    println!("[Trap] Load fault in kernel: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc);
+                }
                 crate::sbi::shutdown();
             }
         }
         scause::Trap::Exception(scause::Exception::InstructionFault)
         | scause::Trap::Exception(scause::Exception::InstructionPageFault) => {
             if is_user_mode {
-                println!("[Trap] Instruction fault in user: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc);
+                // Code pages may have been swapped out: read them back and retry
+                let is_page_fault = matches!(
+                    scause.cause(),
+                    scause::Trap::Exception(scause::Exception::InstructionPageFault)
+                );
+                if is_page_fault && crate::task::handle_page_fault(stval, false) {
+                    return cx;
+                }
+                // Jumping into data (stack, heap, W^X-stripped segments) gets its own report
+                match crate::task::non_executable_area(stval).filter(|_| is_page_fault) {
+                    Some(area) => println!(
+                        "[Trap] Instruction fetch from non-executable {} in user: stval=0x{:x}, sepc=0x{:x}",
+                        area, stval, cx.sepc
+                    ),
+                    None => println!("[Trap] Instruction fault in user: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc),
+                }
                 crate::task::exit_current_and_run_next(-1);
             } else {
                 println!("[Trap] Instruction fault in kernel: stval=0x{:x}, sepc=0x{:x}", stval, cx.sepc);
@@ -188,6 +235,32 @@ pub fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
     cx
 }
 
+/// Report a fault at `stval` as a stack overflow if it hit the space below a stack
+///
+/// For user mode that is the guard page or the room the stack may not grow
+/// into (RLIMIT_STACK); for kernel mode the guard page of a kernel stack.
+///
+/// # Returns
+/// * `true` if the fault was a stack overflow and has been reported
+fn report_stack_overflow(stval: usize, sepc: usize, is_user_mode: bool) -> bool {
+    if is_user_mode {
+        if !crate::task::is_stack_overflow(stval) {
+            return false;
+        }
+        println!("[Trap] Stack overflow in user: stval=0x{:x}, sepc=0x{:x}", stval, sepc);
+    } else {
+        let pid = match crate::task::kernel_stack_guard_owner(stval) {
+            Some(pid) => pid,
+            None => return false,
+        };
+        println!(
+            "[Trap] Kernel stack overflow of pid {}: stval=0x{:x}, sepc=0x{:x}",
+            pid, stval, sepc
+        );
+    }
+    true
+}
+
 /// Enable timer interrupt (should be called after tasks are loaded)
 /// NOTE: This function only enables timer interrupt in sie, but does NOT set the timer
 /// The timer should be set in switch_task() right before switching to user mode
@@ -212,14 +285,14 @@ pub fn enable_timer_interrupt() {
 /// This function should be called right before switching to user mode for the first time,
 /// and also in trap_handler after handling timer interrupts
 pub fn set_next_timer() {
-    use crate::config::CLOCK_FREQ;
+    use crate::config::{CLOCK_FREQ, TICKS_PER_SEC};
     use crate::sbi;
 
     let time = sbi::get_time();
 
-    // Set timer to 10ms intervals (CLOCK_FREQ / 100 = 10ms)
+    // Set timer to 10ms intervals (CLOCK_FREQ / TICKS_PER_SEC = 10ms)
     // This gives us 100 ticks per second, and with time_slice=10, each task gets 100ms
-    let next = time + (CLOCK_FREQ / 100) as u64; // 10ms
+    let next = time + (CLOCK_FREQ / TICKS_PER_SEC) as u64; // 10ms
     sbi::set_timer(next);
 }
 
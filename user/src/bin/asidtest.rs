@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::ptr::addr_of_mut;
+use user_lib::{sys_exit, sys_fork, sys_getpid, sys_yield, waitpid};
+
+const CHILDREN: usize = 8;
+const ROUNDS: usize = 50;
+
+/// Same virtual page in every process, different frames after the first store
+static mut SLOT: [usize; 512] = [0; 512];
+
+fn read(i: usize) -> usize {
+    unsafe { (addr_of_mut!(SLOT) as *const usize).add(i).read_volatile() }
+}
+
+fn write(i: usize, value: usize) {
+    unsafe { (addr_of_mut!(SLOT) as *mut usize).add(i).write_volatile(value) }
+}
+
+#[no_mangle]
+fn main() {
+    println!("asidtest begin");
+
+    let mut pids = [0isize; CHILDREN];
+    for pid in pids.iter_mut() {
+        *pid = sys_fork();
+        if *pid == 0 {
+            // Switch away and back many times; a stale translation from
+            // another address space would show up as a foreign value
+            let me = sys_getpid() as usize;
+            for round in 0..ROUNDS {
+                write(round % 512, me);
+                sys_yield();
+                assert_eq!(read(round % 512), me);
+            }
+            sys_exit(0);
+        }
+    }
+
+    for round in 0..ROUNDS {
+        write(round % 512, usize::MAX);
+        sys_yield();
+        assert_eq!(read(round % 512), usize::MAX);
+    }
+
+    for pid in pids {
+        let mut exit_code: i32 = -1;
+        assert_eq!(waitpid(pid, &mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+    }
+
+    println!("asidtest OK!");
+}
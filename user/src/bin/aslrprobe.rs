@@ -0,0 +1,20 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use core::hint::black_box;
+use user_lib::{sys_mmap, MAP_ANONYMOUS, MAP_PRIVATE, PAGE_SIZE, PROT_READ, PROT_WRITE};
+
+/// Exit with a fingerprint of this address space's layout (used by aslrtest)
+///
+/// The pages of the stack and of the first mmap are folded into the exit code.
+#[no_mangle]
+fn main() -> i32 {
+    let local = black_box(0usize);
+    let stack_page = &local as *const usize as usize / PAGE_SIZE;
+    let mmap_page =
+        sys_mmap(0, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0) as usize
+            / PAGE_SIZE;
+    ((stack_page ^ (mmap_page << 8)) & 0x3fff_ffff) as i32
+}
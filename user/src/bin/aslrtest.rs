@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::hint::black_box;
+use user_lib::{
+    sys_exec, sys_exit, sys_fork, sys_mmap, sys_munmap, waitpid, MAP_ANONYMOUS, MAP_PRIVATE,
+    PAGE_SIZE, PROT_READ, PROT_WRITE,
+};
+
+/// Where the first mmap lands without ASLR
+const MMAP_START: usize = 0x2000_0000;
+
+/// Stack range without ASLR, and how far ASLR may move it down (16MB)
+const USER_STACK_BOTTOM: usize = 0x1000_0000;
+const USER_STACK_TOP: usize = USER_STACK_BOTTOM + 2 * PAGE_SIZE;
+const STACK_RANDOM_RANGE: usize = 16 * 1024 * 1024;
+
+const PROBES: usize = 4;
+
+/// Layout fingerprint of a fresh address space (see aslrprobe)
+fn probe() -> i32 {
+    let pid = sys_fork();
+    if pid == 0 {
+        sys_exec("aslrprobe\0");
+        sys_exit(-1);
+    }
+    let mut exit_code: i32 = -1;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert!(exit_code >= 0);
+    exit_code
+}
+
+#[no_mangle]
+fn main() {
+    println!("aslrtest begin");
+
+    // Without ASLR the first mmap lands exactly at the default base
+    let base = sys_mmap(0, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0);
+    assert!(base > 0);
+    let aslr = base as usize != MMAP_START;
+    assert_eq!(sys_munmap(base as usize, PAGE_SIZE), 0);
+
+    // The stack only ever moves down, and not too far
+    let local = black_box(0usize);
+    let sp = &local as *const usize as usize;
+    assert!(sp < USER_STACK_TOP && sp >= USER_STACK_BOTTOM - STACK_RANDOM_RANGE);
+
+    // Every exec gets a new layout with ASLR, the same one without
+    let mut layouts = [0i32; PROBES];
+    for layout in layouts.iter_mut() {
+        *layout = probe();
+    }
+    let distinct = layouts.iter().filter(|&&l| l != layouts[0]).count();
+    if aslr {
+        assert!(distinct > 0);
+        println!("ASLR enabled, {} of {} layouts differ from the first", distinct, PROBES - 1);
+    } else {
+        assert_eq!(distinct, 0);
+        println!("ASLR disabled, all layouts identical");
+    }
+
+    println!("aslrtest OK!");
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::ptr::addr_of_mut;
+use user_lib::{sys_exit, sys_fork, waitpid};
+
+/// Spans a few pages so that both .bss and the page-sharing paths are exercised
+const LEN: usize = 2048;
+
+static mut SHARED: [usize; LEN] = [0; LEN];
+
+fn read(i: usize) -> usize {
+    unsafe { (addr_of_mut!(SHARED) as *const usize).add(i).read_volatile() }
+}
+
+fn write(i: usize, value: usize) {
+    unsafe { (addr_of_mut!(SHARED) as *mut usize).add(i).write_volatile(value) }
+}
+
+#[no_mangle]
+fn main() {
+    println!("cowtest begin");
+
+    for i in 0..LEN {
+        write(i, i);
+    }
+
+    let pid = sys_fork();
+    if pid == 0 {
+        // The child sees the parent's data and gets its own copy on write
+        for i in 0..LEN {
+            assert_eq!(read(i), i);
+            write(i, i * 2);
+        }
+        for i in 0..LEN {
+            assert_eq!(read(i), i * 2);
+        }
+        sys_exit(0);
+    }
+
+    let mut exit_code: i32 = -1;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    // The child's stores must not be visible in the parent
+    for i in 0..LEN {
+        assert_eq!(read(i), i);
+    }
+
+    println!("cowtest OK!");
+}
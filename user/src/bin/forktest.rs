@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{sys_exec, sys_exit, sys_fork, sys_getpid, sys_getppid, wait, waitpid};
+
+const CHILDREN: usize = 4;
+
+#[no_mangle]
+fn main() {
+    println!("forktest begin, pid = {}", sys_getpid());
+
+    for i in 0..CHILDREN {
+        let pid = sys_fork();
+        if pid == 0 {
+            println!("child {}: pid = {}, ppid = {}", i, sys_getpid(), sys_getppid());
+            sys_exit(100 + i as i32);
+        }
+        assert!(pid > 0);
+    }
+
+    let mut exited = 0;
+    let mut exit_code: i32 = 0;
+    while wait(&mut exit_code) > 0 {
+        assert!(exit_code >= 100 && exit_code < 100 + CHILDREN as i32);
+        exited += 1;
+    }
+    assert_eq!(exited, CHILDREN);
+    println!("forktest: all {} children reaped", exited);
+
+    // fork + exec + waitpid
+    let pid = sys_fork();
+    if pid == 0 {
+        sys_exec("power_3\0");
+        println!("forktest: exec failed");
+        sys_exit(-1);
+    }
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    println!("forktest OK!");
+}
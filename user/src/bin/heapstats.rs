@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sys_heap_stats, sys_mmap, sys_munmap, HeapStats, MAP_ANONYMOUS, MAP_PRIVATE, PAGE_SIZE,
+    PROT_READ, PROT_WRITE,
+};
+
+const PAGES: usize = 256;
+
+fn heap_stats() -> HeapStats {
+    let mut stats = HeapStats::default();
+    assert_eq!(sys_heap_stats(&mut stats), 0);
+    assert!(stats.total_bytes >= stats.actual_bytes);
+    assert!(stats.actual_bytes >= stats.user_bytes);
+    stats
+}
+
+#[no_mangle]
+fn main() {
+    println!("heapstats begin");
+
+    let before = heap_stats();
+    println!(
+        "kernel heap: {} / {} bytes used, grown {} times",
+        before.actual_bytes, before.total_bytes, before.grow_count
+    );
+
+    // Every touched page is tracked by the kernel, on its heap
+    let len = PAGES * PAGE_SIZE;
+    let addr = sys_mmap(0, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0);
+    assert!(addr > 0);
+    for page in 0..PAGES {
+        unsafe { ((addr as usize + page * PAGE_SIZE) as *mut u8).write_volatile(1) };
+    }
+    let during = heap_stats();
+    assert!(during.user_bytes > before.user_bytes);
+    assert!(during.total_bytes >= before.total_bytes);
+    assert!(during.grow_count >= before.grow_count);
+
+    assert_eq!(sys_munmap(addr as usize, len), 0);
+    let after = heap_stats();
+    assert!(after.user_bytes < during.user_bytes);
+    // The heap never shrinks
+    assert!(after.total_bytes >= during.total_bytes);
+
+    println!("heapstats OK!");
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{sys_brk, PAGE_SIZE};
+
+#[no_mangle]
+fn main() {
+    println!("heaptest begin");
+
+    // Raw brk: grow, touch, shrink back (before the allocator takes over the heap)
+    let bottom = sys_brk(0) as usize;
+    let top = bottom + 4 * PAGE_SIZE;
+    assert_eq!(sys_brk(top) as usize, top);
+    for addr in (bottom..top).step_by(PAGE_SIZE) {
+        let ptr = addr as *mut usize;
+        assert_eq!(unsafe { ptr.read_volatile() }, 0);
+        unsafe { ptr.write_volatile(addr) };
+    }
+    assert_eq!(sys_brk(bottom) as usize, bottom);
+    // Moving the break below the heap bottom is refused
+    assert_eq!(sys_brk(bottom - PAGE_SIZE) as usize, bottom);
+
+    // Released pages come back zeroed
+    assert_eq!(sys_brk(top) as usize, top);
+    assert_eq!(unsafe { (bottom as *const usize).read_volatile() }, 0);
+    assert_eq!(sys_brk(bottom) as usize, bottom);
+
+    // Global allocator on top of brk
+    let mut v: Vec<usize> = Vec::new();
+    for i in 0..10000 {
+        v.push(i);
+    }
+    assert_eq!(v.iter().sum::<usize>(), 10000 * 9999 / 2);
+
+    let mut s = String::new();
+    for i in 0..100 {
+        s += &alloc::format!("{} ", i);
+    }
+    assert!(s.starts_with("0 1 2 "));
+    drop(v);
+    drop(s);
+
+    // Freed blocks are reused
+    let a: Vec<u8> = Vec::with_capacity(1000);
+    let ptr = a.as_ptr();
+    drop(a);
+    let b: Vec<u8> = Vec::with_capacity(1000);
+    assert_eq!(b.as_ptr(), ptr);
+
+    println!("heaptest OK!");
+}
@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sys_exit, sys_fork, sys_mmap, sys_mprotect, sys_munmap, waitpid, MAP_ANONYMOUS, MAP_FAILED,
+    MAP_HUGETLB, MAP_PRIVATE, MAP_SHARED, PAGE_SIZE, PROT_READ, PROT_WRITE,
+};
+
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+const LEN: usize = 2 * HUGE_PAGE_SIZE;
+
+fn huge_mmap(len: usize, flags: usize) -> isize {
+    sys_mmap(0, len, PROT_READ | PROT_WRITE, flags | MAP_ANONYMOUS | MAP_HUGETLB, usize::MAX, 0)
+}
+
+#[no_mangle]
+fn main() {
+    println!("hugetest begin");
+
+    let addr = huge_mmap(LEN, MAP_PRIVATE);
+    assert!(addr > 0);
+    let addr = addr as usize;
+    assert_eq!(addr % HUGE_PAGE_SIZE, 0);
+
+    // Fresh huge pages are zeroed; every 4KB page inside is usable
+    for offset in (0..LEN).step_by(PAGE_SIZE) {
+        let ptr = (addr + offset) as *mut usize;
+        assert_eq!(unsafe { ptr.read_volatile() }, 0);
+        unsafe { ptr.write_volatile(offset) };
+    }
+    for offset in (0..LEN).step_by(PAGE_SIZE) {
+        assert_eq!(unsafe { ((addr + offset) as *const usize).read_volatile() }, offset);
+    }
+
+    // A length that is not a multiple of 2MB is rounded up
+    let small = huge_mmap(PAGE_SIZE, MAP_PRIVATE);
+    assert!(small > 0 && small as usize % HUGE_PAGE_SIZE == 0);
+    unsafe { ((small as usize + HUGE_PAGE_SIZE - 8) as *mut usize).write_volatile(1) };
+    assert_eq!(sys_munmap(small as usize, HUGE_PAGE_SIZE), 0);
+
+    // The child gets its own copy
+    let pid = sys_fork();
+    if pid == 0 {
+        for offset in (0..LEN).step_by(PAGE_SIZE) {
+            let ptr = (addr + offset) as *mut usize;
+            assert_eq!(unsafe { ptr.read_volatile() }, offset);
+            unsafe { ptr.write_volatile(0) };
+        }
+        sys_exit(0);
+    }
+    assert!(pid > 0);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(unsafe { ((addr + PAGE_SIZE) as *const usize).read_volatile() }, PAGE_SIZE);
+
+    // Huge pages cannot be split
+    assert_eq!(sys_munmap(addr + PAGE_SIZE, PAGE_SIZE), -1);
+    assert_eq!(sys_mprotect(addr, PAGE_SIZE, PROT_READ), -1);
+    assert_eq!(sys_mprotect(addr, HUGE_PAGE_SIZE, PROT_READ), 0);
+    assert_eq!(sys_munmap(addr + HUGE_PAGE_SIZE, HUGE_PAGE_SIZE), 0);
+    assert_eq!(unsafe { (addr as *const usize).read_volatile() }, 0);
+    assert_eq!(sys_munmap(addr, HUGE_PAGE_SIZE), 0);
+
+    // Shared huge mappings are not supported
+    assert_eq!(huge_mmap(HUGE_PAGE_SIZE, MAP_SHARED), MAP_FAILED);
+
+    println!("hugetest OK!");
+}
@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{sys_exec, sys_exit, sys_fork, wait};
+
+/// Apps run by initproc, one at a time, so that tests that fork or put the
+/// system under memory pressure don't compete with each other
+/// (aslrprobe is only exec'd by aslrtest)
+const APPS: &[&str] = &[
+    "power_3\0",
+    "power_5\0",
+    "power_7\0",
+    "forktest\0",
+    "cowtest\0",
+    "lazytest\0",
+    "heaptest\0",
+    "munmaptest\0",
+    "mprotecttest\0",
+    "mmaptest\0",
+    "shmtest\0",
+    "heapstats\0",
+    "slabtest\0",
+    "hugetest\0",
+    "asidtest\0",
+    "vatest\0",
+    "swaptest\0",
+    "oomtest\0",
+    "rlimittest\0",
+    "stacktest\0",
+    "aslrtest\0",
+    "wxtest\0",
+    "stridetest\0",
+    "schedbench\0",
+];
+
+/// PID 1: runs every app in APPS, reaps every child (including adopted
+/// orphans) and exits when none are left, which shuts the kernel down.
+#[no_mangle]
+fn main() -> i32 {
+    println!("[initproc] started");
+    let mut exit_code: i32 = 0;
+    for app in APPS {
+        let name = &app[..app.len() - 1];
+        let pid = sys_fork();
+        if pid == 0 {
+            sys_exec(app);
+            println!("[initproc] exec {} failed", name);
+            sys_exit(-1);
+        }
+        if pid < 0 {
+            println!("[initproc] fork for {} failed", name);
+            continue;
+        }
+        loop {
+            let reaped = wait(&mut exit_code);
+            if reaped == pid {
+                println!("[initproc] {} (pid {}) exited with code {}", name, pid, exit_code);
+                break;
+            }
+            if reaped == -1 {
+                break;
+            }
+            println!("[initproc] reaped pid {}, exit code {}", reaped, exit_code);
+        }
+    }
+    loop {
+        let pid = wait(&mut exit_code);
+        if pid == -1 {
+            break;
+        }
+        println!("[initproc] reaped pid {}, exit code {}", pid, exit_code);
+    }
+    println!("[initproc] no children left, exiting");
+    0
+}
@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sys_mmap, sys_munmap, MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, PAGE_SIZE, PROT_READ, PROT_WRITE,
+};
+
+/// Larger than physical memory, so this only works if pages are allocated on touch
+const LEN: usize = 256 * 1024 * 1024;
+
+/// Distance between the pages we touch
+const STRIDE: usize = 16 * 1024 * 1024;
+
+#[no_mangle]
+fn main() {
+    println!("lazytest begin");
+
+    let addr = sys_mmap(0, LEN, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0);
+    assert_ne!(addr, MAP_FAILED);
+    let base = addr as usize;
+
+    // First touch by a load: the page reads as zero
+    for offset in (0..LEN).step_by(STRIDE) {
+        let value = unsafe { ((base + offset) as *const usize).read_volatile() };
+        assert_eq!(value, 0);
+    }
+
+    // First touch by a store, at the last word of a page
+    for offset in (STRIDE / 2..LEN).step_by(STRIDE) {
+        let ptr = (base + offset + PAGE_SIZE - 8) as *mut usize;
+        unsafe { ptr.write_volatile(offset) };
+    }
+    for offset in (STRIDE / 2..LEN).step_by(STRIDE) {
+        let ptr = (base + offset + PAGE_SIZE - 8) as *const usize;
+        assert_eq!(unsafe { ptr.read_volatile() }, offset);
+    }
+
+    assert_eq!(sys_munmap(base, LEN), 0);
+    println!("lazytest OK!");
+}
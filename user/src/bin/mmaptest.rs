@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sys_mmap, sys_munmap, MAP_ANONYMOUS, MAP_FAILED, MAP_FIXED, MAP_PRIVATE, MAP_SHARED, PAGE_SIZE,
+    PROT_READ, PROT_WRITE,
+};
+
+const LEN: usize = 4 * PAGE_SIZE;
+
+fn mmap(addr: usize, flags: usize) -> usize {
+    let ret = sys_mmap(addr, LEN, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | flags, usize::MAX, 0);
+    assert_ne!(ret, MAP_FAILED);
+    ret as usize
+}
+
+fn disjoint(a: usize, b: usize) -> bool {
+    a + LEN <= b || b + LEN <= a
+}
+
+fn fill(base: usize, value: usize) {
+    for offset in (0..LEN).step_by(PAGE_SIZE) {
+        unsafe { ((base + offset) as *mut usize).write_volatile(value) };
+    }
+}
+
+fn check(base: usize, value: usize) {
+    for offset in (0..LEN).step_by(PAGE_SIZE) {
+        assert_eq!(unsafe { ((base + offset) as *const usize).read_volatile() }, value);
+    }
+}
+
+#[no_mangle]
+fn main() {
+    println!("mmaptest begin");
+
+    // Repeated mmaps get distinct regions
+    let a = mmap(0, 0);
+    let b = mmap(0, 0);
+    assert!(disjoint(a, b));
+    fill(a, 1);
+    fill(b, 2);
+    check(a, 1);
+    check(b, 2);
+
+    // A free hint is honored, a taken one is not
+    let hint = 0x3000_0000;
+    let c = mmap(hint, 0);
+    assert_eq!(c, hint);
+    let d = mmap(hint + PAGE_SIZE, 0);
+    assert!(disjoint(c, d) && disjoint(a, d) && disjoint(b, d));
+    fill(c, 3);
+    fill(d, 4);
+    check(c, 3);
+
+    // MAP_FIXED replaces the overlapped part of an existing mapping
+    let e = mmap(c + 2 * PAGE_SIZE, MAP_FIXED);
+    assert_eq!(e, c + 2 * PAGE_SIZE);
+    for i in 0..4 {
+        let value = unsafe { ((c + i * PAGE_SIZE) as *const usize).read_volatile() };
+        assert_eq!(value, if i < 2 { 3 } else { 0 });
+    }
+
+    // A refused MAP_FIXED (no such shared memory object) leaves the mapping
+    // it would have replaced alone
+    let ret = sys_mmap(d, LEN, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_FIXED, usize::MAX, 0);
+    assert_eq!(ret, MAP_FAILED);
+    check(d, 4);
+
+    // A hole left by munmap is reused
+    assert_eq!(sys_munmap(a, LEN), 0);
+    assert_eq!(mmap(0, 0), a);
+    check(b, 2);
+    check(d, 4);
+
+    println!("mmaptest OK!");
+}
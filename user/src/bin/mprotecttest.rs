@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use user_lib::{
+    run_in_child, sys_mmap, sys_mprotect, MAP_ANONYMOUS, MAP_PRIVATE, PAGE_SIZE, PROT_EXEC,
+    PROT_READ, PROT_WRITE,
+};
+
+const BASE: usize = 0x3000_0000;
+const PAGES: usize = 3;
+
+fn page(i: usize) -> *mut usize {
+    (BASE + i * PAGE_SIZE) as *mut usize
+}
+
+#[no_mangle]
+fn main() {
+    println!("mprotecttest begin");
+
+    let ret = sys_mmap(BASE, PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0);
+    assert_eq!(ret, BASE as isize);
+    for i in 0..PAGES {
+        unsafe { page(i).write_volatile(i) };
+    }
+
+    // Read-only middle page: reads work, stores kill the task
+    assert_eq!(sys_mprotect(page(1) as usize, PAGE_SIZE, PROT_READ), 0);
+    assert_eq!(run_in_child(|| assert_eq!(unsafe { page(1).read_volatile() }, 1)), 0);
+    assert_eq!(run_in_child(|| unsafe { page(1).write_volatile(0) }), -1);
+    // Its neighbours keep their old rights
+    assert_eq!(run_in_child(|| unsafe { page(0).write_volatile(0) }), 0);
+    assert_eq!(run_in_child(|| unsafe { page(2).write_volatile(0) }), 0);
+
+    // Guard page: no access at all
+    assert_eq!(sys_mprotect(page(2) as usize, PAGE_SIZE, 0), 0);
+    assert_eq!(run_in_child(|| unsafe { page(2).read_volatile(); }), -1);
+
+    // Everything back to read-write; the data survived
+    assert_eq!(sys_mprotect(BASE, PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE), 0);
+    for i in 0..PAGES {
+        assert_eq!(unsafe { page(i).read_volatile() }, i);
+        unsafe { page(i).write_volatile(i * 10) };
+    }
+
+    // Unmapped pages in the range are an error
+    assert_eq!(sys_mprotect(BASE, (PAGES + 1) * PAGE_SIZE, PROT_READ), -1);
+
+    // JIT: write `li a0, 42; ret`, then flip the page to executable
+    let code = page(0) as *mut u32;
+    unsafe {
+        code.write_volatile(0x02a0_0513);
+        code.add(1).write_volatile(0x0000_8067);
+    }
+    assert_eq!(sys_mprotect(BASE, PAGE_SIZE, PROT_READ | PROT_EXEC), 0);
+    unsafe { asm!("fence.i") };
+    let jitted: extern "C" fn() -> usize = unsafe { core::mem::transmute(code) };
+    assert_eq!(jitted(), 42);
+
+    println!("mprotecttest OK!");
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sys_exit, sys_fork, sys_mmap, sys_munmap, waitpid, MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE,
+    PAGE_SIZE, PROT_READ, PROT_WRITE,
+};
+
+const BASE: usize = 0x3000_0000;
+const PAGES: usize = 8;
+
+fn page(i: usize) -> *mut usize {
+    (BASE + i * PAGE_SIZE) as *mut usize
+}
+
+/// Map PAGES pages at `addr` and tag every page with its index
+fn map_and_fill(addr: usize, first: usize) {
+    let ret = sys_mmap(addr, PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0);
+    assert_eq!(ret, addr as isize);
+    assert_ne!(ret, MAP_FAILED);
+    for i in first..first + PAGES {
+        unsafe { page(i).write_volatile(i) };
+    }
+}
+
+/// Touch a page in a child and check that the access kills it
+fn assert_unmapped(i: usize) {
+    let pid = sys_fork();
+    if pid == 0 {
+        unsafe { page(i).read_volatile() };
+        sys_exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, -1);
+}
+
+#[no_mangle]
+fn main() {
+    println!("munmaptest begin");
+
+    // Two adjacent mappings: pages [0, 8) and [8, 16)
+    map_and_fill(BASE, 0);
+    map_and_fill(BASE + PAGES * PAGE_SIZE, PAGES);
+
+    // Hole in the middle of the first mapping
+    assert_eq!(sys_munmap(page(2) as usize, 2 * PAGE_SIZE), 0);
+    // Range spanning the end of the first mapping and the start of the second
+    assert_eq!(sys_munmap(page(6) as usize, 4 * PAGE_SIZE), 0);
+    // Range with no mapping at all
+    assert_eq!(sys_munmap(page(2) as usize, PAGE_SIZE), 0);
+
+    for i in [0, 1, 4, 5, 10, 11, 15] {
+        assert_eq!(unsafe { page(i).read_volatile() }, i);
+    }
+    for i in [2, 3, 6, 7, 8, 9] {
+        assert_unmapped(i);
+    }
+
+    // The rest goes away in one call
+    assert_eq!(sys_munmap(BASE, 2 * PAGES * PAGE_SIZE), 0);
+    assert_unmapped(0);
+    assert_unmapped(15);
+
+    println!("munmaptest OK!");
+}
@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sys_brk, sys_exit, sys_fork, sys_mmap, sys_munmap, waitpid, ENOMEM, MAP_ANONYMOUS, MAP_FIXED,
+    MAP_HUGETLB, MAP_PRIVATE, MAP_SHARED, PAGE_SIZE, PROT_READ, PROT_WRITE,
+};
+
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Far more than the 128MB of RAM the kernel gets by default
+const LEN: usize = 1024 * 1024 * 1024;
+
+/// Exit code of a task killed by the OOM killer
+const OOM_EXIT_CODE: i32 = -9;
+
+#[no_mangle]
+fn main() {
+    println!("oomtest begin");
+
+    // Shared anonymous memory is allocated up front, so a huge request fails cleanly
+    let ret = sys_mmap(0, LEN, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_ANONYMOUS, usize::MAX, 0);
+    assert_eq!(ret, -ENOMEM);
+
+    // The heap cannot grow into another mapping
+    let brk = sys_brk(0) as usize;
+    let blocker = (brk + 4 * PAGE_SIZE) & !(PAGE_SIZE - 1);
+    let ret = sys_mmap(
+        blocker,
+        PAGE_SIZE,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED,
+        usize::MAX,
+        0,
+    );
+    assert_eq!(ret, blocker as isize);
+    assert_eq!(sys_brk(blocker + PAGE_SIZE), -ENOMEM);
+    assert_eq!(sys_brk(0) as usize, brk);
+    assert_eq!(sys_munmap(blocker, PAGE_SIZE), 0);
+
+    // A child touching more memory than there is gets killed instead of the kernel
+    // (huge pages are never swapped out, so this does not wait for the swap area to fill).
+    // The OOM killer picks the largest task system-wide, so this relies on
+    // initproc running the apps one at a time.
+    let pid = sys_fork();
+    if pid == 0 {
+        let base = sys_mmap(
+            0,
+            LEN,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS | MAP_HUGETLB,
+            usize::MAX,
+            0,
+        );
+        assert!(base > 0);
+        for offset in (0..LEN).step_by(HUGE_PAGE_SIZE) {
+            unsafe { ((base as usize + offset) as *mut usize).write_volatile(offset) };
+        }
+        println!("oomtest: child was not killed");
+        sys_exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, OOM_EXIT_CODE);
+
+    // The memory of the killed child is back
+    let ret = sys_mmap(0, 16 * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_ANONYMOUS, usize::MAX, 0);
+    assert!(ret > 0);
+    assert_eq!(sys_munmap(ret as usize, 16 * PAGE_SIZE), 0);
+
+    println!("oomtest OK!");
+}
@@ -0,0 +1,126 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    burn, run_in_child, sys_brk, sys_getrlimit, sys_mem_stats, sys_mmap, sys_munmap, sys_setrlimit,
+    sys_shm_open, MemStats, RLimit, ENOMEM, MAP_ANONYMOUS, MAP_PRIVATE, PAGE_SIZE, PROT_READ,
+    PROT_WRITE, RLIMIT_AS, RLIMIT_CPU, RLIMIT_NOFILE, RLIM_INFINITY, SHM_CREATE,
+};
+
+/// Exit code of a task killed for exceeding RLIMIT_CPU
+const CPU_LIMIT_EXIT_CODE: i32 = -24;
+
+fn mem_stats() -> MemStats {
+    let mut stats = MemStats::default();
+    assert_eq!(sys_mem_stats(&mut stats), 0);
+    stats
+}
+
+fn getrlimit(resource: usize) -> RLimit {
+    let mut limit = RLimit { cur: 0, max: 0 };
+    assert_eq!(sys_getrlimit(resource, &mut limit), 0);
+    limit
+}
+
+fn mmap(pages: usize) -> isize {
+    sys_mmap(0, pages * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0)
+}
+
+/// Lazy pages count in the virtual size right away, in the RSS once touched
+fn check_counters() {
+    let before = mem_stats();
+    assert!(before.resident_pages > 0);
+    assert!(before.virtual_pages >= before.resident_pages);
+
+    let base = mmap(64);
+    assert!(base > 0);
+    let mapped = mem_stats();
+    assert_eq!(mapped.virtual_pages, before.virtual_pages + 64);
+    assert_eq!(mapped.resident_pages, before.resident_pages);
+
+    for i in 0..16 {
+        unsafe { ((base as usize + i * PAGE_SIZE) as *mut usize).write_volatile(i) };
+    }
+    let touched = mem_stats();
+    assert_eq!(touched.resident_pages, before.resident_pages + 16);
+
+    assert_eq!(sys_munmap(base as usize, 64 * PAGE_SIZE), 0);
+    let after = mem_stats();
+    assert_eq!(after.virtual_pages, before.virtual_pages);
+    assert_eq!(after.resident_pages, before.resident_pages);
+    println!(
+        "rss {} pages, vsz {} pages",
+        after.resident_pages, after.virtual_pages
+    );
+}
+
+/// Address space beyond RLIMIT_AS is refused to mmap and brk
+fn check_address_space_limit() {
+    let vsz = mem_stats().virtual_pages * PAGE_SIZE;
+    let limit = RLimit { cur: vsz + 16 * PAGE_SIZE, max: RLIM_INFINITY };
+    assert_eq!(sys_setrlimit(RLIMIT_AS, &limit), 0);
+    assert_eq!(getrlimit(RLIMIT_AS), limit);
+
+    assert_eq!(mmap(32), -ENOMEM);
+    let base = mmap(8);
+    assert!(base > 0);
+    let brk = sys_brk(0) as usize;
+    assert_eq!(sys_brk(brk + 16 * PAGE_SIZE), -ENOMEM);
+    assert_eq!(sys_brk(brk + 4 * PAGE_SIZE) as usize, brk + 4 * PAGE_SIZE);
+    assert_eq!(sys_brk(brk) as usize, brk);
+    assert_eq!(sys_munmap(base as usize, 8 * PAGE_SIZE), 0);
+
+    // The soft limit can go back up to the hard limit
+    let unlimited = RLimit { cur: RLIM_INFINITY, max: RLIM_INFINITY };
+    assert_eq!(sys_setrlimit(RLIMIT_AS, &unlimited), 0);
+    let base = mmap(32);
+    assert!(base > 0);
+    assert_eq!(sys_munmap(base as usize, 32 * PAGE_SIZE), 0);
+}
+
+/// Hard limits can be lowered but not raised again; children inherit limits
+fn check_hard_limit() {
+    let lowered = RLimit { cur: 1 << 30, max: 1 << 30 };
+    assert_eq!(sys_setrlimit(RLIMIT_AS, &lowered), 0);
+    let raised = RLimit { cur: 1 << 30, max: RLIM_INFINITY };
+    assert_eq!(sys_setrlimit(RLIMIT_AS, &raised), -1);
+    let inverted = RLimit { cur: 1 << 30, max: 1 << 20 };
+    assert_eq!(sys_setrlimit(RLIMIT_AS, &inverted), -1);
+    assert_eq!(
+        run_in_child(|| assert_eq!(getrlimit(RLIMIT_AS), RLimit { cur: 1 << 30, max: 1 << 30 })),
+        0
+    );
+}
+
+/// Descriptor numbers (shared memory ids) stay below RLIMIT_NOFILE
+fn check_nofile_limit() {
+    let limit = RLimit { cur: 0, max: getrlimit(RLIMIT_NOFILE).max };
+    assert_eq!(sys_setrlimit(RLIMIT_NOFILE, &limit), 0);
+    assert_eq!(sys_shm_open("rlimittest\0", PAGE_SIZE, SHM_CREATE), -1);
+}
+
+/// A task that uses up RLIMIT_CPU is killed
+fn check_cpu_limit() {
+    let limit = RLimit { cur: 1, max: 1 };
+    assert_eq!(sys_setrlimit(RLIMIT_CPU, &limit), 0);
+    loop {
+        burn(1);
+    }
+}
+
+#[no_mangle]
+fn main() {
+    println!("rlimittest begin");
+
+    assert_eq!(getrlimit(RLIMIT_AS).cur, RLIM_INFINITY);
+    check_counters();
+    assert_eq!(run_in_child(check_address_space_limit), 0);
+    assert_eq!(run_in_child(check_hard_limit), 0);
+    assert_eq!(run_in_child(check_nofile_limit), 0);
+    assert_eq!(run_in_child(check_cpu_limit), CPU_LIMIT_EXIT_CODE);
+
+    println!("rlimittest OK!");
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{burn, sys_exit, sys_fork, sys_get_time, sys_yield, waitpid};
+
+/// sys_get_time counts timer ticks (10MHz on QEMU virt)
+const CLOCK_FREQ: isize = 10_000_000;
+
+/// How long the workload runs (2s)
+const DURATION: isize = 2 * CLOCK_FREQ;
+
+/// CPU hogs competing with the interactive task
+const HOGS: usize = 3;
+
+/// Spin until `deadline`, returning the work done
+fn hog(deadline: isize) -> i32 {
+    let mut work = 0;
+    while sys_get_time() < deadline {
+        burn(1);
+        work += 1;
+    }
+    work
+}
+
+/// Work a little and yield until `deadline`, returning the number of rounds
+///
+/// The time from each sys_yield until the task runs again is its response time.
+fn interactive(deadline: isize) -> i32 {
+    let mut rounds = 0;
+    let mut total: isize = 0;
+    let mut worst: isize = 0;
+    while sys_get_time() < deadline {
+        burn(1);
+        let start = sys_get_time();
+        sys_yield();
+        let waited = sys_get_time() - start;
+        total += waited;
+        worst = worst.max(waited);
+        rounds += 1;
+    }
+    let us = |cycles: isize| cycles / (CLOCK_FREQ / 1_000_000);
+    println!(
+        "schedbench: interactive task ran {} rounds, response {}us average, {}us worst",
+        rounds,
+        us(total / rounds.max(1) as isize),
+        us(worst)
+    );
+    rounds
+}
+
+/// The same workload under every scheduling policy: boot with `sched=<policy>`
+/// to compare how CPU hogs and a task that yields often are treated
+#[no_mangle]
+fn main() {
+    println!("schedbench begin");
+
+    let deadline = sys_get_time() + DURATION;
+    let pid = sys_fork();
+    if pid == 0 {
+        sys_exit(interactive(deadline));
+    }
+    assert!(pid > 0);
+    let mut hogs = [0isize; HOGS];
+    for hog_pid in hogs.iter_mut() {
+        *hog_pid = sys_fork();
+        if *hog_pid == 0 {
+            sys_exit(hog(deadline));
+        }
+        assert!(*hog_pid > 0);
+    }
+
+    // Everybody makes progress, whatever the policy
+    let mut rounds = 0;
+    assert_eq!(waitpid(pid, &mut rounds), pid);
+    assert!(rounds > 0);
+    for (i, &hog_pid) in hogs.iter().enumerate() {
+        let mut work = 0;
+        assert_eq!(waitpid(hog_pid, &mut work), hog_pid);
+        assert!(work > 0);
+        println!("schedbench: hog {} did {} units", i, work);
+    }
+
+    println!("schedbench OK!");
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sys_exit, sys_fork, sys_mmap, sys_shm_open, sys_shm_unlink, sys_yield, waitpid, MAP_ANONYMOUS,
+    MAP_FAILED, MAP_PRIVATE, MAP_SHARED, PAGE_SIZE, PROT_READ, PROT_WRITE, SHM_CREATE,
+};
+
+const LEN: usize = 2 * PAGE_SIZE;
+const NAME: &str = "shmtest\0";
+
+fn mmap(flags: usize, fd: usize) -> *mut usize {
+    let ret = sys_mmap(0, LEN, PROT_READ | PROT_WRITE, flags, fd, 0);
+    assert_ne!(ret, MAP_FAILED);
+    ret as *mut usize
+}
+
+fn read(ptr: *mut usize, i: usize) -> usize {
+    unsafe { ptr.add(i).read_volatile() }
+}
+
+fn write(ptr: *mut usize, i: usize, value: usize) {
+    unsafe { ptr.add(i).write_volatile(value) }
+}
+
+#[no_mangle]
+fn main() {
+    println!("shmtest begin");
+
+    // Anonymous: the child's stores to the shared mapping reach the parent,
+    // stores to the private one do not
+    let shared = mmap(MAP_SHARED | MAP_ANONYMOUS, usize::MAX);
+    let private = mmap(MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX);
+    write(shared, 0, 1);
+    write(private, 0, 1);
+    let pid = sys_fork();
+    if pid == 0 {
+        write(shared, 0, 2);
+        write(shared, PAGE_SIZE / 8, 3);
+        write(private, 0, 2);
+        sys_exit(0);
+    }
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(read(shared, 0), 2);
+    assert_eq!(read(shared, PAGE_SIZE / 8), 3);
+    assert_eq!(read(private, 0), 1);
+
+    // Named: the child opens and maps the object on its own, and the two
+    // tasks hand a counter back and forth through it
+    assert_eq!(sys_shm_open(NAME, LEN, 0), -1);
+    let id = sys_shm_open(NAME, LEN, SHM_CREATE);
+    assert!(id >= 0);
+    let pid = sys_fork();
+    if pid == 0 {
+        let id = sys_shm_open(NAME, 0, 0);
+        assert!(id >= 0);
+        let buf = mmap(MAP_SHARED, id as usize);
+        for round in 0..10 {
+            while read(buf, 0) != 2 * round + 1 {
+                sys_yield();
+            }
+            write(buf, 0, 2 * round + 2);
+        }
+        sys_exit(0);
+    }
+    let buf = mmap(MAP_SHARED, id as usize);
+    for round in 0..10 {
+        write(buf, 0, 2 * round + 1);
+        while read(buf, 0) != 2 * round + 2 {
+            sys_yield();
+        }
+    }
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    // Unlinking keeps existing mappings alive
+    assert_eq!(sys_shm_unlink(NAME), 0);
+    assert_eq!(sys_shm_open(NAME, 0, 0), -1);
+    assert_eq!(read(buf, 0), 20);
+
+    println!("shmtest OK!");
+}
@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sys_exit, sys_fork, sys_mmap, sys_munmap, sys_slab_stats, wait, SlabStats, MAP_ANONYMOUS,
+    MAP_PRIVATE, PAGE_SIZE, PROT_READ, PROT_WRITE,
+};
+
+const CHILDREN: usize = 4;
+const PAGES: usize = 64;
+
+fn cache(name: &str) -> SlabStats {
+    let mut stats = SlabStats::default();
+    let mut id = 0;
+    while sys_slab_stats(id, &mut stats) == 0 {
+        if stats.name() == name {
+            assert!(stats.active_objects <= stats.slabs * stats.objects_per_slab);
+            assert_eq!(stats.allocs - stats.frees, stats.active_objects);
+            return stats;
+        }
+        id += 1;
+    }
+    panic!("no slab cache named {}", name);
+}
+
+#[no_mangle]
+fn main() {
+    println!("slabtest begin");
+
+    let mut stats = SlabStats::default();
+    let mut id = 0;
+    while sys_slab_stats(id, &mut stats) == 0 {
+        println!(
+            "{}: {} bytes, {}/slab, {} slabs, {} active",
+            stats.name(),
+            stats.object_size,
+            stats.objects_per_slab,
+            stats.slabs,
+            stats.active_objects
+        );
+        id += 1;
+    }
+
+    // Every fork takes a task control block from the task cache, every reap returns it
+    let before = cache("task");
+    for _ in 0..CHILDREN {
+        let pid = sys_fork();
+        if pid == 0 {
+            sys_exit(0);
+        }
+        assert!(pid > 0);
+    }
+    let mut exit_code = 0;
+    while wait(&mut exit_code) > 0 {}
+    let after = cache("task");
+    assert!(after.allocs >= before.allocs + CHILDREN);
+    assert!(after.frees >= before.frees + CHILDREN);
+
+    // Every touched page is tracked through the frame tracker cache, and the
+    // mapping itself is a map area from the map area cache
+    let before = cache("frame_tracker");
+    let areas_before = cache("map_area");
+    let len = PAGES * PAGE_SIZE;
+    let addr = sys_mmap(0, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0);
+    assert!(addr > 0);
+    for page in 0..PAGES {
+        unsafe { ((addr as usize + page * PAGE_SIZE) as *mut u8).write_volatile(1) };
+    }
+    assert_eq!(sys_munmap(addr as usize, len), 0);
+    let after = cache("frame_tracker");
+    assert!(after.allocs >= before.allocs + PAGES);
+    assert!(after.frees >= before.frees + PAGES);
+    let areas_after = cache("map_area");
+    assert!(areas_after.allocs > areas_before.allocs);
+    assert!(areas_after.frees > areas_before.frees);
+
+    println!("slabtest OK!");
+}
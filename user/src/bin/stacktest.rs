@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::hint::black_box;
+use user_lib::{
+    sys_exit, sys_fork, sys_mem_stats, sys_setrlimit, waitpid, MemStats, RLimit, PAGE_SIZE,
+    RLIMIT_STACK,
+};
+
+/// Far more than the 8KB the stack starts with, well below the default RLIMIT_STACK
+const DEPTH: usize = 256;
+
+fn mem_stats() -> MemStats {
+    let mut stats = MemStats::default();
+    assert_eq!(sys_mem_stats(&mut stats), 0);
+    stats
+}
+
+/// Recurse `depth` times with a 1KB frame each, returning a checksum of all frames
+fn recurse(depth: usize) -> usize {
+    let mut frame = [0u8; 1024];
+    for (i, byte) in frame.iter_mut().enumerate() {
+        *byte = depth.wrapping_add(i) as u8;
+    }
+    let frame = black_box(frame);
+    let below = if depth == 0 { 0 } else { recurse(depth - 1) };
+    below + frame.iter().map(|&b| b as usize).sum::<usize>()
+}
+
+fn expected_checksum(depth: usize) -> usize {
+    (0..=depth)
+        .map(|d| (0..1024).map(|i| ((d + i) as u8) as usize).sum::<usize>())
+        .sum()
+}
+
+#[no_mangle]
+fn main() {
+    println!("stacktest begin");
+
+    // The stack grows down on demand and keeps its contents
+    let before = mem_stats();
+    assert_eq!(recurse(DEPTH), expected_checksum(DEPTH));
+    let after = mem_stats();
+    assert!(after.virtual_pages >= before.virtual_pages + DEPTH * 1024 / PAGE_SIZE);
+    println!("stack grew by {} pages", after.virtual_pages - before.virtual_pages);
+
+    // Unbounded recursion runs into the guard page below RLIMIT_STACK and is killed
+    let pid = sys_fork();
+    if pid == 0 {
+        let limit = RLimit { cur: 64 * 1024, max: 64 * 1024 };
+        assert_eq!(sys_setrlimit(RLIMIT_STACK, &limit), 0);
+        recurse(usize::MAX);
+        println!("stacktest: child was not killed");
+        sys_exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, -1);
+
+    // The stack of a child grows on its own
+    let pid = sys_fork();
+    if pid == 0 {
+        assert_eq!(recurse(DEPTH * 2), expected_checksum(DEPTH * 2));
+        sys_exit(0);
+    }
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    println!("stacktest OK!");
+}
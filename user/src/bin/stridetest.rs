@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{burn, sys_exit, sys_fork, sys_get_time, sys_sched_policy, sys_set_priority, waitpid};
+
+/// sys_get_time counts timer ticks (10MHz on QEMU virt)
+const CLOCK_FREQ: isize = 10_000_000;
+
+/// How long the children compete for the CPU (4s)
+const DURATION: isize = 4 * CLOCK_FREQ;
+
+/// Priorities of the competing children; each gets twice the share of the one before
+const PRIORITIES: [isize; 3] = [4, 8, 16];
+
+/// Spin until `deadline`, returning the work done (in units of 1024 iterations)
+fn spin_until(deadline: isize) -> i32 {
+    let mut count: usize = 0;
+    loop {
+        burn(1);
+        count += 1;
+        if sys_get_time() >= deadline {
+            return count as i32;
+        }
+    }
+}
+
+/// The CPU shares are only checked under a policy that honors priorities
+/// (`sched=stride`, the default, or `sched=cfs`)
+#[no_mangle]
+fn main() {
+    let mut name = [0u8; 16];
+    let len = sys_sched_policy(&mut name);
+    assert!(len > 0);
+    let policy = core::str::from_utf8(&name[..len as usize]).unwrap();
+    println!("stridetest begin (policy {})", policy);
+
+    assert_eq!(sys_set_priority(1), -1);
+    assert_eq!(sys_set_priority(0), -1);
+    // A priority above the big stride would give a stride of 0
+    assert_eq!(sys_set_priority(0x10_0001), -1);
+    assert_eq!(sys_set_priority(0x10_0000), 0x10_0000);
+    assert_eq!(sys_set_priority(16), 16);
+
+    let deadline = sys_get_time() + DURATION;
+    let mut pids = [0isize; PRIORITIES.len()];
+    for (pid, &prio) in pids.iter_mut().zip(PRIORITIES.iter()) {
+        *pid = sys_fork();
+        if *pid == 0 {
+            assert_eq!(sys_set_priority(prio), prio);
+            sys_exit(spin_until(deadline));
+        }
+        assert!(*pid > 0);
+    }
+
+    // CPU shares follow the priorities: compare the work per unit of priority
+    let mut work = [0i32; PRIORITIES.len()];
+    for (i, &pid) in pids.iter().enumerate() {
+        assert_eq!(waitpid(pid, &mut work[i]), pid);
+        println!(
+            "stridetest: priority {} did {} units ({} per priority)",
+            PRIORITIES[i],
+            work[i],
+            work[i] as isize / PRIORITIES[i]
+        );
+    }
+    if policy == "stride" || policy == "cfs" {
+        for i in 1..PRIORITIES.len() {
+            assert!(work[i] > work[i - 1]);
+        }
+    }
+
+    println!("stridetest OK!");
+}
@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sys_exit, sys_fork, sys_mmap, sys_munmap, sys_swap_stats, waitpid, SwapStats, MAP_ANONYMOUS,
+    MAP_FAILED, MAP_PRIVATE, PAGE_SIZE, PROT_READ, PROT_WRITE,
+};
+
+/// More than the 128MB of RAM the kernel gets by default
+const LEN: usize = 160 * 1024 * 1024;
+const PAGES: usize = LEN / PAGE_SIZE;
+
+fn swap_stats() -> SwapStats {
+    let mut stats = SwapStats::default();
+    assert_eq!(sys_swap_stats(&mut stats), 0);
+    stats
+}
+
+fn page(base: usize, i: usize) -> *mut usize {
+    (base + i * PAGE_SIZE) as *mut usize
+}
+
+/// Value stored at the start of page `i`
+fn pattern(i: usize) -> usize {
+    i.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ 0x5a5a
+}
+
+#[no_mangle]
+fn main() {
+    println!("swaptest begin");
+
+    let before = swap_stats();
+    if before.total_slots == 0 {
+        println!("swaptest: no swap device, skipped");
+        return;
+    }
+    println!("swap area: {} slots, {} in use", before.total_slots, before.used_slots);
+
+    let base = sys_mmap(0, LEN, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0);
+    assert_ne!(base, MAP_FAILED);
+    let base = base as usize;
+
+    // Touching more pages than fit in RAM forces earlier ones out to swap
+    for i in 0..PAGES {
+        unsafe { page(base, i).write_volatile(pattern(i)) };
+    }
+    let filled = swap_stats();
+    assert!(filled.swapped_out > before.swapped_out);
+
+    // Every page must come back with its own contents
+    for i in 0..PAGES {
+        assert_eq!(unsafe { page(base, i).read_volatile() }, pattern(i));
+    }
+    let peak = swap_stats();
+    assert!(peak.swapped_in > before.swapped_in);
+    println!(
+        "swapped out {} pages, read back {} pages",
+        peak.swapped_out - before.swapped_out,
+        peak.swapped_in - before.swapped_in
+    );
+
+    // A child inherits swapped out pages and reads them back on its own
+    let pid = sys_fork();
+    if pid == 0 {
+        for i in (0..PAGES).step_by(64) {
+            assert_eq!(unsafe { page(base, i).read_volatile() }, pattern(i));
+            unsafe { page(base, i).write_volatile(0) };
+        }
+        sys_exit(0);
+    }
+    let mut exit_code: i32 = -1;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    for i in (0..PAGES).step_by(64) {
+        assert_eq!(unsafe { page(base, i).read_volatile() }, pattern(i));
+    }
+
+    // Unmapping releases the slots
+    assert_eq!(sys_munmap(base, LEN), 0);
+    assert!(swap_stats().used_slots < peak.used_slots);
+
+    println!("swaptest OK!");
+}
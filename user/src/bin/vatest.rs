@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sys_mmap, sys_munmap, MAP_ANONYMOUS, MAP_FAILED, MAP_FIXED, MAP_PRIVATE, PAGE_SIZE, PROT_READ,
+    PROT_WRITE,
+};
+
+/// End of the user half with Sv39 (256GB)
+const SV39_USER_END: usize = 1 << 38;
+
+/// End of the user half with Sv48 (128TB)
+const SV48_USER_END: usize = 1 << 47;
+
+fn map_fixed(addr: usize) -> bool {
+    let ret = sys_mmap(addr, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED, usize::MAX, 0);
+    ret != MAP_FAILED
+}
+
+/// Store a distinct value at each address, then read them all back
+fn touch(addrs: &[usize]) {
+    for (i, &addr) in addrs.iter().enumerate() {
+        unsafe { (addr as *mut usize).write_volatile(addr ^ i) };
+    }
+    for (i, &addr) in addrs.iter().enumerate() {
+        assert_eq!(unsafe { (addr as *const usize).read_volatile() }, addr ^ i);
+    }
+}
+
+#[no_mangle]
+fn main() {
+    println!("vatest begin");
+
+    // The last user page of Sv39 exists in both modes
+    let low = SV39_USER_END - PAGE_SIZE;
+    assert!(map_fixed(low));
+    touch(&[low]);
+
+    if map_fixed(SV39_USER_END) {
+        // Sv48: pages that differ only in the fourth level index stay apart
+        let addrs = [SV39_USER_END, 1 << 40, SV48_USER_END - PAGE_SIZE];
+        for &addr in &addrs[1..] {
+            assert!(map_fixed(addr));
+        }
+        touch(&addrs);
+        touch(&[low]);
+        assert!(!map_fixed(SV48_USER_END));
+        for &addr in &addrs {
+            assert_eq!(sys_munmap(addr, PAGE_SIZE), 0);
+        }
+        println!("vatest: Sv48, 128TB of user address space");
+    } else {
+        println!("vatest: Sv39, 256GB of user address space");
+    }
+    assert_eq!(sys_munmap(low, PAGE_SIZE), 0);
+
+    println!("vatest OK!");
+}
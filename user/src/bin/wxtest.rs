@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use user_lib::{
+    run_in_child, sys_brk, sys_mmap, sys_mprotect, sys_munmap, MAP_ANONYMOUS, MAP_FAILED, MAP_FIXED,
+    MAP_PRIVATE, PAGE_SIZE, PROT_EXEC, PROT_READ, PROT_WRITE,
+};
+
+/// `li a0, 42; ret`
+const CODE: [u32; 2] = [0x02a0_0513, 0x0000_8067];
+
+/// Copy CODE to `dst` and call it
+fn run_code_at(dst: *mut u32) -> usize {
+    unsafe {
+        dst.write_volatile(CODE[0]);
+        dst.add(1).write_volatile(CODE[1]);
+        asm!("fence.i");
+        let f: extern "C" fn() -> usize = core::mem::transmute(dst);
+        f()
+    }
+}
+
+fn run_on_stack() {
+    let mut code = [0u32; 2];
+    run_code_at(code.as_mut_ptr());
+}
+
+fn run_on_heap() {
+    let brk = sys_brk(0) as usize;
+    assert_eq!(sys_brk(brk + PAGE_SIZE) as usize, brk + PAGE_SIZE);
+    run_code_at(brk as *mut u32);
+}
+
+fn mmap(prot: usize) -> isize {
+    sys_mmap(0, PAGE_SIZE, prot, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0)
+}
+
+#[no_mangle]
+fn main() {
+    println!("wxtest begin");
+
+    // Stacks and heaps are never executable: the child is killed
+    assert_eq!(run_in_child(run_on_stack), -1);
+    assert_eq!(run_in_child(run_on_heap), -1);
+
+    // A writable and executable mapping is only refused while W^X is enforced
+    let rwx = mmap(PROT_READ | PROT_WRITE | PROT_EXEC);
+    let enforced = rwx == MAP_FAILED;
+    if !enforced {
+        assert_eq!(sys_munmap(rwx as usize, PAGE_SIZE), 0);
+    }
+
+    let base = mmap(PROT_READ | PROT_WRITE);
+    assert!(base > 0);
+    let base = base as usize;
+    let rwx = sys_mprotect(base, PAGE_SIZE, PROT_READ | PROT_WRITE | PROT_EXEC);
+    if enforced {
+        assert_eq!(rwx, -1);
+
+        // A refused MAP_FIXED leaves the mapping it would have replaced readable
+        unsafe { (base as *mut usize).write_volatile(7) };
+        let fixed = sys_mmap(
+            base,
+            PAGE_SIZE,
+            PROT_READ | PROT_WRITE | PROT_EXEC,
+            MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED,
+            usize::MAX,
+            0,
+        );
+        assert_eq!(fixed, MAP_FAILED);
+        assert_eq!(unsafe { (base as *const usize).read_volatile() }, 7);
+
+        // Neither may the stack or heap be flipped to executable
+        let local = 0usize;
+        let stack_page = &local as *const usize as usize & !(PAGE_SIZE - 1);
+        assert_eq!(sys_mprotect(stack_page, PAGE_SIZE, PROT_READ | PROT_EXEC), -1);
+        let brk = sys_brk(0) as usize;
+        assert_eq!(sys_brk(brk + PAGE_SIZE) as usize, brk + PAGE_SIZE);
+        assert_eq!(sys_mprotect(brk, PAGE_SIZE, PROT_READ | PROT_EXEC), -1);
+        assert_eq!(sys_brk(brk) as usize, brk);
+    } else {
+        assert_eq!(rwx, 0);
+    }
+
+    // Write, then execute (JIT style) is allowed either way
+    unsafe {
+        (base as *mut u32).write_volatile(CODE[0]);
+        (base as *mut u32).add(1).write_volatile(CODE[1]);
+    }
+    assert_eq!(sys_mprotect(base, PAGE_SIZE, PROT_READ | PROT_EXEC), 0);
+    unsafe { asm!("fence.i") };
+    let f: extern "C" fn() -> usize = unsafe { core::mem::transmute(base) };
+    assert_eq!(f(), 42);
+    assert_eq!(sys_munmap(base, PAGE_SIZE), 0);
+
+    println!("wxtest OK! (W^X {})", if enforced { "enforced" } else { "not enforced" });
+}
@@ -0,0 +1,116 @@
+//! User heap allocator
+//!
+//! A small segregated free-list allocator on top of `sys_brk`. Every block is
+//! rounded up to a power of two; freed blocks go to the free list of their size
+//! class, and the program break is moved up when no free block fits.
+//!
+//! User programs are single-threaded, so no locking is done.
+
+use crate::sys_brk;
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr::null_mut;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Smallest block handed out (must hold a free list link)
+const MIN_BLOCK_SIZE: usize = 16;
+
+/// One size class per power of two
+const NUM_CLASSES: usize = usize::BITS as usize;
+
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+struct HeapInner {
+    free_lists: [*mut FreeBlock; NUM_CLASSES],
+    /// First byte not yet handed out
+    top: usize,
+    /// Current program break (0 until the first allocation)
+    brk: usize,
+}
+
+pub struct BrkAllocator {
+    inner: UnsafeCell<HeapInner>,
+}
+
+unsafe impl Sync for BrkAllocator {}
+
+impl BrkAllocator {
+    pub const fn empty() -> Self {
+        Self {
+            inner: UnsafeCell::new(HeapInner {
+                free_lists: [null_mut(); NUM_CLASSES],
+                top: 0,
+                brk: 0,
+            }),
+        }
+    }
+}
+
+impl HeapInner {
+    /// Take a fresh block from the top of the heap, moving the break if needed
+    fn carve(&mut self, size: usize, align: usize) -> *mut u8 {
+        if self.brk == 0 {
+            let brk = sys_brk(0);
+            if brk <= 0 {
+                return null_mut();
+            }
+            self.top = brk as usize;
+            self.brk = brk as usize;
+        }
+
+        let start = (self.top + align - 1) & !(align - 1);
+        let end = match start.checked_add(size) {
+            Some(end) => end,
+            None => return null_mut(),
+        };
+        if end > self.brk {
+            let new_brk = (end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+            if sys_brk(new_brk) != new_brk as isize {
+                return null_mut();
+            }
+            self.brk = new_brk;
+        }
+        self.top = end;
+        start as *mut u8
+    }
+}
+
+unsafe impl GlobalAlloc for BrkAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(MIN_BLOCK_SIZE)
+            .next_power_of_two();
+        let class = size.trailing_zeros() as usize;
+        let inner = &mut *self.inner.get();
+
+        // Reuse a freed block of the same class if it is aligned well enough
+        let head = inner.free_lists[class];
+        if !head.is_null() && (head as usize) % layout.align() == 0 {
+            inner.free_lists[class] = (*head).next;
+            return head as *mut u8;
+        }
+
+        // Fresh blocks are aligned to their size (up to a page), so they can
+        // be reused for any layout of the same class
+        inner.carve(size, layout.align().max(size.min(PAGE_SIZE)))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(MIN_BLOCK_SIZE)
+            .next_power_of_two();
+        let class = size.trailing_zeros() as usize;
+        let inner = &mut *self.inner.get();
+
+        let block = ptr as *mut FreeBlock;
+        (*block).next = inner.free_lists[class];
+        inner.free_lists[class] = block;
+    }
+}
@@ -2,17 +2,47 @@
 #![feature(linkage)]
 #![no_main]
 
+extern crate alloc;
+
+mod heap;
 mod lang_items;
 
 use core::arch::asm;
 
+/// Heap for `alloc` types (Vec, String, ...), grown with sys_brk
+#[global_allocator]
+static HEAP: heap::BrkAllocator = heap::BrkAllocator::empty();
+
 /// System call numbers
 pub const SYS_WRITE: usize = 64;
 pub const SYS_EXIT: usize = 93;
 pub const SYS_YIELD: usize = 124;
+pub const SYS_SET_PRIORITY: usize = 140;
+pub const SYS_GETRLIMIT: usize = 163;
+pub const SYS_SETRLIMIT: usize = 164;
 pub const SYS_GET_TIME: usize = 169;
+pub const SYS_GETPID: usize = 172;
+pub const SYS_GETPPID: usize = 173;
+pub const SYS_FORK: usize = 220;
+pub const SYS_EXEC: usize = 221;
+pub const SYS_WAITPID: usize = 260;
 pub const SYS_MMAP: usize = 222;
 pub const SYS_MUNMAP: usize = 215;
+pub const SYS_BRK: usize = 214;
+pub const SYS_MPROTECT: usize = 226;
+pub const SYS_SHM_OPEN: usize = 490;
+pub const SYS_SHM_UNLINK: usize = 491;
+pub const SYS_HEAP_STATS: usize = 492;
+pub const SYS_SLAB_STATS: usize = 493;
+pub const SYS_SWAP_STATS: usize = 494;
+pub const SYS_MEM_STATS: usize = 495;
+pub const SYS_SCHED_POLICY: usize = 496;
+
+/// Error numbers (from Linux), returned negated by system calls
+pub const ENOMEM: isize = 12;
+
+/// Size of a (base) page
+pub const PAGE_SIZE: usize = 4096;
 
 /// System call wrapper functions
 
@@ -65,11 +95,117 @@ pub fn sys_yield() -> isize {
     syscall_3(SYS_YIELD, [0, 0, 0])
 }
 
+/// Set the scheduling priority of the current process (from 2 to 0x10_0000)
+///
+/// Under the stride and cfs scheduling policies the process gets a share of
+/// the CPU proportional to its priority; the default priority is 16.
+///
+/// # Returns
+/// * Success: the new priority
+/// * Failure: -1
+pub fn sys_set_priority(prio: isize) -> isize {
+    syscall_3(SYS_SET_PRIORITY, [prio as usize, 0, 0])
+}
+
 /// Get time in microseconds
 pub fn sys_get_time() -> isize {
     syscall_3(SYS_GET_TIME, [0, 0, 0])
 }
 
+/// Get PID of the current process
+pub fn sys_getpid() -> isize {
+    syscall_3(SYS_GETPID, [0, 0, 0])
+}
+
+/// Get PID of the parent process (0 if there is none)
+pub fn sys_getppid() -> isize {
+    syscall_3(SYS_GETPPID, [0, 0, 0])
+}
+
+/// Create a child process
+///
+/// # Returns
+/// * Parent: PID of the child
+/// * Child: 0
+/// * Failure: -ENOMEM if memory runs out, -1 otherwise
+pub fn sys_fork() -> isize {
+    syscall_3(SYS_FORK, [0, 0, 0])
+}
+
+/// Replace the current program with an embedded app
+///
+/// `path` must be NUL-terminated, e.g. `"power_3\0"`
+///
+/// # Returns
+/// * Does not return on success
+/// * Failure: -ENOMEM if the user stack exceeds RLIMIT_STACK, -1 otherwise
+pub fn sys_exec(path: &str) -> isize {
+    syscall_3(SYS_EXEC, [path.as_ptr() as usize, 0, 0])
+}
+
+/// Reap an exited child process (non-blocking)
+///
+/// # Arguments
+/// * `pid` - PID of the child, or -1 for any child
+/// * `exit_code` - Receives the child's exit code
+///
+/// # Returns
+/// * PID of the reaped child
+/// * -1 if there is no such child
+/// * -2 if the child has not exited yet
+pub fn sys_waitpid(pid: isize, exit_code: &mut i32) -> isize {
+    syscall_3(SYS_WAITPID, [pid as usize, exit_code as *mut i32 as usize, 0])
+}
+
+/// Wait for any child process to exit
+///
+/// # Returns
+/// * PID of the reaped child, or -1 if there are no children
+pub fn wait(exit_code: &mut i32) -> isize {
+    waitpid(-1, exit_code)
+}
+
+/// Wait for a specific child process to exit
+///
+/// # Returns
+/// * PID of the reaped child, or -1 if there is no such child
+pub fn waitpid(pid: isize, exit_code: &mut i32) -> isize {
+    loop {
+        match sys_waitpid(pid, exit_code) {
+            -2 => {
+                sys_yield();
+            }
+            pid => return pid,
+        }
+    }
+}
+
+/// Run `f` in a child process and wait for it
+///
+/// # Returns
+/// * Exit code of the child: 0 if `f` returns, otherwise whatever ended it
+pub fn run_in_child(f: fn()) -> i32 {
+    let pid = sys_fork();
+    if pid == 0 {
+        f();
+        sys_exit(0);
+    }
+    assert!(pid > 0);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid);
+    exit_code
+}
+
+/// Keep the CPU busy for `units` times 1024 steps of a linear congruential
+/// generator
+pub fn burn(units: usize) {
+    let mut x: usize = 0;
+    for _ in 0..units * 1024 {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1);
+        unsafe { core::ptr::write_volatile(&mut x, x) };
+    }
+}
+
 /// Protection flags for mmap
 pub const PROT_READ: usize = 0x1;
 pub const PROT_WRITE: usize = 0x2;
@@ -80,23 +216,28 @@ pub const MAP_PRIVATE: usize = 0x02;
 pub const MAP_SHARED: usize = 0x01;
 pub const MAP_ANONYMOUS: usize = 0x20;
 pub const MAP_FIXED: usize = 0x10;
+/// Back the mapping with 2MB pages (private anonymous mappings only)
+pub const MAP_HUGETLB: usize = 0x40000;
 
 /// Error return value for mmap
 pub const MAP_FAILED: isize = -1;
 
+/// shm_open flag: create the object if it does not exist
+pub const SHM_CREATE: usize = 0o100;
+
 /// Map memory region
 ///
 /// # Arguments
 /// * `addr` - Suggested virtual address (0 means let kernel choose)
 /// * `length` - Size of mapping in bytes
 /// * `prot` - Protection flags (PROT_READ, PROT_WRITE, PROT_EXEC)
-/// * `flags` - Mapping flags (MAP_PRIVATE, MAP_SHARED, MAP_ANONYMOUS, MAP_FIXED)
+/// * `flags` - Mapping flags (MAP_PRIVATE, MAP_SHARED, MAP_ANONYMOUS, MAP_FIXED, MAP_HUGETLB)
 /// * `fd` - File descriptor (ignored for anonymous mappings, use -1)
 /// * `offset` - File offset (ignored for anonymous mappings, use 0)
 ///
 /// # Returns
 /// * Success: Virtual address of mapped region
-/// * Failure: MAP_FAILED (-1)
+/// * Failure: -ENOMEM if memory runs out, MAP_FAILED (-1) otherwise
 pub fn sys_mmap(
     addr: usize,
     length: usize,
@@ -121,6 +262,209 @@ pub fn sys_munmap(addr: usize, length: usize) -> isize {
     syscall_6(SYS_MUNMAP, addr, length, 0, 0, 0, 0)
 }
 
+/// Change the access protection of a memory region
+///
+/// # Arguments
+/// * `addr` - Start of the range (must be page-aligned)
+/// * `length` - Size of the range in bytes
+/// * `prot` - New protection flags (PROT_READ, PROT_WRITE, PROT_EXEC)
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_mprotect(addr: usize, length: usize, prot: usize) -> isize {
+    syscall_3(SYS_MPROTECT, [addr, length, prot])
+}
+
+/// Open a named shared memory object
+///
+/// Map it with `sys_mmap(addr, len, prot, MAP_SHARED, id, offset)`.
+/// `name` must be NUL-terminated, e.g. `"buffer\0"`
+///
+/// # Arguments
+/// * `size` - Object size in bytes, used only when the object is created
+/// * `flags` - SHM_CREATE to create the object if it does not exist
+///
+/// # Returns
+/// * Success: object id
+/// * Failure: -1
+pub fn sys_shm_open(name: &str, size: usize, flags: usize) -> isize {
+    syscall_3(SYS_SHM_OPEN, [name.as_ptr() as usize, size, flags])
+}
+
+/// Remove the name of a shared memory object (existing mappings stay valid)
+///
+/// `name` must be NUL-terminated
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_shm_unlink(name: &str) -> isize {
+    syscall_3(SYS_SHM_UNLINK, [name.as_ptr() as usize, 0, 0])
+}
+
+/// Kernel heap usage, filled in by sys_heap_stats
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct HeapStats {
+    /// Bytes handed to the kernel heap so far
+    pub total_bytes: usize,
+    /// Bytes requested by live kernel allocations
+    pub user_bytes: usize,
+    /// Bytes taken by live kernel allocations, including rounding
+    pub actual_bytes: usize,
+    /// Number of times the kernel heap has grown
+    pub grow_count: usize,
+}
+
+/// Query kernel heap usage
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_heap_stats(stats: &mut HeapStats) -> isize {
+    syscall_3(SYS_HEAP_STATS, [stats as *mut HeapStats as usize, 0, 0])
+}
+
+/// Statistics of one kernel slab cache, filled in by sys_slab_stats
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct SlabStats {
+    /// Cache name, NUL-padded
+    pub name: [u8; 16],
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    /// Slabs currently held by the cache
+    pub slabs: usize,
+    /// Objects currently allocated
+    pub active_objects: usize,
+    /// Allocations since boot
+    pub allocs: usize,
+    /// Frees since boot
+    pub frees: usize,
+}
+
+impl SlabStats {
+    /// Cache name without the NUL padding
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+/// Query the statistics of the `id`-th kernel slab cache
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1 (also when `id` is past the last cache)
+pub fn sys_slab_stats(id: usize, stats: &mut SlabStats) -> isize {
+    syscall_3(SYS_SLAB_STATS, [id, stats as *mut SlabStats as usize, 0])
+}
+
+/// Swap area usage, filled in by sys_swap_stats
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct SwapStats {
+    /// Slots in the swap area (0 if swapping is disabled)
+    pub total_slots: usize,
+    /// Slots holding a page
+    pub used_slots: usize,
+    /// Pages written to swap since boot
+    pub swapped_out: usize,
+    /// Pages read back from swap since boot
+    pub swapped_in: usize,
+}
+
+/// Query swap area usage
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_swap_stats(stats: &mut SwapStats) -> isize {
+    syscall_3(SYS_SWAP_STATS, [stats as *mut SwapStats as usize, 0, 0])
+}
+
+/// Memory usage of the calling task, filled in by sys_mem_stats
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct MemStats {
+    /// Resident set size (RSS) in 4KB pages
+    pub resident_pages: usize,
+    /// Virtual size (VSZ) in 4KB pages
+    pub virtual_pages: usize,
+}
+
+/// Query the memory usage of the calling task
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_mem_stats(stats: &mut MemStats) -> isize {
+    syscall_3(SYS_MEM_STATS, [stats as *mut MemStats as usize, 0, 0])
+}
+
+/// Get the name of the scheduling policy selected at boot ("rr", "stride",
+/// "mlfq" or "cfs"), padded with NUL bytes
+///
+/// # Returns
+/// * Success: length of the name
+/// * Failure: -1
+pub fn sys_sched_policy(name: &mut [u8; 16]) -> isize {
+    syscall_3(SYS_SCHED_POLICY, [name as *mut [u8; 16] as usize, 0, 0])
+}
+
+/// Resources for getrlimit/setrlimit
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_AS: usize = 9;
+
+/// No limit
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Soft and hard limit of a resource
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RLimit {
+    /// Soft limit: the one enforced
+    pub cur: usize,
+    /// Hard limit: ceiling for the soft limit
+    pub max: usize,
+}
+
+/// Get the limits of a resource
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_getrlimit(resource: usize, rlim: &mut RLimit) -> isize {
+    syscall_3(SYS_GETRLIMIT, [resource, rlim as *mut RLimit as usize, 0])
+}
+
+/// Set the limits of a resource
+///
+/// The soft limit may be raised up to the hard limit; the hard limit can only
+/// be lowered. Children inherit the limits.
+///
+/// # Returns
+/// * Success: 0
+/// * Failure: -1
+pub fn sys_setrlimit(resource: usize, rlim: &RLimit) -> isize {
+    syscall_3(SYS_SETRLIMIT, [resource, rlim as *const RLimit as usize, 0])
+}
+
+/// Set the program break (end of the heap)
+///
+/// # Arguments
+/// * `addr` - New program break, or 0 to query the current one
+///
+/// # Returns
+/// * The program break after the call (unchanged if it could not be lowered)
+/// * -ENOMEM if the heap cannot grow to `addr`
+pub fn sys_brk(addr: usize) -> isize {
+    syscall_3(SYS_BRK, [addr, 0, 0])
+}
+
 /// Console writer for implementing core::fmt::Write
 struct Stdout;
 